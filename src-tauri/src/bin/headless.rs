@@ -73,27 +73,21 @@ async fn main() {
 
     if active_accounts == 0 {
         tracing::warn!("⚠️  No active accounts found!");
-        tracing::warn!("📝 Please add accounts via web interface: http://{}:{}/admin",
-            config.proxy.get_bind_address(), config.proxy.port);
+        tracing::warn!("📝 Please add accounts via web interface: {}://{}:{}/admin",
+            config.proxy.url_scheme(), config.proxy.get_bind_address(), config.proxy.port);
         tracing::warn!("🔑 API Key: {}", config.proxy.api_key);
     } else {
         info!("✅ Loaded {} active account(s)", active_accounts);
     }
 
+    token_manager.update_scheduling(config.proxy.scheduling.clone()).await;
+    token_manager.update_routing_rules(config.proxy.routing_rules.clone()).await;
+
     // 启动 Axum 服务器
     let bind_address = config.proxy.get_bind_address().to_string();
     let port = config.proxy.port;
 
-    let (axum_server, server_handle) = match AxumServer::start(
-        bind_address.clone(),
-        port,
-        token_manager.clone(),
-        config.proxy.anthropic_mapping.clone(),
-        config.proxy.openai_mapping.clone(),
-        config.proxy.custom_mapping.clone(),
-        config.proxy.request_timeout,
-        config.proxy.upstream_proxy.clone(),
-    ).await {
+    let (axum_server, server_handle) = match AxumServer::start(&config.proxy, token_manager.clone()).await {
         Ok((server, handle)) => (server, handle),
         Err(e) => {
             error!("Failed to start Axum server: {}", e);
@@ -101,16 +95,20 @@ async fn main() {
         }
     };
 
-    info!("🚀 Proxy server started successfully on http://{}:{}", bind_address, port);
+    let scheme = config.proxy.url_scheme();
+    info!("🚀 Proxy server started successfully on {}://{}:{}", scheme, bind_address, port);
+    if let Some(fingerprint) = axum_server.tls_fingerprint() {
+        info!("🔒 TLS certificate SHA-256 fingerprint: {}", fingerprint);
+    }
     info!("");
     info!("📊 Web Management Interface:");
-    info!("   URL: http://{}:{}/admin", bind_address, port);
+    info!("   URL: {}://{}:{}/admin", scheme, bind_address, port);
     info!("   API Key: {}", config.proxy.api_key);
     info!("");
     info!("🔌 API Endpoints:");
-    info!("   OpenAI:  http://{}:{}/v1/chat/completions", bind_address, port);
-    info!("   Claude:  http://{}:{}/v1/messages", bind_address, port);
-    info!("   Gemini:  http://{}:{}/v1beta/models", bind_address, port);
+    info!("   OpenAI:  {}://{}:{}/v1/chat/completions", scheme, bind_address, port);
+    info!("   Claude:  {}://{}:{}/v1/messages", scheme, bind_address, port);
+    info!("   Gemini:  {}://{}:{}/v1beta/models", scheme, bind_address, port);
     info!("");
     if active_accounts == 0 {
         tracing::warn!("⚠️  Add accounts via web interface to start using the proxy");
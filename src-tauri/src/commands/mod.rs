@@ -1,4 +1,4 @@
-use crate::models::{Account, TokenData, QuotaData, AppConfig};
+use crate::models::{Account, AccountStatus, TokenData, QuotaData, AppConfig};
 use crate::modules;
 use tauri::Emitter;
 
@@ -74,6 +74,72 @@ pub async fn delete_accounts(app: tauri::AppHandle, account_ids: Vec<String>) ->
     Ok(())
 }
 
+/// 更新账号分组标签
+#[tauri::command]
+pub async fn update_account_tags(
+    proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
+    account_id: String,
+    tags: Vec<String>,
+) -> Result<Account, String> {
+    let account = modules::account::update_account_tags(&account_id, tags)?;
+
+    // 同步到运行中的反代账号池，使分组路由立即生效
+    let instance_lock = proxy_state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        instance.token_manager.reload_accounts().await?;
+    }
+    Ok(account)
+}
+
+/// 更新账号状态，并同步到运行中的反代账号池
+async fn set_account_status(
+    app: &tauri::AppHandle,
+    proxy_state: &crate::commands::proxy::ProxyServiceState,
+    account_id: &str,
+    status: AccountStatus,
+) -> Result<Account, String> {
+    let account = modules::account::set_account_status(account_id, status)?;
+    modules::logger::log_info(&format!("账号 {} 状态已更新为 {:?}", account.email, status));
+
+    let instance_lock = proxy_state.instance.read().await;
+    if let Some(instance) = instance_lock.as_ref() {
+        instance.token_manager.reload_accounts().await?;
+    }
+
+    crate::modules::tray::update_tray_menus(app);
+    Ok(account)
+}
+
+/// 停用账号 (不删除)
+#[tauri::command]
+pub async fn disable_account(
+    app: tauri::AppHandle,
+    proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
+    account_id: String,
+) -> Result<Account, String> {
+    set_account_status(&app, &proxy_state, &account_id, AccountStatus::Disabled).await
+}
+
+/// 启用账号
+#[tauri::command]
+pub async fn enable_account(
+    app: tauri::AppHandle,
+    proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
+    account_id: String,
+) -> Result<Account, String> {
+    set_account_status(&app, &proxy_state, &account_id, AccountStatus::Active).await
+}
+
+/// 排空账号：已绑定的会话继续使用，不再接收新会话
+#[tauri::command]
+pub async fn drain_account(
+    app: tauri::AppHandle,
+    proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
+    account_id: String,
+) -> Result<Account, String> {
+    set_account_status(&app, &proxy_state, &account_id, AccountStatus::Draining).await
+}
+
 /// 切换账号
 #[tauri::command]
 pub async fn switch_account(app: tauri::AppHandle, account_id: String) -> Result<(), String> {
@@ -210,6 +276,10 @@ pub async fn save_config(
     proxy_state: tauri::State<'_, crate::commands::proxy::ProxyServiceState>,
     config: AppConfig
 ) -> Result<(), String> {
+    // 校验 IP 访问控制与跨域规则，避免保存无法启动的配置
+    crate::proxy::common::ip_filter::IpAccessControl::from_config(&config.proxy.ip_access)?;
+    crate::proxy::middleware::cors_layer(&config.proxy.cors.api)?;
+    crate::proxy::middleware::cors_layer(&config.proxy.cors.admin)?;
     modules::save_app_config(&config)?;
     
     // 通知托盘配置已更新
@@ -222,6 +292,16 @@ pub async fn save_config(
         instance.axum_server.update_mapping(&config.proxy).await;
         // 更新上游代理
         instance.axum_server.update_proxy(config.proxy.upstream_proxy.clone()).await;
+        // 更新调度策略
+        instance.axum_server.update_scheduling(&config.proxy).await;
+        // 更新账号分组路由规则
+        instance.axum_server.update_routing_rules(&config.proxy).await;
+        // 更新客户端认证
+        instance.axum_server.update_auth(&config.proxy).await;
+        // 更新请求限流
+        instance.axum_server.update_rate_limit(&config.proxy).await;
+        // 更新 IP 访问控制
+        instance.axum_server.update_ip_access(&config.proxy).await?;
         tracing::info!("已同步热更新反代服务配置");
     }
     
@@ -11,6 +11,8 @@ pub struct ProxyStatus {
     pub port: u16,
     pub base_url: String,
     pub active_accounts: usize,
+    /// TLS 证书 SHA-256 指纹 (未启用 TLS 时为空)
+    pub tls_fingerprint: Option<String>,
 }
 
 /// 反代服务统计
@@ -69,23 +71,18 @@ pub async fn start_proxy_service(
     if active_accounts == 0 {
         return Err("没有可用账号，请先添加账号".to_string());
     }
+    token_manager.update_scheduling(config.scheduling.clone()).await;
+    token_manager.update_routing_rules(config.routing_rules.clone()).await;
     
     // 启动 Axum 服务器
     let (axum_server, server_handle) =
-        match crate::proxy::AxumServer::start(
-            config.get_bind_address().to_string(),
-            config.port,
-            token_manager.clone(),
-            config.anthropic_mapping.clone(),
-            config.openai_mapping.clone(),
-            config.custom_mapping.clone(),
-            config.request_timeout,
-            config.upstream_proxy.clone(),
-        ).await {
+        match crate::proxy::AxumServer::start(&config, token_manager.clone()).await {
             Ok((server, handle)) => (server, handle),
             Err(e) => return Err(format!("启动 Axum 服务器失败: {}", e)),
         };
     
+    let tls_fingerprint = axum_server.tls_fingerprint();
+
     // 创建服务实例
     let instance = ProxyServiceInstance {
         config: config.clone(),
@@ -105,8 +102,9 @@ pub async fn start_proxy_service(
     Ok(ProxyStatus {
         running: true,
         port: config.port,
-        base_url: format!("http://127.0.0.1:{}", config.port),
+        base_url: format!("{}://127.0.0.1:{}", config.url_scheme(), config.port),
         active_accounts,
+        tls_fingerprint,
     })
 }
 
@@ -142,14 +140,16 @@ pub async fn get_proxy_status(
         Some(instance) => Ok(ProxyStatus {
             running: true,
             port: instance.config.port,
-            base_url: format!("http://127.0.0.1:{}", instance.config.port),
+            base_url: format!("{}://127.0.0.1:{}", instance.config.url_scheme(), instance.config.port),
             active_accounts: instance.token_manager.len(),
+            tls_fingerprint: instance.axum_server.tls_fingerprint(),
         }),
         None => Ok(ProxyStatus {
             running: false,
             port: 0,
             base_url: String::new(),
             active_accounts: 0,
+            tls_fingerprint: None,
         }),
     }
 }
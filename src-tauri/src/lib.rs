@@ -87,6 +87,10 @@ pub fn run() {
             commands::delete_account,
             commands::delete_accounts,
             commands::switch_account,
+            commands::update_account_tags,
+            commands::disable_account,
+            commands::enable_account,
+            commands::drain_account,
             commands::get_current_account,
             // 配额命令
             commands::fetch_account_quota,
@@ -1,6 +1,19 @@
 use serde::{Deserialize, Serialize};
 use super::{token::TokenData, quota::QuotaData};
 
+/// 账号状态
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    /// 正常参与反代轮换
+    #[default]
+    Active,
+    /// 停用：不参与反代
+    Disabled,
+    /// 排空：已绑定的会话可继续使用，不再接收新会话
+    Draining,
+}
+
 /// 账号数据结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
@@ -11,6 +24,11 @@ pub struct Account {
     pub quota: Option<QuotaData>,
     pub created_at: i64,
     pub last_used: i64,
+    /// 账号分组标签 (用于反代路由规则)
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub status: AccountStatus,
 }
 
 impl Account {
@@ -24,6 +42,8 @@ impl Account {
             quota: None,
             created_at: now,
             last_used: now,
+            tags: Vec::new(),
+            status: AccountStatus::Active,
         }
     }
 
@@ -52,6 +72,10 @@ pub struct AccountSummary {
     pub name: Option<String>,
     pub created_at: i64,
     pub last_used: i64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub status: AccountStatus,
 }
 
 impl AccountIndex {
@@ -3,7 +3,7 @@ pub mod token;
 pub mod quota;
 pub mod config;
 
-pub use account::{Account, AccountIndex, AccountSummary};
+pub use account::{Account, AccountIndex, AccountStatus, AccountSummary};
 pub use token::TokenData;
 pub use quota::QuotaData;
 pub use config::AppConfig;
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// 模型配额信息
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ModelQuota {
     pub name: String,
     pub percentage: i32,  // 剩余百分比 0-100
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use serde_json;
 use uuid::Uuid;
 
-use crate::models::{Account, AccountIndex, AccountSummary, TokenData, QuotaData};
+use crate::models::{Account, AccountIndex, AccountStatus, AccountSummary, TokenData, QuotaData};
 use crate::modules;
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
@@ -99,15 +99,19 @@ pub fn load_account(account_id: &str) -> Result<Account, String> {
         .map_err(|e| format!("解析账号数据失败: {}", e))
 }
 
-/// 保存账号数据
+/// 保存账号数据 (原子化写入，避免反代的账号目录监听读到写了一半的文件)
 pub fn save_account(account: &Account) -> Result<(), String> {
     let accounts_dir = get_accounts_dir()?;
     let account_path = accounts_dir.join(format!("{}.json", account.id));
+    let temp_path = accounts_dir.join(format!("{}.json.tmp", account.id));
     
     let content = serde_json::to_string_pretty(account)
         .map_err(|e| format!("序列化账号数据失败: {}", e))?;
     
-    fs::write(&account_path, content)
+    fs::write(&temp_path, content)
+        .map_err(|e| format!("保存账号数据失败: {}", e))?;
+
+    fs::rename(temp_path, account_path)
         .map_err(|e| format!("保存账号数据失败: {}", e))
 }
 
@@ -182,6 +186,8 @@ pub fn add_account(email: String, name: Option<String>, token: TokenData) -> Res
         name: name.clone(),
         created_at: account.created_at,
         last_used: account.last_used,
+        tags: account.tags.clone(),
+        status: account.status,
     });
     
     // 如果是第一个账号，设为当前账号
@@ -410,6 +416,49 @@ pub fn update_account_quota(account_id: &str, quota: QuotaData) -> Result<(), St
     save_account(&account)
 }
 
+/// 更新账号分组标签 (去除空白与重复)
+pub fn update_account_tags(account_id: &str, tags: Vec<String>) -> Result<Account, String> {
+    let _lock = ACCOUNT_INDEX_LOCK.lock().map_err(|e| format!("获取锁失败: {}", e))?;
+
+    let mut tags: Vec<String> = tags.into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut account = load_account(account_id)?;
+    account.tags = tags.clone();
+    save_account(&account)?;
+
+    // 同步更新索引中的 tags
+    let mut index = load_account_index()?;
+    if let Some(summary) = index.accounts.iter_mut().find(|s| s.id == account_id) {
+        summary.tags = tags;
+        save_account_index(&index)?;
+    }
+
+    Ok(account)
+}
+
+/// 更新账号状态 (启用 / 停用 / 排空)
+pub fn set_account_status(account_id: &str, status: AccountStatus) -> Result<Account, String> {
+    let _lock = ACCOUNT_INDEX_LOCK.lock().map_err(|e| format!("获取锁失败: {}", e))?;
+
+    let mut account = load_account(account_id)?;
+    account.status = status;
+    save_account(&account)?;
+
+    // 同步更新索引中的 status
+    let mut index = load_account_index()?;
+    if let Some(summary) = index.accounts.iter_mut().find(|s| s.id == account_id) {
+        summary.status = status;
+        save_account_index(&index)?;
+    }
+
+    Ok(account)
+}
+
 /// 导出所有账号的 refresh_token
 #[allow(dead_code)]
 pub fn export_accounts() -> Result<Vec<(String, String)>, String> {
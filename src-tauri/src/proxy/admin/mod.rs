@@ -1,4 +1,6 @@
 pub mod models;
 pub mod stats;
+pub mod usage;
 
 pub use stats::{global_stats, StatsTracker, StatsSnapshot};
+pub use usage::UsageStore;
@@ -98,6 +98,33 @@ pub struct UpstreamProxyDto {
     pub url: String,
 }
 
+/// 账号健康状态 DTO (冷却 / 熔断)
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountHealthDto {
+    pub account_id: String,
+    pub email: String,
+    pub available: bool,
+    pub circuit_open: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_open_until: Option<i64>,
+    pub consecutive_auth_failures: u32,
+    /// 模型 -> 冷却结束时间 (毫秒时间戳)；键为空字符串表示整个账号冷却
+    pub cooldowns: std::collections::HashMap<String, i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<i64>,
+    /// 上游响应延迟 EWMA (毫秒)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ewma_ms: Option<f64>,
+    /// refresh_token 已失效 (invalid_grant)，需要重新登录
+    pub invalid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh_error: Option<String>,
+}
+
 /// 服务状态 DTO
 #[derive(Debug, Clone, Serialize)]
 pub struct StatusDto {
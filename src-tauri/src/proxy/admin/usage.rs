@@ -0,0 +1,400 @@
+// Token 用量记录 (SQLite 持久化)
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// 单次请求的 Token 用量 (来自上游 usageMetadata)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    /// 输入 Token (promptTokenCount，包含命中缓存的部分)
+    pub input_tokens: u64,
+    /// 输出 Token (candidatesTokenCount)
+    pub output_tokens: u64,
+    /// 思考 Token (thoughtsTokenCount)
+    pub thinking_tokens: u64,
+    /// 输入中命中缓存的 Token (cachedContentTokenCount)
+    pub cached_tokens: u64,
+}
+
+impl TokenUsage {
+    /// 从 Gemini 响应中读取 usageMetadata (兼容 v1internal 的 response 包装)
+    pub fn from_gemini(value: &serde_json::Value) -> Option<Self> {
+        let meta = value
+            .get("usageMetadata")
+            .or_else(|| value.get("response").and_then(|r| r.get("usageMetadata")))?;
+        let count = |field: &str| meta.get(field).and_then(|v| v.as_u64()).unwrap_or(0);
+        Some(Self {
+            input_tokens: count("promptTokenCount"),
+            output_tokens: count("candidatesTokenCount"),
+            thinking_tokens: count("thoughtsTokenCount"),
+            cached_tokens: count("cachedContentTokenCount"),
+        })
+    }
+}
+
+/// 一条用量记录
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    /// 记录时间 (Unix 秒)
+    pub ts: i64,
+    /// 客户端 Key ID；None 表示主 API Key
+    pub client_key_id: Option<String>,
+    pub client_name: String,
+    pub account_id: String,
+    pub account_email: String,
+    pub protocol: String,
+    /// 客户端请求的原始模型名
+    pub model: String,
+    /// 映射后的上游模型名
+    pub mapped_model: String,
+    pub usage: TokenUsage,
+}
+
+/// 聚合粒度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGranularity {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl UsageGranularity {
+    fn bucket_secs(self) -> i64 {
+        match self {
+            UsageGranularity::Hour => 3600,
+            UsageGranularity::Day => 86400,
+        }
+    }
+}
+
+/// 聚合维度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageDimension {
+    Key,
+    Account,
+    Model,
+    MappedModel,
+    Protocol,
+}
+
+impl UsageDimension {
+    /// (分组列, 展示名列)
+    fn columns(self) -> (&'static str, &'static str) {
+        match self {
+            UsageDimension::Key => ("COALESCE(client_key_id, 'default')", "MAX(client_name)"),
+            UsageDimension::Account => ("account_id", "MAX(account_email)"),
+            UsageDimension::Model => ("model", "model"),
+            UsageDimension::MappedModel => ("mapped_model", "mapped_model"),
+            UsageDimension::Protocol => ("protocol", "protocol"),
+        }
+    }
+}
+
+/// 用量查询条件 (时间范围为 Unix 秒，左闭右开)
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub granularity: UsageGranularity,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub key_id: Option<String>,
+    pub account_id: Option<String>,
+    pub model: Option<String>,
+    pub group_by: Option<UsageDimension>,
+}
+
+/// 聚合结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageBucket {
+    /// 时间桶起点 (Unix 秒，UTC 对齐)
+    pub bucket_start: i64,
+    /// 分组值 (未指定 group_by 时为空)
+    pub group: Option<String>,
+    pub label: Option<String>,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub thinking_tokens: u64,
+    pub cached_tokens: u64,
+}
+
+/// 某个 Key 在一段时间内的累计用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UsageTotals {
+    /// 放行的请求数 (含失败 / 中断的请求)
+    pub requests: u64,
+    pub input_tokens: u64,
+    /// 输出 Token (含思考 Token)
+    pub output_tokens: u64,
+}
+
+/// 用量存储 (同步接口，调用方应在 spawn_blocking 中使用)
+#[derive(Clone)]
+pub struct UsageStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl UsageStore {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("打开用量数据库失败: {}", e))?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| format!("打开用量数据库失败: {}", e))?;
+        Self::init(conn)
+    }
+
+    fn init(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS usage_records (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 ts INTEGER NOT NULL,
+                 client_key_id TEXT,
+                 client_name TEXT NOT NULL,
+                 account_id TEXT NOT NULL,
+                 account_email TEXT NOT NULL,
+                 protocol TEXT NOT NULL,
+                 model TEXT NOT NULL,
+                 mapped_model TEXT NOT NULL,
+                 input_tokens INTEGER NOT NULL,
+                 output_tokens INTEGER NOT NULL,
+                 thinking_tokens INTEGER NOT NULL,
+                 cached_tokens INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_usage_ts ON usage_records(ts);
+             CREATE INDEX IF NOT EXISTS idx_usage_key_ts ON usage_records(client_key_id, ts);
+             CREATE TABLE IF NOT EXISTS key_requests (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 ts INTEGER NOT NULL,
+                 client_key_id TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_key_requests_key_ts ON key_requests(client_key_id, ts);",
+        )
+        .map_err(|e| format!("初始化用量数据库失败: {}", e))?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    pub fn insert(&self, record: &UsageRecord) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|_| "用量数据库锁已损坏".to_string())?;
+        conn.execute(
+            "INSERT INTO usage_records (ts, client_key_id, client_name, account_id, account_email, protocol,
+                 model, mapped_model, input_tokens, output_tokens, thinking_tokens, cached_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                record.ts,
+                record.client_key_id,
+                record.client_name,
+                record.account_id,
+                record.account_email,
+                record.protocol,
+                record.model,
+                record.mapped_model,
+                record.usage.input_tokens as i64,
+                record.usage.output_tokens as i64,
+                record.usage.thinking_tokens as i64,
+                record.usage.cached_tokens as i64,
+            ],
+        )
+        .map_err(|e| format!("写入用量记录失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 后台写入一条记录，失败只记录日志
+    pub fn record(&self, record: UsageRecord) {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.insert(&record) {
+                tracing::warn!("{}", e);
+            }
+        });
+    }
+
+    /// 记录一次放行的客户端 Key 请求 (不论上游是否成功、是否返回用量)
+    pub fn insert_request(&self, key_id: &str, ts: i64) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|_| "用量数据库锁已损坏".to_string())?;
+        conn.execute(
+            "INSERT INTO key_requests (ts, client_key_id) VALUES (?1, ?2)",
+            params![ts, key_id],
+        )
+        .map_err(|e| format!("写入请求记录失败: {}", e))?;
+        Ok(())
+    }
+
+    /// 后台记录一次放行的请求，失败只记录日志
+    pub fn record_request(&self, key_id: String, ts: i64) {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.insert_request(&key_id, ts) {
+                tracing::warn!("{}", e);
+            }
+        });
+    }
+
+    /// 统计某个客户端 Key 自 `since` (Unix 秒) 起的累计用量
+    /// (请求数按放行次数统计，输出 Token 包含思考 Token)
+    pub fn key_totals(&self, key_id: &str, since: i64) -> Result<UsageTotals, String> {
+        let conn = self.conn.lock().map_err(|_| "用量数据库锁已损坏".to_string())?;
+        let requests: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM key_requests WHERE client_key_id = ?1 AND ts >= ?2",
+                params![key_id, since],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("查询用量失败: {}", e))?;
+        conn.query_row(
+            "SELECT COALESCE(SUM(input_tokens), 0), COALESCE(SUM(output_tokens + thinking_tokens), 0)
+             FROM usage_records WHERE client_key_id = ?1 AND ts >= ?2",
+            params![key_id, since],
+            |row| {
+                Ok(UsageTotals {
+                    requests: requests as u64,
+                    input_tokens: row.get::<_, i64>(0)? as u64,
+                    output_tokens: row.get::<_, i64>(1)? as u64,
+                })
+            },
+        )
+        .map_err(|e| format!("查询用量失败: {}", e))
+    }
+
+    /// 按时间桶 (及可选维度) 聚合用量
+    pub fn query(&self, query: &UsageQuery) -> Result<Vec<UsageBucket>, String> {
+        let bucket_secs = query.granularity.bucket_secs();
+        let (group_col, label_col) = match query.group_by {
+            Some(dim) => dim.columns(),
+            None => ("NULL", "NULL"),
+        };
+
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(from) = query.from {
+            conditions.push("ts >= ?");
+            values.push(from.into());
+        }
+        if let Some(to) = query.to {
+            conditions.push("ts < ?");
+            values.push(to.into());
+        }
+        if let Some(key_id) = &query.key_id {
+            conditions.push("COALESCE(client_key_id, 'default') = ?");
+            values.push(key_id.clone().into());
+        }
+        if let Some(account_id) = &query.account_id {
+            conditions.push("account_id = ?");
+            values.push(account_id.clone().into());
+        }
+        if let Some(model) = &query.model {
+            conditions.push("model = ?");
+            values.push(model.clone().into());
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let sql = format!(
+            "SELECT (ts / {b}) * {b} AS bucket, {g} AS grp, {l} AS label, COUNT(*),
+                    SUM(input_tokens), SUM(output_tokens), SUM(thinking_tokens), SUM(cached_tokens)
+             FROM usage_records {w}
+             GROUP BY bucket, grp
+             ORDER BY bucket, grp",
+            b = bucket_secs,
+            g = group_col,
+            l = label_col,
+            w = where_clause,
+        );
+
+        let conn = self.conn.lock().map_err(|_| "用量数据库锁已损坏".to_string())?;
+        let mut stmt = conn.prepare(&sql).map_err(|e| format!("查询用量失败: {}", e))?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(values), |row| {
+                Ok(UsageBucket {
+                    bucket_start: row.get(0)?,
+                    group: row.get(1)?,
+                    label: row.get(2)?,
+                    requests: row.get::<_, i64>(3)? as u64,
+                    input_tokens: row.get::<_, i64>(4)? as u64,
+                    output_tokens: row.get::<_, i64>(5)? as u64,
+                    thinking_tokens: row.get::<_, i64>(6)? as u64,
+                    cached_tokens: row.get::<_, i64>(7)? as u64,
+                })
+            })
+            .map_err(|e| format!("查询用量失败: {}", e))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("查询用量失败: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(ts: i64, key: Option<&str>, account: &str, model: &str, input: u64) -> UsageRecord {
+        UsageRecord {
+            ts,
+            client_key_id: key.map(String::from),
+            client_name: key.unwrap_or("default").to_string(),
+            account_id: account.to_string(),
+            account_email: format!("{}@example.com", account),
+            protocol: "claude".to_string(),
+            model: model.to_string(),
+            mapped_model: format!("mapped-{}", model),
+            usage: TokenUsage { input_tokens: input, output_tokens: 10, thinking_tokens: 5, cached_tokens: 1 },
+        }
+    }
+
+    #[test]
+    fn test_usage_from_gemini_metadata() {
+        let wrapped = json!({
+            "response": {
+                "usageMetadata": {
+                    "promptTokenCount": 120,
+                    "candidatesTokenCount": 30,
+                    "thoughtsTokenCount": 12,
+                    "cachedContentTokenCount": 100
+                }
+            }
+        });
+        assert_eq!(
+            TokenUsage::from_gemini(&wrapped),
+            Some(TokenUsage { input_tokens: 120, output_tokens: 30, thinking_tokens: 12, cached_tokens: 100 })
+        );
+        assert_eq!(TokenUsage::from_gemini(&json!({"candidates": []})), None);
+    }
+
+    #[test]
+    fn test_query_aggregates_by_bucket_and_dimension() {
+        let store = UsageStore::open_in_memory().unwrap();
+        store.insert(&record(0, Some("k1"), "a", "claude-sonnet", 100)).unwrap();
+        store.insert(&record(1800, None, "a", "claude-sonnet", 50)).unwrap();
+        store.insert(&record(3600, Some("k1"), "b", "gpt-4o", 20)).unwrap();
+        store.insert(&record(90000, Some("k1"), "b", "gpt-4o", 7)).unwrap();
+
+        let hourly = store.query(&UsageQuery { granularity: UsageGranularity::Hour, ..Default::default() }).unwrap();
+        assert_eq!(hourly.iter().map(|b| (b.bucket_start, b.requests, b.input_tokens)).collect::<Vec<_>>(),
+            vec![(0, 2, 150), (3600, 1, 20), (90000, 1, 7)]);
+
+        let by_key = store.query(&UsageQuery { group_by: Some(UsageDimension::Key), ..Default::default() }).unwrap();
+        assert_eq!(by_key.iter().map(|b| (b.bucket_start, b.group.clone().unwrap(), b.input_tokens)).collect::<Vec<_>>(),
+            vec![(0, "default".to_string(), 50), (0, "k1".to_string(), 120), (86400, "k1".to_string(), 7)]);
+
+        let filtered = store.query(&UsageQuery {
+            account_id: Some("b".to_string()),
+            from: Some(0),
+            to: Some(86400),
+            group_by: Some(UsageDimension::Account),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].label.as_deref(), Some("b@example.com"));
+        assert_eq!((filtered[0].output_tokens, filtered[0].thinking_tokens, filtered[0].cached_tokens), (10, 5, 1));
+    }
+}
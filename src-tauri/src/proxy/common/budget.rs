@@ -0,0 +1,199 @@
+// 客户端 Key 用量预算：按 UTC 自然日 / 自然月统计，超出后拒绝请求直到周期重置
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::proxy::admin::usage::{UsageStore, UsageTotals};
+use crate::proxy::config::{BudgetLimits, ClientApiKey};
+
+/// 预算周期
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    Daily,
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Monthly => "monthly",
+        }
+    }
+
+    /// 当前周期的起点
+    pub fn start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let day = Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0).unwrap();
+        match self {
+            BudgetPeriod::Daily => day,
+            BudgetPeriod::Monthly => Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).unwrap(),
+        }
+    }
+
+    /// 下一次重置的时间
+    pub fn reset_at(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let start = self.start(now);
+        match self {
+            BudgetPeriod::Daily => start + Duration::days(1),
+            BudgetPeriod::Monthly => {
+                let (year, month) = if start.month() == 12 {
+                    (start.year() + 1, 1)
+                } else {
+                    (start.year(), start.month() + 1)
+                };
+                Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+            }
+        }
+    }
+}
+
+/// 单个周期的预算状态
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetPeriodStatus {
+    pub period: BudgetPeriod,
+    pub limits: BudgetLimits,
+    pub used: UsageTotals,
+    /// 重置时间 (Unix 秒)
+    pub reset_at: i64,
+}
+
+impl BudgetPeriodStatus {
+    /// 返回第一个已用尽的指标 (指标名, 上限)
+    pub fn exceeded(&self) -> Option<(&'static str, u64)> {
+        [
+            ("requests", self.limits.requests, self.used.requests),
+            ("input_tokens", self.limits.input_tokens, self.used.input_tokens),
+            ("output_tokens", self.limits.output_tokens, self.used.output_tokens),
+        ]
+        .into_iter()
+        .find_map(|(metric, limit, used)| limit.filter(|l| used >= *l).map(|l| (metric, l)))
+    }
+}
+
+/// 客户端 Key 的预算状态
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyBudgetStatus {
+    pub key_id: String,
+    pub name: String,
+    pub periods: Vec<BudgetPeriodStatus>,
+}
+
+impl KeyBudgetStatus {
+    /// 读取 Key 在当前日 / 月周期内的用量 (未配置上限的周期不统计)
+    pub fn load(store: &UsageStore, key: &ClientApiKey, now: DateTime<Utc>) -> Result<Self, String> {
+        let mut periods = Vec::new();
+        for (period, limits) in [
+            (BudgetPeriod::Daily, &key.budget.daily),
+            (BudgetPeriod::Monthly, &key.budget.monthly),
+        ] {
+            if limits.is_unlimited() {
+                continue;
+            }
+            periods.push(BudgetPeriodStatus {
+                period,
+                limits: limits.clone(),
+                used: store.key_totals(&key.id, period.start(now).timestamp())?,
+                reset_at: period.reset_at(now).timestamp(),
+            });
+        }
+        Ok(Self { key_id: key.id.clone(), name: key.name.clone(), periods })
+    }
+
+    /// 已超出的预算中最晚重置的一个 (需等到它重置请求才能恢复)
+    pub fn exceeded(&self) -> Option<(&BudgetPeriodStatus, &'static str, u64)> {
+        self.periods
+            .iter()
+            .filter_map(|p| p.exceeded().map(|(metric, limit)| (p, metric, limit)))
+            .max_by_key(|(p, _, _)| p.reset_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::admin::usage::{TokenUsage, UsageRecord};
+
+    #[test]
+    fn test_period_boundaries() {
+        let now = Utc.with_ymd_and_hms(2025, 12, 31, 18, 30, 0).unwrap();
+        assert_eq!(BudgetPeriod::Daily.start(now), Utc.with_ymd_and_hms(2025, 12, 31, 0, 0, 0).unwrap());
+        assert_eq!(BudgetPeriod::Daily.reset_at(now), Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(BudgetPeriod::Monthly.start(now), Utc.with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(BudgetPeriod::Monthly.reset_at(now), Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_budget_exceeded_uses_current_period_only() {
+        let store = UsageStore::open_in_memory().unwrap();
+        let mut key = ClientApiKey::new("alice".to_string());
+        key.budget.daily.output_tokens = Some(100);
+        key.budget.monthly.requests = Some(3);
+
+        let now = Utc.with_ymd_and_hms(2025, 6, 15, 12, 0, 0).unwrap();
+        let insert = |ts: DateTime<Utc>, output: u64| {
+            store.insert_request(&key.id, ts.timestamp()).unwrap();
+            store.insert(&UsageRecord {
+                ts: ts.timestamp(),
+                client_key_id: Some(key.id.clone()),
+                client_name: key.name.clone(),
+                account_id: "a".to_string(),
+                account_email: "a@example.com".to_string(),
+                protocol: "claude".to_string(),
+                model: "m".to_string(),
+                mapped_model: "m".to_string(),
+                usage: TokenUsage { output_tokens: output, ..Default::default() },
+            }).unwrap();
+        };
+        // 上个月与昨天的用量不计入当日预算
+        insert(Utc.with_ymd_and_hms(2025, 5, 31, 23, 0, 0).unwrap(), 500);
+        insert(Utc.with_ymd_and_hms(2025, 6, 14, 23, 0, 0).unwrap(), 500);
+        insert(now, 60);
+
+        let status = KeyBudgetStatus::load(&store, &key, now).unwrap();
+        assert_eq!(status.periods[0].used.output_tokens, 60);
+        assert_eq!(status.periods[1].used.requests, 2);
+        assert!(status.exceeded().is_none());
+
+        insert(now, 40);
+        let status = KeyBudgetStatus::load(&store, &key, now).unwrap();
+        let (period, metric, limit) = status.exceeded().unwrap();
+        // 日预算与月预算同时用尽时以较晚的月度重置为准
+        assert_eq!((period.period, metric, limit), (BudgetPeriod::Monthly, "requests", 3));
+        assert_eq!(period.reset_at, Utc.with_ymd_and_hms(2025, 7, 1, 0, 0, 0).unwrap().timestamp());
+    }
+
+    #[test]
+    fn test_thinking_tokens_and_failed_requests_count_against_budget() {
+        let store = UsageStore::open_in_memory().unwrap();
+        let mut key = ClientApiKey::new("alice".to_string());
+        key.budget.daily.output_tokens = Some(100);
+        key.budget.daily.requests = Some(3);
+        let now = Utc.with_ymd_and_hms(2025, 6, 15, 12, 0, 0).unwrap();
+
+        store.insert_request(&key.id, now.timestamp()).unwrap();
+        store.insert(&UsageRecord {
+            ts: now.timestamp(),
+            client_key_id: Some(key.id.clone()),
+            client_name: key.name.clone(),
+            account_id: "a".to_string(),
+            account_email: "a@example.com".to_string(),
+            protocol: "claude".to_string(),
+            model: "m".to_string(),
+            mapped_model: "m".to_string(),
+            usage: TokenUsage { output_tokens: 10, thinking_tokens: 95, ..Default::default() },
+        }).unwrap();
+
+        // 可见输出仅 10，思考 Token 使输出预算用尽
+        let status = KeyBudgetStatus::load(&store, &key, now).unwrap();
+        assert_eq!(status.periods[0].used.output_tokens, 105);
+        assert_eq!(status.exceeded().map(|(_, metric, _)| metric), Some("output_tokens"));
+
+        // 没有用量记录的失败请求同样计入请求数
+        key.budget.daily.output_tokens = None;
+        store.insert_request(&key.id, now.timestamp()).unwrap();
+        store.insert_request(&key.id, now.timestamp()).unwrap();
+        let status = KeyBudgetStatus::load(&store, &key, now).unwrap();
+        assert_eq!(status.periods[0].used.requests, 3);
+        assert_eq!(status.exceeded().map(|(_, metric, _)| metric), Some("requests"));
+    }
+}
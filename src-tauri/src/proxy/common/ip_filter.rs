@@ -0,0 +1,225 @@
+// 来源 IP 访问控制：CIDR 允许 / 拒绝列表 + 受信任反向代理的 X-Forwarded-For 解析
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::proxy::config::{IpAccessConfig, IpRules};
+
+/// 规则作用范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpScope {
+    Api,
+    Admin,
+}
+
+impl IpScope {
+    /// 按请求路径区分管理后台与协议路由
+    pub fn from_path(path: &str) -> Self {
+        if path == "/admin" || path.starts_with("/admin/") || path.starts_with("/api/admin") {
+            IpScope::Admin
+        } else {
+            IpScope::Api
+        }
+    }
+}
+
+/// 解析 "10.0.0.0/8" 或单个 IP
+fn parse_net(entry: &str) -> Result<IpNet, String> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map(|net| net.trunc())
+        .map_err(|_| format!("无效的 IP / CIDR: {}", entry))
+}
+
+fn parse_nets(entries: &[String]) -> Result<Vec<IpNet>, String> {
+    entries.iter().map(|e| parse_net(e)).collect()
+}
+
+#[derive(Debug, Clone, Default)]
+struct CompiledRules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl CompiledRules {
+    fn compile(rules: &IpRules) -> Result<Self, String> {
+        Ok(Self { allow: parse_nets(&rules.allow)?, deny: parse_nets(&rules.deny)? })
+    }
+
+    /// allow_loopback: 直连的回环地址默认放行；经代理转发时回环地址只能来自代理自身，需按规则匹配
+    fn allows(&self, ip: IpAddr, allow_loopback: bool) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty()
+            || (allow_loopback && ip.is_loopback())
+            || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// 请求的真实来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr {
+    pub ip: IpAddr,
+    /// 直连方是否为受信任代理 (此时回环地址不再默认放行)
+    pub forwarded: bool,
+}
+
+/// 编译后的访问控制规则
+#[derive(Debug, Clone, Default)]
+pub struct IpAccessControl {
+    api: CompiledRules,
+    admin: CompiledRules,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl IpAccessControl {
+    /// 编译配置；任一条目无效时整体报错 (避免跳过 allow 条目后意外放开访问)
+    pub fn from_config(config: &IpAccessConfig) -> Result<Self, String> {
+        Ok(Self {
+            api: CompiledRules::compile(&config.api)?,
+            admin: CompiledRules::compile(&config.admin)?,
+            trusted_proxies: parse_nets(&config.trusted_proxies)?,
+        })
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// 建立连接时的检查：来源需至少被一组规则放行，或是受信任的反向代理 (真实来源在请求级检查)
+    pub fn accepts_connection(&self, peer: IpAddr) -> bool {
+        let peer = peer.to_canonical();
+        self.is_trusted_proxy(peer) || self.api.allows(peer, true) || self.admin.allows(peer, true)
+    }
+
+    /// 解析真实客户端 IP
+    ///
+    /// 仅当直连方是受信任代理时才读取 X-Forwarded-For，并从右往左跳过受信任代理，
+    /// 取第一个不受信任的地址。遇到无法解析的一跳时拒绝请求，不会退回到受信任的直连地址
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> Result<ClientAddr, String> {
+        let peer = peer.to_canonical();
+        if !self.is_trusted_proxy(peer) {
+            return Ok(ClientAddr { ip: peer, forwarded: false });
+        }
+        let mut hops = Vec::new();
+        for value in headers.get_all("x-forwarded-for") {
+            let value = value.to_str().map_err(|_| "无效的 X-Forwarded-For 头".to_string())?;
+            hops.extend(value.split(',').map(str::trim));
+        }
+
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            client = hop
+                .parse::<IpAddr>()
+                .map(|ip| ip.to_canonical())
+                .map_err(|_| format!("无效的 X-Forwarded-For 地址: {:?}", hop))?;
+            if !self.is_trusted_proxy(client) {
+                break;
+            }
+        }
+        Ok(ClientAddr { ip: client, forwarded: true })
+    }
+
+    pub fn allows(&self, scope: IpScope, client: ClientAddr) -> bool {
+        let ip = client.ip.to_canonical();
+        let allow_loopback = !client.forwarded;
+        match scope {
+            IpScope::Api => self.api.allows(ip, allow_loopback),
+            IpScope::Admin => self.admin.allows(ip, allow_loopback),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn direct(s: &str) -> ClientAddr {
+        ClientAddr { ip: ip(s), forwarded: false }
+    }
+
+    fn control() -> IpAccessControl {
+        IpAccessControl::from_config(&IpAccessConfig {
+            api: IpRules {
+                allow: vec!["192.168.10.0/24".to_string(), "10.8.0.0/16".to_string()],
+                deny: vec!["192.168.10.13".to_string()],
+            },
+            admin: IpRules { allow: vec!["192.168.10.5".to_string()], deny: vec![] },
+            trusted_proxies: vec!["172.16.0.2".to_string()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_allow_deny_per_scope() {
+        let acl = control();
+        assert!(acl.allows(IpScope::Api, direct("192.168.10.42")));
+        assert!(acl.allows(IpScope::Api, direct("10.8.3.4")));
+        assert!(!acl.allows(IpScope::Api, direct("192.168.10.13")));
+        assert!(!acl.allows(IpScope::Api, direct("8.8.8.8")));
+        assert!(!acl.allows(IpScope::Admin, direct("192.168.10.42")));
+        assert!(acl.allows(IpScope::Admin, direct("192.168.10.5")));
+        // 回环地址始终放行；IPv4 映射的 IPv6 地址按 IPv4 匹配
+        assert!(acl.allows(IpScope::Admin, direct("127.0.0.1")));
+        assert!(acl.allows(IpScope::Api, direct("::ffff:192.168.10.42")));
+
+        assert!(acl.accepts_connection(ip("172.16.0.2")));
+        assert!(!acl.accepts_connection(ip("8.8.8.8")));
+        assert!(IpAccessControl::default().allows(IpScope::Api, direct("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_forwarded_for_only_from_trusted_proxy() {
+        let acl = control();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("1.2.3.4, 192.168.10.42, 172.16.0.2"));
+
+        // 伪造的最左侧地址被忽略，取最右侧第一个不受信任的地址
+        let client = acl.client_ip(ip("172.16.0.2"), &headers).unwrap();
+        assert_eq!(client, ClientAddr { ip: ip("192.168.10.42"), forwarded: true });
+        assert!(acl.allows(IpScope::Api, client));
+        // 非受信任来源的 X-Forwarded-For 不采信
+        assert_eq!(acl.client_ip(ip("192.168.10.7"), &headers).unwrap(), direct("192.168.10.7"));
+    }
+
+    #[test]
+    fn test_malformed_forwarded_hop_is_rejected() {
+        let acl = IpAccessControl::from_config(&IpAccessConfig {
+            api: IpRules { allow: vec!["10.8.0.0/16".to_string()], deny: vec![] },
+            trusted_proxies: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("garbage"));
+        assert!(acl.client_ip(ip("127.0.0.1"), &headers).is_err());
+        // 不可解析的一跳之前的地址同样不采信
+        headers.insert("x-forwarded-for", HeaderValue::from_static("10.8.0.1, garbage"));
+        assert!(acl.client_ip(ip("127.0.0.1"), &headers).is_err());
+
+        // 受信任代理未带 X-Forwarded-For 时来源即代理自身，回环地址不再默认放行
+        let client = acl.client_ip(ip("127.0.0.1"), &HeaderMap::new()).unwrap();
+        assert_eq!(client, ClientAddr { ip: ip("127.0.0.1"), forwarded: true });
+        assert!(!acl.allows(IpScope::Api, client));
+        assert!(acl.allows(IpScope::Api, direct("127.0.0.1")));
+    }
+
+    #[test]
+    fn test_invalid_entry_rejected() {
+        let config = IpAccessConfig {
+            api: IpRules { allow: vec!["10.0.0.0/33".to_string()], deny: vec![] },
+            ..Default::default()
+        };
+        assert!(IpAccessControl::from_config(&config).is_err());
+        assert_eq!(parse_net(" 10.1.2.3/8 ").unwrap().to_string(), "10.0.0.0/8");
+    }
+}
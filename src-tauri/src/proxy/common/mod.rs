@@ -1,7 +1,13 @@
 // Common 模块 - 公共工具
 
 // pub mod error;
-// pub mod rate_limiter;
+pub mod rate_limiter;
 pub mod model_mapping;
 pub mod utils;
 pub mod json_schema;
+pub mod session;
+pub mod usage;
+pub mod budget;
+pub mod ip_filter;
+pub mod token_count;
+pub mod response_store;
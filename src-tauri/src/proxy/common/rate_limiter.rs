@@ -1,51 +1,221 @@
 // Rate Limiter
-// 确保 API 调用间隔 ≥ 500ms
+// 按 Key (客户端 Key / 来源 IP) 的令牌桶限流：每分钟请求数 + 并发流数
 
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration, Instant};
+use std::time::{Duration, Instant};
 
+use crate::proxy::config::RateLimit;
+
+/// 超过该数量时清理已回满且无并发流的桶，避免按 IP 计数时无限增长
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// 令牌桶 (容量 = 每分钟请求数，按秒匀速回填)
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, capacity: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.last_refill = now;
+    }
+}
+
+/// 请求配额状态 (用于 x-ratelimit-* 响应头)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// 令牌桶回满所需时间
+    pub reset_after: Duration,
+}
+
+/// 限流拒绝原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitRejection {
+    /// 每分钟请求数已用尽
+    Requests { status: RateLimitStatus, retry_after: Duration },
+    /// 并发流数已满
+    Streams { limit: u32 },
+}
+
+/// 并发流占位，响应体传输结束 (或客户端断开) 后释放
+#[derive(Debug, Default)]
+pub struct StreamPermit {
+    counter: Option<Arc<AtomicU32>>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        if let Some(counter) = self.counter.take() {
+            counter.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// 按 Key 的令牌桶限流器
+#[derive(Debug, Default)]
 pub struct RateLimiter {
-    min_interval: Duration,
-    last_call: Arc<Mutex<Option<Instant>>>,
+    buckets: DashMap<String, Bucket>,
+    streams: DashMap<String, Arc<AtomicU32>>,
 }
 
 impl RateLimiter {
-    pub fn new(min_interval_ms: u64) -> Self {
-        Self {
-            min_interval: Duration::from_millis(min_interval_ms),
-            last_call: Arc::new(Mutex::new(None)),
-        }
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub async fn wait(&self) {
-        let mut last = self.last_call.lock().await;
-        if let Some(last_time) = *last {
-            let elapsed = last_time.elapsed();
-            if elapsed < self.min_interval {
-                sleep(self.min_interval - elapsed).await;
+    /// 检查并占用一次请求配额与一个并发流名额；限额为 0 的维度不限制
+    pub fn check(&self, key: &str, limit: RateLimit) -> Result<(Option<RateLimitStatus>, StreamPermit), RateLimitRejection> {
+        self.check_at(key, limit, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, limit: RateLimit, now: Instant) -> Result<(Option<RateLimitStatus>, StreamPermit), RateLimitRejection> {
+        // 仅限制并发流时不会创建令牌桶，需同时计入并发计数
+        if self.buckets.len() + self.streams.len() > MAX_TRACKED_KEYS {
+            self.prune(now);
+        }
+
+        // 1. 并发流 (先占位，请求配额不足时随 permit 释放)
+        let permit = if limit.max_concurrent_streams > 0 {
+            let counter = self.streams.entry(key.to_string()).or_default().clone();
+            let max = limit.max_concurrent_streams;
+            if counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1)).is_err() {
+                return Err(RateLimitRejection::Streams { limit: max });
             }
+            StreamPermit { counter: Some(counter) }
+        } else {
+            StreamPermit::default()
+        };
+
+        // 2. 每分钟请求数
+        if limit.requests_per_minute == 0 {
+            return Ok((None, permit));
+        }
+        let capacity = limit.requests_per_minute as f64;
+        let per_token = Duration::from_secs_f64(60.0 / capacity);
+        let mut bucket = self.buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket { tokens: capacity, last_refill: now });
+        bucket.refill(capacity, now);
+
+        let taken = bucket.tokens >= 1.0;
+        if taken {
+            bucket.tokens -= 1.0;
+        }
+        let status = RateLimitStatus {
+            limit: limit.requests_per_minute,
+            remaining: bucket.tokens.floor() as u32,
+            reset_after: per_token.mul_f64(capacity - bucket.tokens),
+        };
+        if taken {
+            Ok((Some(status), permit))
+        } else {
+            let retry_after = per_token.mul_f64(1.0 - bucket.tokens);
+            Err(RateLimitRejection::Requests { status, retry_after })
+        }
+    }
+
+    /// 退还一次已占用的请求配额 (同一请求的后续维度被拒绝时调用)
+    pub fn refund(&self, key: &str, limit: RateLimit) {
+        if limit.requests_per_minute == 0 {
+            return;
+        }
+        if let Some(mut bucket) = self.buckets.get_mut(key) {
+            bucket.tokens = (bucket.tokens + 1.0).min(limit.requests_per_minute as f64);
         }
-        *last = Some(Instant::now());
+    }
+
+    /// 清理已回满的桶与空闲的并发计数
+    fn prune(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            // 按最小容量 1 估算：一分钟无请求的桶视为已回满
+            now.saturating_duration_since(bucket.last_refill) < Duration::from_secs(60)
+        });
+        self.streams.retain(|_, counter| counter.load(Ordering::Acquire) > 0);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::Instant;
 
-    #[tokio::test]
-    async fn test_rate_limiter() {
-        let limiter = RateLimiter::new(500);
+    #[test]
+    fn test_token_bucket_refills_over_time() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { requests_per_minute: 2, max_concurrent_streams: 0 };
         let start = Instant::now();
 
-        limiter.wait().await; // 第一次调用，立即返回
-        let elapsed1 = start.elapsed().as_millis();
-        assert!(elapsed1 < 50);
+        let (status, _) = limiter.check_at("k", limit, start).unwrap();
+        assert_eq!(status.unwrap().remaining, 1);
+        limiter.check_at("k", limit, start).unwrap();
+
+        match limiter.check_at("k", limit, start).unwrap_err() {
+            RateLimitRejection::Requests { status, retry_after } => {
+                assert_eq!(status.remaining, 0);
+                assert_eq!(retry_after, Duration::from_secs(30));
+            }
+            other => panic!("unexpected rejection: {:?}", other),
+        }
+
+        // 30 秒回填一个令牌；其他 Key 互不影响
+        assert!(limiter.check_at("k", limit, start + Duration::from_secs(30)).is_ok());
+        assert!(limiter.check_at("other", limit, start).is_ok());
+    }
+
+    #[test]
+    fn test_concurrent_streams_released_on_drop() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { requests_per_minute: 0, max_concurrent_streams: 1 };
+
+        let (_, permit) = limiter.check("k", limit).unwrap();
+        assert_eq!(limiter.check("k", limit).unwrap_err(), RateLimitRejection::Streams { limit: 1 });
+        drop(permit);
+        assert!(limiter.check("k", limit).is_ok());
+    }
+
+    #[test]
+    fn test_streams_only_limit_prunes_idle_counters() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { requests_per_minute: 0, max_concurrent_streams: 2 };
+        for i in 0..=MAX_TRACKED_KEYS {
+            limiter.check(&format!("ip:{}", i), limit).unwrap();
+        }
+        let (_, _held) = limiter.check("ip:active", limit).unwrap();
+        // 已释放的并发计数被清理，仍在进行中的保留
+        assert_eq!(limiter.streams.len(), 1);
+        assert!(limiter.streams.contains_key("ip:active"));
+    }
+
+    #[test]
+    fn test_refund_restores_request_token() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { requests_per_minute: 1, max_concurrent_streams: 0 };
+        let now = Instant::now();
+
+        limiter.check_at("k", limit, now).unwrap();
+        limiter.refund("k", limit);
+        limiter.refund("k", limit);
+        let (status, _) = limiter.check_at("k", limit, now).unwrap();
+        // 退还不超过桶容量
+        assert_eq!(status.unwrap().remaining, 0);
+        assert!(limiter.check_at("k", limit, now).is_err());
+    }
+
+    #[test]
+    fn test_rejected_request_does_not_hold_stream_slot() {
+        let limiter = RateLimiter::new();
+        let limit = RateLimit { requests_per_minute: 1, max_concurrent_streams: 1 };
+        let now = Instant::now();
 
-        limiter.wait().await; // 第二次调用，等待 500ms
-        let elapsed2 = start.elapsed().as_millis();
-        assert!(elapsed2 >= 500 && elapsed2 < 600);
+        let (_, permit) = limiter.check_at("k", limit, now).unwrap();
+        drop(permit);
+        assert!(matches!(limiter.check_at("k", limit, now), Err(RateLimitRejection::Requests { .. })));
+        assert!(limiter.check_at("k", limit, now + Duration::from_secs(60)).is_ok());
     }
 }
@@ -0,0 +1,441 @@
+// Responses API 响应存储：内存 LRU + 可选 SQLite 持久化，供 previous_response_id 续接与 GET / DELETE 查询
+//
+// 每条响应只保存本轮新增的 contents，并通过 previous_response_id 链接上一轮；续接时沿链拼回完整对话
+use lru::LruCache;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::proxy::config::ResponseStoreConfig;
+
+/// 一条已存储的响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredResponse {
+    /// 创建该响应的客户端 Key ID；None 表示主 API Key
+    pub owner: Option<String>,
+    /// 会话亲和标识，续接时沿用以命中同一账号
+    pub session_id: Option<String>,
+    pub created_at: i64,
+    /// 续接的上一轮响应 ID
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+    /// 返回给客户端的 response 对象
+    pub response: Value,
+    /// 本轮新增的 Gemini contents (本轮输入 + 模型输出及 thoughtSignature)
+    pub contents: Vec<Value>,
+}
+
+impl StoredResponse {
+    /// 调用方是否可以访问该响应
+    pub fn visible_to(&self, owner: Option<&str>) -> bool {
+        self.owner.as_deref() == owner
+    }
+
+    /// 内存占用估算 (按序列化后的字节数)
+    fn approx_bytes(&self) -> usize {
+        serde_json::to_vec(self).map(|v| v.len()).unwrap_or_default()
+    }
+}
+
+/// 从完整请求 contents 中取出本轮新增部分 (history 为续接链拼回的历史)
+///
+/// 本轮首条输入与历史末条同角色时会被合并进历史末条，此时只保留新增的 parts
+pub fn turn_contents(history: &[Value], mut contents: Vec<Value>) -> Vec<Value> {
+    let Some(last) = history.last() else { return contents };
+    if contents.len() < history.len() {
+        return contents;
+    }
+    let mut turn = contents.split_off(history.len());
+    let merged = &contents[history.len() - 1];
+    let old_parts = last["parts"].as_array().map_or(0, |p| p.len());
+    if let Some(extra) = merged["parts"].as_array().filter(|p| p.len() > old_parts) {
+        turn.insert(0, serde_json::json!({ "role": merged["role"], "parts": extra[old_parts..] }));
+    }
+    turn
+}
+
+/// 把一轮 contents 接到历史末尾，边界处同角色的 content 合并 (与请求构建时的合并规则一致)
+fn append_turn(history: &mut Vec<Value>, turn: &[Value]) {
+    let mut turn = turn.iter().cloned();
+    if let (Some(last), Some(first)) = (history.last_mut(), turn.clone().next()) {
+        if last["role"] == first["role"] {
+            if let (Some(parts), Some(extra)) = (last["parts"].as_array_mut(), first["parts"].as_array()) {
+                parts.extend(extra.iter().cloned());
+                turn.next();
+            }
+        }
+    }
+    history.extend(turn);
+}
+
+/// 内存 LRU：同时受条数与字节数上限约束
+struct MemoryCache {
+    entries: LruCache<String, (Arc<StoredResponse>, usize)>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl MemoryCache {
+    fn get(&mut self, id: &str) -> Option<Arc<StoredResponse>> {
+        self.entries.get(id).map(|(stored, _)| stored.clone())
+    }
+
+    /// 放入后按 LRU 顺序淘汰直到字节数不超过上限 (单条超过上限的响应不驻留内存)
+    fn put(&mut self, id: String, stored: Arc<StoredResponse>) {
+        let size = stored.approx_bytes();
+        self.bytes += size;
+        if let Some((_, (_, old))) = self.entries.push(id, (stored, size)) {
+            self.bytes -= old;
+        }
+        while self.bytes > self.max_bytes {
+            let Some((_, (_, size))) = self.entries.pop_lru() else { break };
+            self.bytes -= size;
+        }
+    }
+
+    fn pop(&mut self, id: &str) -> Option<Arc<StoredResponse>> {
+        let (stored, size) = self.entries.pop(id)?;
+        self.bytes -= size;
+        Some(stored)
+    }
+}
+
+/// 响应存储 (内存操作同步完成，SQLite 读写在 spawn_blocking 中执行)
+#[derive(Clone)]
+pub struct ResponseStore {
+    cache: Arc<Mutex<MemoryCache>>,
+    db: Option<Arc<Mutex<Connection>>>,
+    /// 已 put 但尚未落库的响应 ID；delete 时移除，后台写入在数据库锁内检查，避免已删除的响应被写回
+    pending: Arc<Mutex<HashSet<String>>>,
+    retention_secs: i64,
+}
+
+impl ResponseStore {
+    /// 按配置创建；persist 为 true 时使用 data_dir/responses.db
+    pub fn open(config: &ResponseStoreConfig, data_dir: &Path) -> Result<Self, String> {
+        let db = if config.persist {
+            let conn = Connection::open(data_dir.join("responses.db"))
+                .map_err(|e| format!("打开响应存储数据库失败: {}", e))?;
+            Some(Self::init(conn)?)
+        } else {
+            None
+        };
+        Ok(Self::with_db(config, db))
+    }
+
+    pub fn in_memory(config: &ResponseStoreConfig) -> Self {
+        Self::with_db(config, None)
+    }
+
+    #[cfg(test)]
+    fn open_in_memory_db(config: &ResponseStoreConfig) -> Self {
+        let conn = Connection::open_in_memory().unwrap();
+        Self::with_db(config, Some(Self::init(conn).unwrap()))
+    }
+
+    fn with_db(config: &ResponseStoreConfig, db: Option<Connection>) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            cache: Arc::new(Mutex::new(MemoryCache {
+                entries: LruCache::new(capacity),
+                bytes: 0,
+                max_bytes: config.max_bytes,
+            })),
+            db: db.map(|conn| Arc::new(Mutex::new(conn))),
+            pending: Arc::new(Mutex::new(HashSet::new())),
+            retention_secs: i64::from(config.retention_days) * 86400,
+        }
+    }
+
+    fn init(conn: Connection) -> Result<Connection, String> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS stored_responses (
+                 id TEXT PRIMARY KEY,
+                 created_at INTEGER NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_stored_responses_created ON stored_responses(created_at);",
+        )
+        .map_err(|e| format!("初始化响应存储数据库失败: {}", e))?;
+        Ok(conn)
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, MemoryCache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 早于该时间 (Unix 秒) 创建的响应视为已过期
+    fn expires_before(&self) -> i64 {
+        chrono::Utc::now().timestamp() - self.retention_secs
+    }
+
+    /// 写入响应；持久化在后台完成
+    pub fn put(&self, id: String, stored: StoredResponse) {
+        let stored = Arc::new(stored);
+        self.lock_cache().put(id.clone(), stored.clone());
+
+        if let Some(db) = self.db.clone() {
+            self.pending.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone());
+            let pending = self.pending.clone();
+            let expires_before = self.expires_before();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = persist(&db, &pending, &id, &stored, expires_before) {
+                    tracing::warn!("{}", e);
+                }
+            });
+        }
+    }
+
+    /// 读取响应：先查内存，未命中时回查数据库并放回内存；超过保留期的响应视为不存在
+    pub async fn get(&self, id: &str) -> Option<Arc<StoredResponse>> {
+        let expires_before = self.expires_before();
+        {
+            let mut cache = self.lock_cache();
+            match cache.get(id) {
+                Some(stored) if stored.created_at >= expires_before => return Some(stored.clone()),
+                Some(_) => {
+                    cache.pop(id);
+                    return None;
+                }
+                None => {}
+            }
+        }
+        let db = self.db.clone()?;
+        let key = id.to_string();
+        let loaded = tokio::task::spawn_blocking(move || load(&db, &key, expires_before))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        match loaded {
+            Ok(Some(stored)) => {
+                let stored = Arc::new(stored);
+                self.lock_cache().put(id.to_string(), stored.clone());
+                Some(stored)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// 沿 previous_response_id 链拼回截至该响应的完整 contents；链上任一响应已淘汰或过期时返回 None
+    pub async fn conversation(&self, stored: &StoredResponse) -> Option<Vec<Value>> {
+        let mut turns = vec![stored.contents.clone()];
+        let mut seen = HashSet::new();
+        let mut previous = stored.previous_response_id.clone();
+        while let Some(id) = previous {
+            if !seen.insert(id.clone()) {
+                break;
+            }
+            let parent = self.get(&id).await.filter(|p| p.owner == stored.owner)?;
+            turns.push(parent.contents.clone());
+            previous = parent.previous_response_id.clone();
+        }
+        let mut contents = Vec::new();
+        for turn in turns.iter().rev() {
+            append_turn(&mut contents, turn);
+        }
+        Some(contents)
+    }
+
+    /// 删除响应，返回是否存在
+    pub async fn delete(&self, id: &str) -> bool {
+        let cached = self.lock_cache().pop(id).is_some();
+        let Some(db) = self.db.clone() else { return cached };
+        // 取消尚未落库的写入
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+        let key = id.to_string();
+        let deleted = tokio::task::spawn_blocking(move || {
+            let conn = db.lock().map_err(|_| "响应存储数据库锁已损坏".to_string())?;
+            conn.execute("DELETE FROM stored_responses WHERE id = ?1", params![key])
+                .map_err(|e| format!("删除存储的响应失败: {}", e))
+        })
+        .await;
+        match deleted {
+            Ok(Ok(rows)) => cached || rows > 0,
+            Ok(Err(e)) => {
+                tracing::warn!("{}", e);
+                cached
+            }
+            Err(_) => cached,
+        }
+    }
+}
+
+fn persist(
+    db: &Mutex<Connection>,
+    pending: &Mutex<HashSet<String>>,
+    id: &str,
+    stored: &StoredResponse,
+    expires_before: i64,
+) -> Result<(), String> {
+    let data = serde_json::to_string(stored).map_err(|e| format!("序列化响应失败: {}", e))?;
+    let conn = db.lock().map_err(|_| "响应存储数据库锁已损坏".to_string())?;
+    // 持有数据库锁时确认未被删除，与 delete 的数据库操作串行
+    if !pending.lock().unwrap_or_else(|e| e.into_inner()).remove(id) {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO stored_responses (id, created_at, data) VALUES (?1, ?2, ?3)",
+        params![id, stored.created_at, data],
+    )
+    .map_err(|e| format!("写入存储的响应失败: {}", e))?;
+    conn.execute(
+        "DELETE FROM stored_responses WHERE created_at < ?1",
+        params![expires_before],
+    )
+    .map_err(|e| format!("清理过期响应失败: {}", e))?;
+    Ok(())
+}
+
+fn load(db: &Mutex<Connection>, id: &str, expires_before: i64) -> Result<Option<StoredResponse>, String> {
+    let conn = db.lock().map_err(|_| "响应存储数据库锁已损坏".to_string())?;
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM stored_responses WHERE id = ?1 AND created_at >= ?2",
+            params![id, expires_before],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("读取存储的响应失败: {}", e))?;
+    data.map(|d| serde_json::from_str(&d).map_err(|e| format!("解析存储的响应失败: {}", e)))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stored(text: &str) -> StoredResponse {
+        StoredResponse {
+            owner: Some("key-1".to_string()),
+            session_id: None,
+            created_at: chrono::Utc::now().timestamp(),
+            previous_response_id: None,
+            response: json!({ "object": "response", "output_text": text }),
+            contents: vec![json!({ "role": "user", "parts": [{ "text": text }] })],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let store = ResponseStore::in_memory(&ResponseStoreConfig { max_entries: 2, ..Default::default() });
+        store.put("resp-a".to_string(), stored("a"));
+        store.put("resp-b".to_string(), stored("b"));
+        assert!(store.get("resp-a").await.is_some());
+        store.put("resp-c".to_string(), stored("c"));
+
+        // resp-a 刚被访问过，淘汰的是 resp-b
+        assert!(store.get("resp-b").await.is_none());
+        assert!(store.get("resp-a").await.unwrap().visible_to(Some("key-1")));
+        assert!(store.delete("resp-c").await);
+        assert!(!store.delete("resp-c").await);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_entries_survive_eviction() {
+        let store = ResponseStore::open_in_memory_db(&ResponseStoreConfig { max_entries: 1, persist: true, ..Default::default() });
+        let db = store.db.clone().unwrap();
+        for id in ["resp-a", "resp-b"] {
+            let entry = stored(id);
+            store.lock_cache().put(id.to_string(), Arc::new(entry.clone()));
+            store.pending.lock().unwrap().insert(id.to_string());
+            persist(&db, &store.pending, id, &entry, store.expires_before()).unwrap();
+        }
+
+        let restored = store.get("resp-a").await.unwrap();
+        assert_eq!(restored.contents[0]["parts"][0]["text"], "resp-a");
+        assert!(!restored.visible_to(None));
+        assert!(store.delete("resp-a").await);
+        assert!(store.get("resp-a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_not_found() {
+        let store = ResponseStore::open_in_memory_db(&ResponseStoreConfig { retention_days: 1, persist: true, ..Default::default() });
+        let db = store.db.clone().unwrap();
+        let mut old = stored("old");
+        old.created_at -= 2 * 86400;
+        store.lock_cache().put("resp-old".to_string(), Arc::new(old.clone()));
+        assert!(store.get("resp-old").await.is_none());
+
+        // 数据库中的过期记录在未触发清理时同样不可读取
+        store.pending.lock().unwrap().insert("resp-old".to_string());
+        persist(&db, &store.pending, "resp-old", &old, i64::MIN).unwrap();
+        assert!(store.get("resp-old").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_cancels_pending_persist() {
+        let store = ResponseStore::open_in_memory_db(&ResponseStoreConfig { persist: true, ..Default::default() });
+        let db = store.db.clone().unwrap();
+        let entry = stored("a");
+
+        // put 已入队但尚未落库时 delete，之后到达的写入不应恢复该响应
+        store.pending.lock().unwrap().insert("resp-a".to_string());
+        store.lock_cache().put("resp-a".to_string(), Arc::new(entry.clone()));
+        assert!(store.delete("resp-a").await);
+        persist(&db, &store.pending, "resp-a", &entry, store.expires_before()).unwrap();
+        assert!(store.get("resp-a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conversation_rebuilt_from_turns() {
+        let store = ResponseStore::in_memory(&ResponseStoreConfig::default());
+        let text = |role: &str, t: &str| json!({ "role": role, "parts": [{ "text": t }] });
+
+        // 第一轮：user -> model
+        let first = vec![text("user", "q1"), text("model", "a1")];
+        store.put("resp-1".to_string(), StoredResponse { contents: first.clone(), ..stored("q1") });
+
+        // 第二轮输入以 model 角色开头，构建请求时被合并进历史末条
+        let history = store.conversation(&store.get("resp-1").await.unwrap()).await.unwrap();
+        assert_eq!(history, first);
+        let request = vec![
+            text("user", "q1"),
+            json!({ "role": "model", "parts": [{ "text": "a1" }, { "text": "a1-edit" }] }),
+            text("user", "q2"),
+        ];
+        let mut turn = turn_contents(&history, request.clone());
+        assert_eq!(turn[0], text("model", "a1-edit"));
+        turn.push(text("model", "a2"));
+        store.put("resp-2".to_string(), StoredResponse {
+            previous_response_id: Some("resp-1".to_string()),
+            contents: turn,
+            ..stored("q2")
+        });
+
+        let rebuilt = store.conversation(&store.get("resp-2").await.unwrap()).await.unwrap();
+        assert_eq!(rebuilt[..3], request[..]);
+        assert_eq!(rebuilt[3], text("model", "a2"));
+
+        // 链上的上一轮已删除时无法续接
+        assert!(store.delete("resp-1").await);
+        assert!(store.conversation(&store.get("resp-2").await.unwrap()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_cache_capped_by_bytes() {
+        let size = stored("a").approx_bytes();
+        let store = ResponseStore::in_memory(&ResponseStoreConfig { max_bytes: size * 2, ..Default::default() });
+        for id in ["resp-a", "resp-b", "resp-c"] {
+            store.put(id.to_string(), stored("a"));
+        }
+        assert!(store.get("resp-a").await.is_none());
+        assert!(store.get("resp-c").await.is_some());
+        assert_eq!(store.lock_cache().bytes, size * 2);
+
+        // 单条超过上限的响应不驻留内存
+        store.put("resp-big".to_string(), stored(&"x".repeat(size * 2)));
+        assert!(store.get("resp-big").await.is_none());
+        assert_eq!(store.lock_cache().bytes, 0);
+    }
+}
@@ -0,0 +1,64 @@
+// 会话标识解析 - 用于账号的会话亲和
+
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::hash::{Hash, Hasher};
+
+/// 客户端显式指定会话的请求头
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// 解析请求的会话标识，优先级：
+/// 1. `x-session-id` 请求头
+/// 2. 请求体中的客户端标识 (Claude `metadata.user_id` / OpenAI `user`)
+/// 3. 会话首条消息的哈希 (同一对话的后续轮次首条消息不变)
+///
+/// 三者都没有时返回 None，此时不做会话绑定
+pub fn resolve_session_id<T: Serialize>(
+    headers: &HeaderMap,
+    client_id: Option<&str>,
+    first_message: Option<&T>,
+) -> Option<String> {
+    let header = headers
+        .get(SESSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+    if let Some(id) = header {
+        return Some(format!("header:{}", id));
+    }
+
+    if let Some(id) = client_id.map(str::trim).filter(|v| !v.is_empty()) {
+        return Some(format!("client:{}", id));
+    }
+
+    let seed = serde_json::to_string(first_message?).ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    seed.hash(&mut hasher);
+    Some(format!("hash:{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_session_id_priority() {
+        let message = json!({"role": "user", "content": "hello"});
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SESSION_HEADER, "abc".parse().unwrap());
+        assert_eq!(resolve_session_id(&headers, Some("user-1"), Some(&message)).unwrap(), "header:abc");
+
+        let headers = HeaderMap::new();
+        assert_eq!(resolve_session_id(&headers, Some("user-1"), Some(&message)).unwrap(), "client:user-1");
+
+        let hashed = resolve_session_id(&headers, None, Some(&message)).unwrap();
+        assert!(hashed.starts_with("hash:"));
+        assert_eq!(resolve_session_id(&headers, Some(" "), Some(&message)).unwrap(), hashed);
+
+        let other = json!({"role": "user", "content": "bye"});
+        assert_ne!(resolve_session_id(&headers, None, Some(&other)).unwrap(), hashed);
+        assert!(resolve_session_id::<serde_json::Value>(&headers, None, None).is_none());
+    }
+}
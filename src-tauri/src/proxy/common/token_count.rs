@@ -0,0 +1,152 @@
+// 输入 token 统计：构造上游 countTokens 请求，以及上游不可用时的本地估算
+use serde_json::{json, Value};
+
+use crate::proxy::upstream::client::UpstreamClient;
+
+/// 每张图片 / 文件按 Gemini 的固定计费估算
+const INLINE_DATA_TOKENS: u64 = 258;
+/// 每条 content 的角色与分隔开销
+const CONTENT_OVERHEAD_TOKENS: u64 = 3;
+
+fn text_tokens(text: &str) -> u64 {
+    tiktoken_rs::o200k_base_singleton().encode_ordinary(text).len() as u64
+}
+
+fn parts_tokens(parts: Option<&Value>) -> u64 {
+    parts
+        .and_then(|p| p.as_array())
+        .map(|parts| {
+            parts
+                .iter()
+                .map(|part| {
+                    if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+                        text_tokens(text)
+                    } else if part.get("inlineData").is_some() || part.get("fileData").is_some() {
+                        INLINE_DATA_TOKENS
+                    } else if let Some(call) = part.get("functionCall").or_else(|| part.get("functionResponse")) {
+                        text_tokens(&call.to_string())
+                    } else {
+                        0
+                    }
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// 本地估算 Gemini 格式请求 (未经 v1internal 包装) 的输入 token 数
+///
+/// 使用 o200k 分词器近似，与 Gemini 实际分词存在少量偏差，仅作为上游不可用时的回退
+pub fn estimate_input_tokens(request: &Value) -> u64 {
+    let system = parts_tokens(request.get("systemInstruction").and_then(|s| s.get("parts")));
+    let contents: u64 = request
+        .get("contents")
+        .and_then(|c| c.as_array())
+        .map(|contents| {
+            contents
+                .iter()
+                .map(|c| CONTENT_OVERHEAD_TOKENS + parts_tokens(c.get("parts")))
+                .sum()
+        })
+        .unwrap_or(0);
+    let tools = request
+        .get("tools")
+        .filter(|t| !t.is_null())
+        .map(|t| text_tokens(&t.to_string()))
+        .unwrap_or(0);
+    system + contents + tools
+}
+
+/// 构造 v1internal countTokens 请求体
+///
+/// 上游 countTokens 只接受 contents，系统指令与工具声明折算为额外的文本 content 一并计数
+pub fn build_count_tokens_body(request: &Value, model: &str) -> Value {
+    let mut contents: Vec<Value> = Vec::new();
+    if let Some(parts) = request.get("systemInstruction").and_then(|s| s.get("parts")) {
+        contents.push(json!({ "role": "user", "parts": parts }));
+    }
+    if let Some(items) = request.get("contents").and_then(|c| c.as_array()) {
+        contents.extend(items.iter().cloned());
+    }
+    if let Some(tools) = request.get("tools").filter(|t| !t.is_null()) {
+        contents.push(json!({ "role": "user", "parts": [{ "text": tools.to_string() }] }));
+    }
+
+    json!({
+        "request": {
+            "model": format!("models/{}", model),
+            "contents": contents,
+        }
+    })
+}
+
+/// 解析 countTokens 响应中的 totalTokens
+pub fn parse_total_tokens(response: &Value) -> Option<u64> {
+    response
+        .get("response")
+        .unwrap_or(response)
+        .get("totalTokens")
+        .and_then(|v| v.as_u64().or_else(|| v.as_str().and_then(|s| s.parse().ok())))
+}
+
+/// 统计输入 token：有可用账号时调用上游 countTokens，无账号或上游失败时回退到本地估算
+pub async fn count_input_tokens(
+    upstream: &UpstreamClient,
+    access_token: Option<&str>,
+    model: &str,
+    request: &Value,
+) -> u64 {
+    if let Some(access_token) = access_token {
+        match upstream.count_tokens(access_token, model, request).await {
+            Ok(total) => return total,
+            Err(e) => tracing::warn!("上游 countTokens 失败，改用本地估算: {}", e),
+        }
+    }
+    let request = request.clone();
+    tokio::task::spawn_blocking(move || estimate_input_tokens(&request))
+        .await
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_request() -> Value {
+        json!({
+            "systemInstruction": { "parts": [{ "text": "You are a helpful assistant." }] },
+            "contents": [
+                { "role": "user", "parts": [{ "text": "Hello, world!" }, { "inlineData": { "mimeType": "image/png", "data": "AAAA" } }] },
+                { "role": "model", "parts": [{ "functionCall": { "name": "ls", "args": { "path": "." } } }] }
+            ],
+            "tools": [{ "functionDeclarations": [{ "name": "ls", "parameters": { "type": "object" } }] }]
+        })
+    }
+
+    #[test]
+    fn test_estimate_counts_all_sections() {
+        let request = sample_request();
+        let estimate = estimate_input_tokens(&request);
+        let contents_only = estimate_input_tokens(&json!({ "contents": request["contents"] }));
+        assert!(contents_only > INLINE_DATA_TOKENS + 2 * CONTENT_OVERHEAD_TOKENS);
+        assert!(estimate > contents_only);
+        assert_eq!(estimate_input_tokens(&json!({})), 0);
+    }
+
+    #[test]
+    fn test_count_tokens_body_folds_system_and_tools() {
+        let body = build_count_tokens_body(&sample_request(), "gemini-2.5-flash");
+        assert_eq!(body["request"]["model"], "models/gemini-2.5-flash");
+        let contents = body["request"]["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 4);
+        assert_eq!(contents[0]["parts"][0]["text"], "You are a helpful assistant.");
+        assert!(contents[3]["parts"][0]["text"].as_str().unwrap().contains("functionDeclarations"));
+    }
+
+    #[test]
+    fn test_parse_total_tokens() {
+        assert_eq!(parse_total_tokens(&json!({ "totalTokens": 42 })), Some(42));
+        assert_eq!(parse_total_tokens(&json!({ "response": { "totalTokens": "7" } })), Some(7));
+        assert_eq!(parse_total_tokens(&json!({})), None);
+    }
+}
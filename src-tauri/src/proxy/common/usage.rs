@@ -0,0 +1,144 @@
+// 请求级 Token 用量记录：非流式响应直接解析，流式响应在转发过程中旁路读取 usageMetadata
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+
+use crate::proxy::admin::usage::{TokenUsage, UsageRecord, UsageStore};
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::middleware::auth::ClientIdentity;
+
+/// 单次请求的用量上下文 (调用方 / 账号 / 模型)
+#[derive(Clone)]
+pub struct UsageContext {
+    store: UsageStore,
+    client_key_id: Option<String>,
+    client_name: String,
+    account_id: String,
+    account_email: String,
+    protocol: ApiProtocol,
+    model: String,
+    mapped_model: String,
+}
+
+impl UsageContext {
+    pub fn new(
+        store: &UsageStore,
+        client: &ClientIdentity,
+        account_id: &str,
+        account_email: &str,
+        protocol: ApiProtocol,
+        model: &str,
+        mapped_model: &str,
+    ) -> Self {
+        Self {
+            store: store.clone(),
+            client_key_id: client.key_id().map(String::from),
+            client_name: client.name().to_string(),
+            account_id: account_id.to_string(),
+            account_email: account_email.to_string(),
+            protocol,
+            model: model.to_string(),
+            mapped_model: mapped_model.to_string(),
+        }
+    }
+
+    pub fn record(&self, usage: TokenUsage) {
+        let protocol = match self.protocol {
+            ApiProtocol::Openai => "openai",
+            ApiProtocol::Claude => "claude",
+            ApiProtocol::Gemini => "gemini",
+        };
+        self.store.record(UsageRecord {
+            ts: chrono::Utc::now().timestamp(),
+            client_key_id: self.client_key_id.clone(),
+            client_name: self.client_name.clone(),
+            account_id: self.account_id.clone(),
+            account_email: self.account_email.clone(),
+            protocol: protocol.to_string(),
+            model: self.model.clone(),
+            mapped_model: self.mapped_model.clone(),
+            usage,
+        });
+    }
+
+    /// 记录非流式 Gemini 响应中的用量
+    pub fn record_response(&self, gemini_resp: &serde_json::Value) {
+        if let Some(usage) = TokenUsage::from_gemini(gemini_resp) {
+            self.record(usage);
+        }
+    }
+
+    /// 旁路读取上游 SSE 流中的 usageMetadata，流结束 (或客户端断开) 时记录最后一次出现的用量
+    pub fn tap<S, E>(self, stream: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        let mut tap = UsageTap { ctx: self, buffer: Vec::new(), latest: None };
+        stream.map(move |item| {
+            if let Ok(bytes) = &item {
+                tap.feed(bytes);
+            }
+            item
+        })
+    }
+}
+
+struct UsageTap {
+    ctx: UsageContext,
+    buffer: Vec<u8>,
+    latest: Option<TokenUsage>,
+}
+
+impl UsageTap {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let Ok(line) = std::str::from_utf8(&line) else { continue };
+            let Some(data) = line.trim().strip_prefix("data:") else { continue };
+            if !data.contains("usageMetadata") {
+                continue;
+            }
+            if let Some(usage) = serde_json::from_str(data.trim()).ok().as_ref().and_then(TokenUsage::from_gemini) {
+                self.latest = Some(usage);
+            }
+        }
+    }
+}
+
+impl Drop for UsageTap {
+    fn drop(&mut self) {
+        if let Some(usage) = self.latest.take() {
+            self.ctx.record(usage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::admin::usage::UsageQuery;
+
+    #[tokio::test]
+    async fn test_tap_records_last_usage_when_stream_ends() {
+        let store = UsageStore::open_in_memory().unwrap();
+        let ctx = UsageContext::new(&store, &ClientIdentity::default(), "a", "a@example.com", ApiProtocol::Gemini, "gemini-pro", "gemini-2.5-pro");
+        let chunks: Vec<Result<Bytes, String>> = vec![
+            Ok(Bytes::from("data: {\"response\":{\"usageMetadata\":{\"promptTokenCount\":10,\"candidatesTokenCount\":1}}}\n\ndata: {\"response\":{\"usage")),
+            Ok(Bytes::from("Metadata\":{\"promptTokenCount\":10,\"candidatesTokenCount\":25,\"thoughtsTokenCount\":4}}}\n\n")),
+        ];
+        let forwarded: Vec<_> = ctx.tap(futures::stream::iter(chunks)).collect().await;
+        assert_eq!(forwarded.len(), 2);
+
+        // 记录在后台线程写入
+        let mut buckets = Vec::new();
+        for _ in 0..50 {
+            buckets = store.query(&UsageQuery::default()).unwrap();
+            if !buckets.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(buckets.len(), 1);
+        assert_eq!((buckets[0].requests, buckets[0].output_tokens, buckets[0].thinking_tokens), (1, 25, 4));
+    }
+}
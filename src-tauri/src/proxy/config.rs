@@ -18,7 +18,40 @@ pub struct ProxyConfig {
     
     /// API 密钥
     pub api_key: String,
-    
+
+    /// 关闭客户端 API Key 校验
+    /// 仅在 allow_lan_access = false (仅本机访问) 时生效，开启局域网访问时始终校验
+    #[serde(default)]
+    pub disable_auth: bool,
+
+    /// 托管的客户端 API Key (与 api_key 并存，api_key 视为不受限的主 Key)
+    #[serde(default)]
+    pub client_keys: Vec<ClientApiKey>,
+
+    /// 请求限流 (按客户端 Key / 来源 IP)
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// 来源 IP 访问控制 (CIDR 允许 / 拒绝列表)
+    #[serde(default)]
+    pub ip_access: IpAccessConfig,
+
+    /// 监听端口 TLS (修改后需重启服务；证书文件变更会自动重新加载)
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    /// 跨域策略 (修改后需重启服务)
+    #[serde(default)]
+    pub cors: CorsConfig,
+
+    /// Responses API 的响应存储 (修改后需重启服务)
+    #[serde(default)]
+    pub response_store: ResponseStoreConfig,
+
+    /// Web 管理后台密码的 Argon2 哈希 (PHC 字符串)
+    /// 未设置时管理后台暂时使用 api_key 登录
+    #[serde(default)]
+    pub admin_password_hash: Option<String>,
 
     /// 是否自动启动
     pub auto_start: bool,
@@ -42,6 +75,383 @@ pub struct ProxyConfig {
     /// 上游代理配置
     #[serde(default)]
     pub upstream_proxy: UpstreamProxyConfig,
+
+    /// 账号调度配置
+    #[serde(default)]
+    pub scheduling: SchedulingConfig,
+
+    /// 账号分组路由规则 (按顺序匹配，首条命中生效)
+    #[serde(default)]
+    pub routing_rules: Vec<RoutingRule>,
+}
+
+/// 账号调度策略
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulingStrategy {
+    /// 轮询 (优先剩余配额最多的梯队，梯队内轮询)
+    #[default]
+    RoundRobin,
+    /// 用满一个账号再切换下一个
+    FillFirst,
+    /// 最久未使用的账号优先
+    LeastRecentlyUsed,
+    /// 随机选择
+    Random,
+    /// 按剩余配额加权随机
+    QuotaWeighted,
+    /// 上游响应延迟 (EWMA) 最低的账号优先
+    LowestLatency,
+}
+
+/// 账号调度配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulingConfig {
+    #[serde(default)]
+    pub strategy: SchedulingStrategy,
+    /// 会话亲和窗口 (秒)：同一会话在该空闲时间内固定使用同一账号，0 表示关闭
+    #[serde(default = "default_sticky_window_secs")]
+    pub sticky_window_secs: u64,
+    /// 单个账号同时进行中的上游请求上限 (含流式响应)，0 表示不限制
+    #[serde(default)]
+    pub max_concurrency_per_account: usize,
+    /// 所有账号都满载时请求排队等待的最长时间 (秒)
+    #[serde(default = "default_queue_timeout_secs")]
+    pub queue_timeout_secs: u64,
+}
+
+impl Default for SchedulingConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SchedulingStrategy::default(),
+            sticky_window_secs: default_sticky_window_secs(),
+            max_concurrency_per_account: 0,
+            queue_timeout_secs: default_queue_timeout_secs(),
+        }
+    }
+}
+
+fn default_sticky_window_secs() -> u64 {
+    60
+}
+
+fn default_queue_timeout_secs() -> u64 {
+    30
+}
+
+/// 账号分组路由规则：将模型或请求类型限制到指定分组 (账号标签)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RoutingRule {
+    /// 匹配的上游模型名 (映射后)，以 `*` 结尾表示前缀匹配；为空表示匹配所有模型
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 匹配的请求类型 (agent / web_search / image_gen)；为空表示匹配所有类型
+    #[serde(default)]
+    pub request_type: Option<String>,
+    /// 命中后只允许使用带有该标签的账号
+    pub group: String,
+}
+
+impl RoutingRule {
+    pub fn matches(&self, model: &str, request_type: &str) -> bool {
+        let model_ok = match self.model.as_deref() {
+            None | Some("") => true,
+            Some(pattern) => model_pattern_matches(pattern, model),
+        };
+        let type_ok = match self.request_type.as_deref() {
+            None | Some("") => true,
+            Some(t) => t == request_type,
+        };
+        model_ok && type_ok
+    }
+}
+
+/// 模型名匹配：`*` 匹配所有，以 `*` 结尾表示前缀匹配，其余为精确匹配
+pub fn model_pattern_matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+/// 客户端协议 (按路由区分)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiProtocol {
+    /// /v1/chat/completions, /v1/completions, /v1/responses, /v1/models
+    Openai,
+    /// /v1/messages
+    Claude,
+    /// /v1beta/models
+    Gemini,
+}
+
+/// 托管的客户端 API Key：团队共享代理时为每个使用者分配独立的 Key，可单独吊销
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientApiKey {
+    pub id: String,
+    /// 使用者名称 (用于区分调用方)
+    pub name: String,
+    pub key: String,
+    pub created_at: i64,
+    /// 过期时间 (Unix 秒)，为空表示永不过期
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// 允许请求的模型 (客户端模型名，支持末尾 `*` 前缀匹配)；为空表示不限制
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// 允许使用的协议；为空表示不限制
+    #[serde(default)]
+    pub allowed_protocols: Vec<ApiProtocol>,
+    /// 固定使用的账号分组 (账号标签)，优先于分组路由规则
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    /// 用量预算
+    #[serde(default)]
+    pub budget: KeyBudget,
+    /// 限流覆盖；为空时使用 rate_limit.per_key
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// 限流额度 (0 表示不限制)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimit {
+    /// 每分钟请求数 (令牌桶容量，按秒匀速回填)
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// 同时进行中的请求 / 流 (响应体传输结束才释放)
+    #[serde(default)]
+    pub max_concurrent_streams: u32,
+}
+
+impl RateLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_minute == 0 && self.max_concurrent_streams == 0
+    }
+}
+
+/// 请求限流配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// 每个客户端 Key 的默认限额 (主 API Key 视为一个 Key)
+    #[serde(default)]
+    pub per_key: RateLimit,
+    /// 每个来源 IP 的限额
+    #[serde(default)]
+    pub per_ip: RateLimit,
+}
+
+/// 单组路由的跨域策略
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CorsPolicy {
+    /// 允许的来源 (如 "https://chat.example.com")；"*" 表示任意来源，为空表示不允许跨域访问
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// 是否允许携带 Cookie 等凭据 (不能与 "*" 同时使用)
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// 允许浏览器脚本读取的响应头
+    #[serde(default)]
+    pub exposed_headers: Vec<String>,
+}
+
+/// 跨域配置；推理路由与管理后台分别配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// 协议路由 (/v1/*, /v1beta/*)，默认允许任意来源，供浏览器端聊天客户端使用
+    #[serde(default = "default_api_cors")]
+    pub api: CorsPolicy,
+    /// 管理后台 (/admin, /api/admin/*)，默认仅允许同源访问
+    #[serde(default)]
+    pub admin: CorsPolicy,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            api: default_api_cors(),
+            admin: CorsPolicy::default(),
+        }
+    }
+}
+
+fn default_api_cors() -> CorsPolicy {
+    CorsPolicy {
+        allowed_origins: vec!["*".to_string()],
+        allow_credentials: false,
+        exposed_headers: [
+            "x-ratelimit-limit-requests",
+            "x-ratelimit-remaining-requests",
+            "x-ratelimit-reset-requests",
+            "retry-after",
+            "retry-after-ms",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect(),
+    }
+}
+
+/// Responses API 响应存储配置 (store / previous_response_id)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResponseStoreConfig {
+    /// 内存中最多保留的响应数 (LRU 淘汰)
+    #[serde(default = "default_response_store_entries")]
+    pub max_entries: usize,
+    /// 内存中响应的总字节数上限 (按序列化大小估算，超出时按 LRU 淘汰)
+    #[serde(default = "default_response_store_bytes")]
+    pub max_bytes: usize,
+    /// 是否持久化到 SQLite (重启后仍可续接)
+    #[serde(default)]
+    pub persist: bool,
+    /// 响应的保留天数，过期后不可再查询或续接 (持久化记录同时被清理)
+    #[serde(default = "default_response_retention_days")]
+    pub retention_days: u32,
+}
+
+impl Default for ResponseStoreConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_response_store_entries(),
+            max_bytes: default_response_store_bytes(),
+            persist: false,
+            retention_days: default_response_retention_days(),
+        }
+    }
+}
+
+fn default_response_store_entries() -> usize {
+    1000
+}
+
+fn default_response_store_bytes() -> usize {
+    256 * 1024 * 1024
+}
+
+fn default_response_retention_days() -> u32 {
+    30
+}
+
+/// TLS 证书来源
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsMode {
+    /// 使用 cert_path / key_path 指定的 PEM 文件
+    #[default]
+    Files,
+    /// 自动生成自签名证书 (保存在数据目录 tls/ 下，重启后复用)
+    SelfSigned,
+}
+
+/// 监听端口 TLS 配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub mode: TlsMode,
+    /// PEM 证书链路径 (mode = files)
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    /// PEM 私钥路径 (mode = files)
+    #[serde(default)]
+    pub key_path: Option<String>,
+    /// 自签名证书额外包含的主机名 / IP (localhost 与 127.0.0.1 始终包含)；修改后下次启动时重新生成证书
+    #[serde(default)]
+    pub self_signed_hosts: Vec<String>,
+}
+
+/// 一组来源 IP 规则 (CIDR 或单个 IP，如 "10.0.0.0/8"、"192.168.1.20")
+/// - deny 优先于 allow
+/// - allow 为空表示不限制；非空时仅放行匹配项 (直连的本机回环地址始终放行，除非被 deny 命中；
+///   经受信任代理转发的请求不适用)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IpRules {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// 来源 IP 访问控制；协议路由与管理后台分别配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IpAccessConfig {
+    /// 协议路由 (/v1/*, /v1beta/*)
+    #[serde(default)]
+    pub api: IpRules,
+    /// 管理后台 (/admin, /api/admin/*)
+    #[serde(default)]
+    pub admin: IpRules,
+    /// 受信任的反向代理；仅来自这些地址的连接才采信 X-Forwarded-For
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// 单个周期内的用量上限；为空表示不限制
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BudgetLimits {
+    #[serde(default)]
+    pub requests: Option<u64>,
+    #[serde(default)]
+    pub input_tokens: Option<u64>,
+    /// 输出 Token 上限 (含思考 Token)
+    #[serde(default)]
+    pub output_tokens: Option<u64>,
+}
+
+impl BudgetLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.requests.is_none() && self.input_tokens.is_none() && self.output_tokens.is_none()
+    }
+}
+
+/// 客户端 Key 用量预算 (按 UTC 自然日 / 自然月重置)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyBudget {
+    #[serde(default)]
+    pub daily: BudgetLimits,
+    #[serde(default)]
+    pub monthly: BudgetLimits,
+}
+
+impl KeyBudget {
+    pub fn is_unlimited(&self) -> bool {
+        self.daily.is_unlimited() && self.monthly.is_unlimited()
+    }
+}
+
+impl ClientApiKey {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            key: format!("sk-{}", uuid::Uuid::new_v4().simple()),
+            created_at: chrono::Utc::now().timestamp(),
+            expires_at: None,
+            allowed_models: Vec::new(),
+            allowed_protocols: Vec::new(),
+            group: None,
+            disabled: false,
+            budget: KeyBudget::default(),
+            rate_limit: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty()
+            || self.allowed_models.iter().any(|p| model_pattern_matches(p, model))
+    }
+
+    pub fn allows_protocol(&self, protocol: ApiProtocol) -> bool {
+        self.allowed_protocols.is_empty() || self.allowed_protocols.contains(&protocol)
+    }
 }
 
 /// 上游代理配置
@@ -60,12 +470,22 @@ impl Default for ProxyConfig {
             allow_lan_access: false, // 默认仅本机访问，隐私优先
             port: 8045,
             api_key: format!("sk-{}", uuid::Uuid::new_v4().simple()),
+            disable_auth: false,
+            client_keys: Vec::new(),
+            rate_limit: RateLimitConfig::default(),
+            ip_access: IpAccessConfig::default(),
+            tls: TlsConfig::default(),
+            cors: CorsConfig::default(),
+            response_store: ResponseStoreConfig::default(),
+            admin_password_hash: None,
             auto_start: false,
             anthropic_mapping: std::collections::HashMap::new(),
             openai_mapping: std::collections::HashMap::new(),
             custom_mapping: std::collections::HashMap::new(),
             request_timeout: default_request_timeout(),
             upstream_proxy: UpstreamProxyConfig::default(),
+            scheduling: SchedulingConfig::default(),
+            routing_rules: Vec::new(),
         }
     }
 }
@@ -85,4 +505,18 @@ impl ProxyConfig {
             "127.0.0.1"
         }
     }
+
+    /// 访问地址的协议头
+    pub fn url_scheme(&self) -> &str {
+        if self.tls.enabled {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// 是否需要校验客户端 API Key
+    pub fn auth_required(&self) -> bool {
+        self.allow_lan_access || !self.disable_auth
+    }
 }
@@ -1,15 +1,20 @@
 // 管理API处理器 - 用于Web界面
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{Html, IntoResponse, Json},
     http::header,
 };
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 
+use crate::proxy::common::budget::KeyBudgetStatus;
+use crate::proxy::common::ip_filter::IpAccessControl;
+use crate::proxy::config::{ApiProtocol, ClientApiKey, IpAccessConfig, KeyBudget, RateLimit, RateLimitConfig, RoutingRule, SchedulingConfig};
+use crate::proxy::middleware::auth::ProxyAuthConfig;
 use crate::proxy::server::AppState;
-use crate::proxy::admin::models::{AdminError, StatusDto};
+use crate::proxy::admin::usage::{UsageBucket, UsageQuery};
+use crate::proxy::admin::models::{AccountHealthDto, AdminError, StatusDto};
 
 /// 管理界面HTML
 pub async fn serve_admin_ui() -> impl IntoResponse {
@@ -41,6 +46,10 @@ pub struct ProxyConfigData {
     anthropic_mapping: HashMap<String, String>,
     openai_mapping: HashMap<String, String>,
     custom_mapping: HashMap<String, String>,
+    scheduling: SchedulingConfig,
+    routing_rules: Vec<RoutingRule>,
+    rate_limit: RateLimitConfig,
+    ip_access: IpAccessConfig,
 }
 
 pub async fn get_config(State(_state): State<AppState>) -> Result<Json<ConfigResponse>, AdminError> {
@@ -61,6 +70,10 @@ pub async fn get_config(State(_state): State<AppState>) -> Result<Json<ConfigRes
             anthropic_mapping: config.proxy.anthropic_mapping,
             openai_mapping: config.proxy.openai_mapping,
             custom_mapping: config.proxy.custom_mapping,
+            scheduling: config.proxy.scheduling,
+            routing_rules: config.proxy.routing_rules,
+            rate_limit: config.proxy.rate_limit,
+            ip_access: config.proxy.ip_access,
         },
         accounts_count: accounts.len(),
     };
@@ -77,6 +90,10 @@ pub struct UpdateConfigRequest {
     anthropic_mapping: Option<HashMap<String, String>>,
     openai_mapping: Option<HashMap<String, String>>,
     custom_mapping: Option<HashMap<String, String>>,
+    scheduling: Option<SchedulingConfig>,
+    routing_rules: Option<Vec<RoutingRule>>,
+    rate_limit: Option<RateLimitConfig>,
+    ip_access: Option<IpAccessConfig>,
 }
 
 pub async fn update_config(
@@ -106,6 +123,20 @@ pub async fn update_config(
     if let Some(mapping) = req.custom_mapping {
         config.proxy.custom_mapping = mapping;
     }
+    if let Some(scheduling) = req.scheduling {
+        config.proxy.scheduling = scheduling;
+    }
+    if let Some(rules) = req.routing_rules {
+        config.proxy.routing_rules = rules;
+    }
+    if let Some(rate_limit) = req.rate_limit {
+        config.proxy.rate_limit = rate_limit;
+    }
+    if let Some(ip_access) = req.ip_access {
+        config.proxy.ip_access = ip_access;
+    }
+    let ip_access = IpAccessControl::from_config(&config.proxy.ip_access)
+        .map_err(AdminError::bad_request)?;
 
     // 保存配置
     crate::modules::config::save_app_config(&config)
@@ -124,6 +155,10 @@ pub async fn update_config(
         let mut custom = state.custom_mapping.write().await;
         *custom = config.proxy.custom_mapping.clone();
     }
+    state.token_manager.update_scheduling(config.proxy.scheduling.clone()).await;
+    state.token_manager.update_routing_rules(config.proxy.routing_rules.clone()).await;
+    *state.rate_limit.write().await = config.proxy.rate_limit.clone();
+    *state.ip_access.write().await = ip_access;
 
     Ok(Json(serde_json::json!({
         "success": true,
@@ -131,12 +166,205 @@ pub async fn update_config(
     })))
 }
 
+/// 客户端 API Key 信息 (列表中不回传完整密钥)
+#[derive(Serialize)]
+pub struct ClientKeyInfo {
+    id: String,
+    name: String,
+    key_preview: String,
+    created_at: i64,
+    expires_at: Option<i64>,
+    allowed_models: Vec<String>,
+    allowed_protocols: Vec<ApiProtocol>,
+    group: Option<String>,
+    disabled: bool,
+    expired: bool,
+    budget: KeyBudget,
+    rate_limit: Option<RateLimit>,
+}
+
+impl From<&ClientApiKey> for ClientKeyInfo {
+    fn from(key: &ClientApiKey) -> Self {
+        let prefix: String = key.key.chars().take(7).collect();
+        let suffix: String = key.key.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+        Self {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            key_preview: format!("{}...{}", prefix, suffix),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            allowed_models: key.allowed_models.clone(),
+            allowed_protocols: key.allowed_protocols.clone(),
+            group: key.group.clone(),
+            disabled: key.disabled,
+            expired: key.is_expired(chrono::Utc::now().timestamp()),
+            budget: key.budget.clone(),
+            rate_limit: key.rate_limit,
+        }
+    }
+}
+
+/// 保存客户端 Key 并热更新认证配置
+async fn save_client_keys(state: &AppState, config: &crate::models::AppConfig) -> Result<(), AdminError> {
+    crate::modules::config::save_app_config(config)
+        .map_err(|e| AdminError::internal(format!("Failed to save config: {}", e)))?;
+    *state.auth.write().await = ProxyAuthConfig::from_proxy_config(&config.proxy);
+    Ok(())
+}
+
+fn load_config() -> Result<crate::models::AppConfig, AdminError> {
+    crate::modules::config::load_app_config()
+        .map_err(|e| AdminError::internal(format!("Failed to load config: {}", e)))
+}
+
+/// 区分 "未提供" 与 "显式置空 (null)"
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// 列出客户端 API Key
+pub async fn list_client_keys() -> Result<Json<Vec<ClientKeyInfo>>, AdminError> {
+    let config = load_config()?;
+    Ok(Json(config.proxy.client_keys.iter().map(ClientKeyInfo::from).collect()))
+}
+
+/// 创建客户端 API Key 请求
+#[derive(Deserialize)]
+pub struct CreateClientKeyRequest {
+    name: String,
+    #[serde(default)]
+    expires_at: Option<i64>,
+    #[serde(default)]
+    allowed_models: Vec<String>,
+    #[serde(default)]
+    allowed_protocols: Vec<ApiProtocol>,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    budget: KeyBudget,
+    #[serde(default)]
+    rate_limit: Option<RateLimit>,
+}
+
+/// 创建客户端 API Key (完整密钥仅在创建时返回)
+pub async fn create_client_key(
+    State(state): State<AppState>,
+    Json(req): Json<CreateClientKeyRequest>,
+) -> Result<Json<ClientApiKey>, AdminError> {
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AdminError::bad_request("Key name is required"));
+    }
+
+    let mut config = load_config()?;
+    let mut key = ClientApiKey::new(name);
+    key.expires_at = req.expires_at;
+    key.allowed_models = req.allowed_models;
+    key.allowed_protocols = req.allowed_protocols;
+    key.group = req.group.filter(|g| !g.is_empty());
+    key.budget = req.budget;
+    key.rate_limit = req.rate_limit;
+    config.proxy.client_keys.push(key.clone());
+    save_client_keys(&state, &config).await?;
+
+    tracing::info!("已创建客户端 API Key: {} ({})", key.name, key.id);
+    Ok(Json(key))
+}
+
+/// 更新客户端 API Key 请求 (未提供的字段保持不变)
+#[derive(Deserialize)]
+pub struct UpdateClientKeyRequest {
+    name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    expires_at: Option<Option<i64>>,
+    allowed_models: Option<Vec<String>>,
+    allowed_protocols: Option<Vec<ApiProtocol>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    group: Option<Option<String>>,
+    disabled: Option<bool>,
+    budget: Option<KeyBudget>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    rate_limit: Option<Option<RateLimit>>,
+}
+
+/// 更新客户端 API Key 策略
+pub async fn update_client_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<String>,
+    Json(req): Json<UpdateClientKeyRequest>,
+) -> Result<Json<ClientKeyInfo>, AdminError> {
+    let mut config = load_config()?;
+    let key = config.proxy.client_keys
+        .iter_mut()
+        .find(|k| k.id == key_id)
+        .ok_or_else(|| AdminError::not_found(format!("Client key not found: {}", key_id)))?;
+
+    if let Some(name) = req.name {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(AdminError::bad_request("Key name is required"));
+        }
+        key.name = name;
+    }
+    if let Some(expires_at) = req.expires_at {
+        key.expires_at = expires_at;
+    }
+    if let Some(models) = req.allowed_models {
+        key.allowed_models = models;
+    }
+    if let Some(protocols) = req.allowed_protocols {
+        key.allowed_protocols = protocols;
+    }
+    if let Some(group) = req.group {
+        key.group = group.filter(|g| !g.is_empty());
+    }
+    if let Some(disabled) = req.disabled {
+        key.disabled = disabled;
+    }
+    if let Some(budget) = req.budget {
+        key.budget = budget;
+    }
+    if let Some(rate_limit) = req.rate_limit {
+        key.rate_limit = rate_limit;
+    }
+    let info = ClientKeyInfo::from(&*key);
+    save_client_keys(&state, &config).await?;
+
+    Ok(Json(info))
+}
+
+/// 删除 (吊销) 客户端 API Key
+pub async fn delete_client_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    let mut config = load_config()?;
+    let before = config.proxy.client_keys.len();
+    config.proxy.client_keys.retain(|k| k.id != key_id);
+    if config.proxy.client_keys.len() == before {
+        return Err(AdminError::not_found(format!("Client key not found: {}", key_id)));
+    }
+    save_client_keys(&state, &config).await?;
+
+    tracing::info!("已删除客户端 API Key: {}", key_id);
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Key 已删除"
+    })))
+}
+
 /// 账号列表响应
 #[derive(Serialize)]
 pub struct AccountInfo {
     id: String,
     email: String,
     name: Option<String>,
+    tags: Vec<String>,
+    status: crate::models::AccountStatus,
     is_active: bool,
     quota: Option<QuotaInfo>,
 }
@@ -167,6 +395,8 @@ pub async fn list_accounts() -> Result<Json<Vec<AccountInfo>>, AdminError> {
             id: acc.id.clone(),
             email: acc.email,
             name: acc.name,
+            tags: acc.tags,
+            status: acc.status,
             is_active: current_id.as_ref() == Some(&acc.id),
             quota: acc.quota.map(|q| QuotaInfo {
                 models: q.models.iter().map(|m| ModelQuotaInfo {
@@ -281,6 +511,7 @@ pub async fn switch_account(
 
 /// 刷新账号配额
 pub async fn refresh_account_quota(
+    State(state): State<AppState>,
     Path(account_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, AdminError> {
     // 加载账号
@@ -296,12 +527,127 @@ pub async fn refresh_account_quota(
     crate::modules::account::update_account_quota(&account_id, quota)
         .map_err(|e| AdminError::internal(format!("Failed to update quota: {}", e)))?;
 
+    // 运行时同步 TokenManager (配额参与选号)
+    if let Err(e) = state.token_manager.reload_accounts().await {
+        tracing::warn!("Failed to reload accounts in TokenManager: {}", e);
+    }
+
     Ok(Json(serde_json::json!({
         "success": true,
         "message": "配额已刷新"
     })))
 }
 
+/// 更新账号分组标签请求
+#[derive(Deserialize)]
+pub struct UpdateAccountTagsRequest {
+    tags: Vec<String>,
+}
+
+/// 更新账号分组标签
+pub async fn update_account_tags(
+    State(state): State<AppState>,
+    Path(account_id): Path<String>,
+    Json(req): Json<UpdateAccountTagsRequest>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    let account = crate::modules::account::update_account_tags(&account_id, req.tags)
+        .map_err(|e| AdminError::not_found(format!("Account not found: {}", e)))?;
+
+    // 运行时同步 TokenManager (标签参与分组路由)
+    if let Err(e) = state.token_manager.reload_accounts().await {
+        tracing::warn!("Failed to reload accounts in TokenManager: {}", e);
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "tags": account.tags
+    })))
+}
+
+/// 更新账号状态并同步到运行中的账号池
+async fn set_account_status(
+    state: &AppState,
+    account_id: &str,
+    status: crate::models::AccountStatus,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    let account = crate::modules::account::set_account_status(account_id, status)
+        .map_err(|e| AdminError::not_found(format!("Account not found: {}", e)))?;
+
+    if let Err(e) = state.token_manager.reload_accounts().await {
+        tracing::warn!("Failed to reload accounts in TokenManager: {}", e);
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "status": account.status
+    })))
+}
+
+/// 停用账号 (不删除，保留 token 与配额记录)
+pub async fn disable_account(
+    State(state): State<AppState>,
+    Path(account_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    set_account_status(&state, &account_id, crate::models::AccountStatus::Disabled).await
+}
+
+/// 启用账号
+pub async fn enable_account(
+    State(state): State<AppState>,
+    Path(account_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    set_account_status(&state, &account_id, crate::models::AccountStatus::Active).await
+}
+
+/// 排空账号：已绑定的会话继续使用，不再接收新会话
+pub async fn drain_account(
+    State(state): State<AppState>,
+    Path(account_id): Path<String>,
+) -> Result<Json<serde_json::Value>, AdminError> {
+    set_account_status(&state, &account_id, crate::models::AccountStatus::Draining).await
+}
+
+/// 账号池健康状态 (冷却 / 熔断)
+pub async fn get_accounts_health(State(state): State<AppState>) -> Json<Vec<AccountHealthDto>> {
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let health = state.token_manager.health_snapshot()
+        .into_iter()
+        .map(|(account_id, email, h)| {
+            let circuit_open = h.is_circuit_open(now_ms);
+            AccountHealthDto {
+                account_id,
+                email,
+                available: !h.invalid && !circuit_open && !h.cooldowns.contains_key(""),
+                circuit_open,
+                circuit_open_until: h.circuit_open_until.filter(|_| circuit_open),
+                consecutive_auth_failures: h.consecutive_auth_failures,
+                cooldowns: h.cooldowns,
+                last_error: h.last_error,
+                last_error_at: h.last_error_at,
+                latency_ewma_ms: h.latency_ewma_ms,
+                invalid: h.invalid,
+                last_refresh_at: h.last_refresh_at,
+                last_refresh_error: h.last_refresh_error,
+            }
+        })
+        .collect();
+
+    Json(health)
+}
+
+/// 清除账号的冷却与熔断状态
+pub async fn reset_account_health(
+    State(state): State<AppState>,
+    Path(account_id): Path<String>,
+) -> Json<serde_json::Value> {
+    state.token_manager.reset_health(&account_id);
+
+    Json(serde_json::json!({
+        "success": true,
+        "message": "账号健康状态已重置"
+    }))
+}
+
 /// 服务状态
 pub async fn get_status(State(state): State<AppState>) -> Json<StatusDto> {
     let pinned_account_id = state.token_manager.pinned_account_id().await;
@@ -330,10 +676,29 @@ pub struct StatsResponse {
     latency_ms_p95: f64,
     rps: f64,
     time_series: TimeSeriesData,
+    /// 配置了预算的客户端 Key 当前周期用量
+    budgets: Vec<KeyBudgetStatus>,
 }
 
-pub async fn get_stats() -> Json<StatsResponse> {
+pub async fn get_stats(State(state): State<AppState>) -> Json<StatsResponse> {
     let snapshot = crate::proxy::admin::global_stats().snapshot().await;
+    let keys = state.auth.read().await.client_keys.clone();
+    let store = state.usage.clone();
+    let budgets = tokio::task::spawn_blocking(move || {
+        let now = chrono::Utc::now();
+        keys.iter()
+            .filter(|k| !k.budget.is_unlimited())
+            .filter_map(|k| match KeyBudgetStatus::load(&store, k, now) {
+                Ok(status) => Some(status),
+                Err(e) => {
+                    tracing::warn!("读取 Key [{}] 预算用量失败: {}", k.name, e);
+                    None
+                }
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default();
     Json(StatsResponse {
         requests_total: snapshot.requests_total,
         requests_ok: snapshot.requests_ok,
@@ -343,9 +708,23 @@ pub async fn get_stats() -> Json<StatsResponse> {
         latency_ms_p95: snapshot.latency_ms_p95,
         rps: snapshot.rps,
         time_series: snapshot.time_series,
+        budgets,
     })
 }
 
+/// 按天 / 小时聚合的 Token 用量 (可按 Key / 账号 / 模型分组与筛选)
+pub async fn get_usage(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<Vec<UsageBucket>>, AdminError> {
+    let store = state.usage.clone();
+    let buckets = tokio::task::spawn_blocking(move || store.query(&query))
+        .await
+        .map_err(|e| AdminError::internal(format!("Usage query task failed: {}", e)))?
+        .map_err(AdminError::internal)?;
+    Ok(Json(buckets))
+}
+
 // ==================== 配置导出API ====================
 
 #[derive(Serialize)]
@@ -383,6 +762,10 @@ pub async fn export_config() -> Result<Json<ConfigExport>, AdminError> {
                 anthropic_mapping: config.proxy.anthropic_mapping,
                 openai_mapping: config.proxy.openai_mapping,
                 custom_mapping: config.proxy.custom_mapping,
+                scheduling: config.proxy.scheduling,
+                routing_rules: config.proxy.routing_rules,
+                rate_limit: config.proxy.rate_limit,
+                ip_access: config.proxy.ip_access,
             },
         },
     }))
@@ -413,6 +796,10 @@ struct ProxyImportData {
     anthropic_mapping: Option<HashMap<String, String>>,
     openai_mapping: Option<HashMap<String, String>>,
     custom_mapping: Option<HashMap<String, String>>,
+    scheduling: Option<SchedulingConfig>,
+    routing_rules: Option<Vec<RoutingRule>>,
+    rate_limit: Option<RateLimitConfig>,
+    ip_access: Option<IpAccessConfig>,
 }
 
 #[derive(Serialize)]
@@ -509,6 +896,20 @@ pub async fn import_config(
     if let Some(mapping) = proxy_data.custom_mapping {
         config.proxy.custom_mapping = mapping;
     }
+    if let Some(scheduling) = proxy_data.scheduling {
+        config.proxy.scheduling = scheduling;
+    }
+    if let Some(rules) = proxy_data.routing_rules {
+        config.proxy.routing_rules = rules;
+    }
+    if let Some(rate_limit) = proxy_data.rate_limit {
+        config.proxy.rate_limit = rate_limit;
+    }
+    if let Some(ip_access) = proxy_data.ip_access {
+        config.proxy.ip_access = ip_access;
+    }
+    let ip_access = IpAccessControl::from_config(&config.proxy.ip_access)
+        .map_err(AdminError::bad_request)?;
 
     crate::modules::config::save_app_config(&config)
         .map_err(|e| AdminError::internal(format!("Failed to save config: {}", e)))?;
@@ -526,6 +927,10 @@ pub async fn import_config(
         let mut custom = state.custom_mapping.write().await;
         *custom = config.proxy.custom_mapping.clone();
     }
+    state.token_manager.update_scheduling(config.proxy.scheduling.clone()).await;
+    state.token_manager.update_routing_rules(config.proxy.routing_rules.clone()).await;
+    *state.rate_limit.write().await = config.proxy.rate_limit.clone();
+    *state.ip_access.write().await = ip_access;
 
     Ok(Json(serde_json::json!({
         "applied": true,
@@ -3,19 +3,23 @@
 use axum::{
     body::Body,
     extract::{Json, State},
-    http::{header, StatusCode},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
+    Extension,
 };
 use bytes::Bytes;
 use futures::StreamExt;
 use serde_json::{json, Value};
-use tokio::time::{sleep, Duration};
 use tracing::{debug, error};
 
 use crate::proxy::mappers::claude::{
     transform_claude_request_in, transform_response, create_claude_sse_stream, ClaudeRequest,
 };
+use crate::proxy::common::usage::UsageContext;
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::middleware::auth::{client_error_response, ClientIdentity};
 use crate::proxy::server::AppState;
+use crate::proxy::token_manager::SelectedToken;
 
 const MAX_RETRY_ATTEMPTS: usize = 3;
 
@@ -24,6 +28,8 @@ const MAX_RETRY_ATTEMPTS: usize = 3;
 /// 处理 Chat 消息请求流程
 pub async fn handle_messages(
     State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
     Json(request): Json<ClaudeRequest>,
 ) -> Response {
     // 获取最新一条“有意义”的消息内容（用于日志记录和后台任务检测）
@@ -74,8 +80,21 @@ pub async fn handle_messages(
     
     crate::modules::logger::log_info(&format!("Received Claude request for model: {}, content_preview: {:.100}...", request.model, latest_msg));
 
-    // 1. 获取 会话 ID (已废弃基于内容的哈希，改用 TokenManager 内部的时间窗口锁定)
-    let _session_id: Option<&str> = None;
+    // 客户端 Key 模型权限
+    if !client.allows_model(&request.model) {
+        return client_error_response(
+            ApiProtocol::Claude,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", request.model),
+        );
+    }
+
+    // 1. 获取 会话 ID (会话亲和)：x-session-id > metadata.user_id > 首条消息哈希
+    let session_id = crate::proxy::common::session::resolve_session_id(
+        &headers,
+        request.metadata.as_ref().and_then(|m| m.user_id.as_deref()),
+        request.messages.first(),
+    );
 
     // 2. 获取 UpstreamClient
     let upstream = state.upstream.clone();
@@ -89,6 +108,15 @@ pub async fn handle_messages(
 
     let mut last_error = String::new();
     let mut retried_without_thinking = false;
+
+    // --- 核心优化：智能识别与拦截后台自动请求 ---
+    // 关键词识别：标题生成、摘要提取、下一步提示建议等
+    // [Optimization] 使用更长的预览窗口 (500 chars) 以捕获更具体的意图
+    let preview_msg = latest_msg.chars().take(500).collect::<String>();
+    let is_background_task = preview_msg.contains("write a 5-10 word title") 
+        || preview_msg.contains("Respond with the title")
+        || preview_msg.contains("Concise summary")
+        || preview_msg.contains("prompt suggestion generator");
     
     for attempt in 0..max_attempts {
         // 3. 模型路由与配置解析 (提前解析以确定请求类型)
@@ -98,10 +126,14 @@ pub async fn handle_messages(
             &*state.openai_mapping.read().await,
             &*state.anthropic_mapping.read().await,
         );
+        // 后台任务会被重定向到廉价模型，需在选号前确定，以便按实际模型的配额选择账号
+        if is_background_task {
+             mapped_model = "gemini-2.5-flash".to_string();
+        }
         let config = crate::proxy::mappers::common_utils::resolve_request_config(&request_for_body.model, &mapped_model);
 
         // 4. 获取 Token (使用准确的 request_type)
-        let (access_token, project_id, email) = match token_manager.get_token(&config.request_type, false).await {
+        let SelectedToken { account_id, access_token, project_id, email, permit } = match token_manager.get_token_in_group(&config.request_type, &config.final_model, false, session_id.as_deref(), client.group()).await {
             Ok(t) => t,
             Err(e) => {
                  return (
@@ -118,21 +150,11 @@ pub async fn handle_messages(
         };
 
         tracing::info!("Using account: {} for request (type: {})", email, config.request_type);
-        
-        // --- 核心优化：智能识别与拦截后台自动请求 ---
-        // 关键词识别：标题生成、摘要提取、下一步提示建议等
-        // [Optimization] 使用更长的预览窗口 (500 chars) 以捕获更具体的意图
-        let preview_msg = latest_msg.chars().take(500).collect::<String>();
-        let is_background_task = preview_msg.contains("write a 5-10 word title") 
-            || preview_msg.contains("Respond with the title")
-            || preview_msg.contains("Concise summary")
-            || preview_msg.contains("prompt suggestion generator");
 
         // 传递映射后的模型名
         let mut request_with_mapped = request_for_body.clone();
 
         if is_background_task {
-             mapped_model = "gemini-2.5-flash".to_string();
              tracing::info!("[AUTO] 检测到后台自动任务 ({}...)，已智能重定向到廉价节点: {}", 
                 preview_msg,
                 mapped_model
@@ -176,6 +198,7 @@ pub async fn handle_messages(
     let method = if is_stream { "streamGenerateContent" } else { "generateContent" };
     let query = if is_stream { Some("alt=sse") } else { None };
 
+    let started_at = std::time::Instant::now();
     let response = match upstream.call_v1_internal(
         method,
         &access_token,
@@ -194,9 +217,12 @@ pub async fn handle_messages(
         
         // 成功
         if status.is_success() {
+            token_manager.mark_success(&account_id, started_at.elapsed().as_millis() as u64);
+            let usage = UsageContext::new(&state.usage, &client, &account_id, &email, ApiProtocol::Claude, &request.model, &config.final_model);
+
             // 处理流式响应
             if request.stream {
-                let stream = response.bytes_stream();
+                let stream = permit.attach(usage.tap(response.bytes_stream()));
                 let gemini_stream = Box::pin(stream);
                 let claude_stream = create_claude_sse_stream(gemini_stream);
 
@@ -231,6 +257,7 @@ pub async fn handle_messages(
                     Ok(v) => v,
                     Err(e) => return (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)).into_response(),
                 };
+                usage.record_response(&gemini_resp);
 
                 // 解包 response 字段（v1internal 格式）
                 let raw = gemini_resp.get("response").unwrap_or(&gemini_resp);
@@ -257,19 +284,11 @@ pub async fn handle_messages(
         
         let status_code = status.as_u16();
         
-        // Handle transient 429s using upstream-provided retry delay (avoid surfacing errors to clients).
+        // 429: 账号对该模型进入冷却 (时长取上游 retryDelay/quotaResetDelay)，下一次选号会自动避开；
+        // 若所有账号都只是短暂冷却，get_token 会等待最早恢复的账号，避免把瞬时限流暴露给客户端
         if status_code == 429 {
-            if let Some(delay_ms) = crate::proxy::upstream::retry::parse_retry_delay(&error_text) {
-                let actual_delay = delay_ms.saturating_add(200).min(10_000);
-                tracing::warn!(
-                    "Claude Upstream 429 on attempt {}/{}, waiting {}ms then retrying",
-                    attempt + 1,
-                    max_attempts,
-                    actual_delay
-                );
-                sleep(Duration::from_millis(actual_delay)).await;
-                continue;
-            }
+            let retry_delay = crate::proxy::upstream::retry::parse_retry_delay(&error_text);
+            token_manager.mark_rate_limited(&account_id, &config.final_model, retry_delay);
         }
 
         // Special-case 400 errors caused by invalid/foreign thinking signatures (common after /resume).
@@ -318,6 +337,10 @@ pub async fn handle_messages(
                 return (status, error_text).into_response();
             }
 
+            if status_code == 403 || status_code == 401 {
+                token_manager.mark_auth_failure(&account_id, status_code);
+            }
+
             tracing::warn!("Claude Upstream {} on attempt {}/{}, rotating account", status, attempt + 1, max_attempts);
             continue;
         }
@@ -363,12 +386,59 @@ pub async fn handle_list_models() -> impl IntoResponse {
     }))
 }
 
-/// 计算 tokens (占位符)
-pub async fn handle_count_tokens(Json(_body): Json<Value>) -> impl IntoResponse {
-    Json(json!({
-        "input_tokens": 0,
-        "output_tokens": 0
-    }))
+/// 计算输入 tokens
+///
+/// 请求经与 /v1/messages 相同的映射转换后调用上游 countTokens；无可用账号或上游失败时使用本地估算
+pub async fn handle_count_tokens(
+    State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    Json(mut request): Json<ClaudeRequest>,
+) -> Response {
+    if !client.allows_model(&request.model) {
+        return client_error_response(
+            ApiProtocol::Claude,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", request.model),
+        );
+    }
+
+    let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+        &request.model,
+        &*state.custom_mapping.read().await,
+        &*state.openai_mapping.read().await,
+        &*state.anthropic_mapping.read().await,
+    );
+    let config = crate::proxy::mappers::common_utils::resolve_request_config(&request.model, &mapped_model);
+
+    // 不占用推理并发槽位：没有立即空闲的账号时直接本地估算
+    let token = state.token_manager.peek_token(&config.request_type, &config.final_model, client.group()).await;
+    if token.is_none() {
+        tracing::debug!("count_tokens 暂无空闲账号，使用本地估算");
+    }
+
+    request.model = mapped_model;
+    let project_id = token.as_ref().map(|(_, project_id)| project_id.as_str()).unwrap_or_default();
+    let gemini_body = match transform_claude_request_in(&request, project_id) {
+        Ok(b) => b,
+        Err(e) => {
+            return client_error_response(
+                ApiProtocol::Claude,
+                StatusCode::BAD_REQUEST,
+                &format!("Transform error: {}", e),
+            );
+        }
+    };
+    let inner = gemini_body.get("request").unwrap_or(&gemini_body);
+
+    let input_tokens = crate::proxy::common::token_count::count_input_tokens(
+        &state.upstream,
+        token.as_ref().map(|(access_token, _)| access_token.as_str()),
+        &config.final_model,
+        inner,
+    )
+    .await;
+
+    Json(json!({ "input_tokens": input_tokens })).into_response()
 }
 
 #[cfg(test)]
@@ -1,10 +1,14 @@
 // Gemini Handler
-use axum::{extract::State, extract::{Json, Path}, http::StatusCode, response::IntoResponse};
+use axum::{extract::State, extract::{Json, Path}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}, Extension};
 use serde_json::{json, Value};
 use tracing::{debug, error};
 
 use crate::proxy::mappers::gemini::{wrap_request, unwrap_response};
+use crate::proxy::common::usage::UsageContext;
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::middleware::auth::{client_error_response, ClientIdentity};
 use crate::proxy::server::AppState;
+use crate::proxy::token_manager::SelectedToken;
  
 const MAX_RETRY_ATTEMPTS: usize = 3;
  
@@ -13,6 +17,8 @@ const MAX_RETRY_ATTEMPTS: usize = 3;
 pub async fn handle_generate(
     State(state): State<AppState>,
     Path(model_action): Path<String>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
     Json(body): Json<Value>
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     // 解析 model:method
@@ -25,11 +31,30 @@ pub async fn handle_generate(
     crate::modules::logger::log_info(&format!("Received Gemini request: {}/{}", model_name, method));
 
     // 1. 验证方法
+    if method == "countTokens" {
+        return Ok(count_tokens(&state, &client, &model_name, &body).await);
+    }
     if method != "generateContent" && method != "streamGenerateContent" {
         return Err((StatusCode::BAD_REQUEST, format!("Unsupported method: {}", method)));
     }
     let is_stream = method == "streamGenerateContent";
 
+    // 客户端 Key 模型权限
+    if !client.allows_model(&model_name) {
+        return Ok(client_error_response(
+            ApiProtocol::Gemini,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", model_name),
+        ));
+    }
+
+    // 会话标识 (会话亲和)：x-session-id > 首条 content 哈希
+    let session_id = crate::proxy::common::session::resolve_session_id(
+        &headers,
+        None,
+        body.get("contents").and_then(|c| c.get(0)),
+    );
+
     // 2. 获取 UpstreamClient 和 TokenManager
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
@@ -49,7 +74,7 @@ pub async fn handle_generate(
         let config = crate::proxy::mappers::common_utils::resolve_request_config(&model_name, &mapped_model);
 
         // 4. 获取 Token (使用准确的 request_type)
-        let (access_token, project_id, email) = match token_manager.get_token(&config.request_type, false).await {
+        let SelectedToken { account_id, access_token, project_id, email, permit } = match token_manager.get_token_in_group(&config.request_type, &config.final_model, false, session_id.as_deref(), client.group()).await {
             Ok(t) => t,
             Err(e) => {
                 return Err((StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)));
@@ -65,6 +90,7 @@ pub async fn handle_generate(
         let query_string = if is_stream { Some("alt=sse") } else { None };
         let upstream_method = if is_stream { "streamGenerateContent" } else { "generateContent" };
 
+        let started_at = std::time::Instant::now();
         let response = match upstream
             .call_v1_internal(upstream_method, &access_token, wrapped_body, query_string)
             .await {
@@ -78,14 +104,16 @@ pub async fn handle_generate(
 
         let status = response.status();
         if status.is_success() {
+            token_manager.mark_success(&account_id, started_at.elapsed().as_millis() as u64);
+            let usage = UsageContext::new(&state.usage, &client, &account_id, &email, ApiProtocol::Gemini, &model_name, &config.final_model);
+
             // 6. 响应处理
             if is_stream {
                 use axum::body::Body;
-                use axum::response::Response;
                 use bytes::{Bytes, BytesMut};
                 use futures::StreamExt;
                 
-                let mut response_stream = response.bytes_stream();
+                let mut response_stream = permit.attach(usage.tap(response.bytes_stream()));
                 let mut buffer = BytesMut::new();
 
                 let stream = async_stream::stream! {
@@ -155,6 +183,7 @@ pub async fn handle_generate(
                 .json()
                 .await
                 .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
+            usage.record_response(&gemini_resp);
 
             let unwrapped = unwrap_response(&gemini_resp);
             return Ok(Json(unwrapped).into_response());
@@ -167,6 +196,14 @@ pub async fn handle_generate(
  
         // 只有 429 (限流), 403 (权限/地区限制) 和 401 (认证失效) 触发账号轮换
         if status_code == 429 || status_code == 403 || status_code == 401 {
+            // 429 使账号对该模型进入冷却，401/403 累计到一定次数后打开熔断
+            if status_code == 429 {
+                let retry_delay = crate::proxy::upstream::retry::parse_retry_delay(&error_text);
+                token_manager.mark_rate_limited(&account_id, &config.final_model, retry_delay);
+            } else {
+                token_manager.mark_auth_failure(&account_id, status_code);
+            }
+
             // 只有明确包含 "QUOTA_EXHAUSTED" 才停止，避免误判上游的频率限制提示 (如 "check quota")
             if status_code == 429 && error_text.contains("QUOTA_EXHAUSTED") {
                 error!("Gemini Quota exhausted (429) on attempt {}/{}, stopping to protect pool.", attempt + 1, max_attempts);
@@ -187,8 +224,9 @@ pub async fn handle_generate(
 
 pub async fn handle_list_models(State(state): State<AppState>) -> Result<impl IntoResponse, (StatusCode, String)> {
     let model_group = "gemini";
-    let (access_token, _, _) = state.token_manager.get_token(model_group, false).await
-        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)))?;
+    let access_token = state.token_manager.get_token(model_group, "", false, None).await
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)))?
+        .access_token;
 
     // Fetch from upstream
     let upstream_models = state.upstream.fetch_available_models(&access_token).await
@@ -236,10 +274,54 @@ pub async fn handle_get_model(Path(model_name): Path<String>) -> impl IntoRespon
     }))
 }
 
-pub async fn handle_count_tokens(State(state): State<AppState>, Path(_model_name): Path<String>, Json(_body): Json<Value>) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let model_group = "gemini";
-    let (_access_token, _project_id, _) = state.token_manager.get_token(model_group, false).await
-        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)))?;
-    
-    Ok(Json(json!({"totalTokens": 0})))
+/// 处理 countTokens (`/v1beta/models/{model}:countTokens` 与 `/v1beta/models/{model}/countTokens`)
+pub async fn handle_count_tokens(
+    State(state): State<AppState>,
+    Path(model_name): Path<String>,
+    Extension(client): Extension<ClientIdentity>,
+    Json(body): Json<Value>,
+) -> Response {
+    count_tokens(&state, &client, &model_name, &body).await
+}
+
+/// 统计输入 tokens：按与 generateContent 相同的映射包装后调用上游 countTokens，失败时本地估算
+async fn count_tokens(state: &AppState, client: &ClientIdentity, model_name: &str, body: &Value) -> Response {
+    if !client.allows_model(model_name) {
+        return client_error_response(
+            ApiProtocol::Gemini,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", model_name),
+        );
+    }
+
+    // 请求体可以是 {contents} 或 {generateContentRequest: {...}}
+    let request = body.get("generateContentRequest").unwrap_or(body);
+
+    let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+        model_name,
+        &*state.custom_mapping.read().await,
+        &*state.openai_mapping.read().await,
+        &*state.anthropic_mapping.read().await,
+    );
+    let config = crate::proxy::mappers::common_utils::resolve_request_config(model_name, &mapped_model);
+
+    // 不占用推理并发槽位：没有立即空闲的账号时直接本地估算
+    let token = state.token_manager.peek_token(&config.request_type, &config.final_model, client.group()).await;
+    if token.is_none() {
+        tracing::debug!("countTokens 暂无空闲账号，使用本地估算");
+    }
+
+    let project_id = token.as_ref().map(|(_, project_id)| project_id.as_str()).unwrap_or_default();
+    let wrapped = wrap_request(request, project_id, &mapped_model);
+    let inner = wrapped.get("request").unwrap_or(&wrapped);
+
+    let total_tokens = crate::proxy::common::token_count::count_input_tokens(
+        &state.upstream,
+        token.as_ref().map(|(access_token, _)| access_token.as_str()),
+        &config.final_model,
+        inner,
+    )
+    .await;
+
+    Json(json!({ "totalTokens": total_tokens })).into_response()
 }
@@ -3,5 +3,6 @@
 
 pub mod claude;
 pub mod openai;
+pub mod responses;
 pub mod gemini;
 pub mod admin;  // Web管理界面
@@ -1,16 +1,22 @@
 // OpenAI Handler
-use axum::{extract::State, extract::Json, http::StatusCode, response::IntoResponse};
+use axum::{extract::State, extract::Json, http::{HeaderMap, StatusCode}, response::IntoResponse, Extension};
 use serde_json::{json, Value};
 use tracing::{debug, error};
 
 use crate::proxy::mappers::openai::{transform_openai_request, transform_openai_response, OpenAIRequest};
 // use crate::proxy::upstream::client::UpstreamClient; // 通过 state 获取
+use crate::proxy::common::usage::UsageContext;
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::middleware::auth::{client_error_response, ClientIdentity};
 use crate::proxy::server::AppState;
+use crate::proxy::token_manager::SelectedToken;
  
 const MAX_RETRY_ATTEMPTS: usize = 3;
  
 pub async fn handle_chat_completions(
     State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
     Json(body): Json<Value>
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let mut openai_req: OpenAIRequest = serde_json::from_value(body)
@@ -30,6 +36,22 @@ pub async fn handle_chat_completions(
 
     debug!("Received OpenAI request for model: {}", openai_req.model);
 
+    // 客户端 Key 模型权限
+    if !client.allows_model(&openai_req.model) {
+        return Ok(client_error_response(
+            ApiProtocol::Openai,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", openai_req.model),
+        ));
+    }
+
+    // 会话标识 (会话亲和)：x-session-id > user 字段 > 首条用户消息哈希
+    let session_id = crate::proxy::common::session::resolve_session_id(
+        &headers,
+        openai_req.user.as_deref(),
+        openai_req.messages.iter().find(|m| m.role == "user"),
+    );
+
     // 1. 获取 UpstreamClient (Clone handle)
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
@@ -49,7 +71,7 @@ pub async fn handle_chat_completions(
         let config = crate::proxy::mappers::common_utils::resolve_request_config(&openai_req.model, &mapped_model);
 
         // 3. 获取 Token (使用准确的 request_type)
-        let (access_token, project_id, email) = match token_manager.get_token(&config.request_type, false).await {
+        let SelectedToken { account_id, access_token, project_id, email, permit } = match token_manager.get_token_in_group(&config.request_type, &config.final_model, false, session_id.as_deref(), client.group()).await {
             Ok(t) => t,
             Err(e) => {
                 return Err((StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e)));
@@ -66,6 +88,7 @@ pub async fn handle_chat_completions(
         let method = if list_response { "streamGenerateContent" } else { "generateContent" };
         let query_string = if list_response { Some("alt=sse") } else { None };
 
+        let started_at = std::time::Instant::now();
         let response = match upstream
             .call_v1_internal(method, &access_token, gemini_body, query_string)
             .await {
@@ -79,6 +102,9 @@ pub async fn handle_chat_completions(
 
         let status = response.status();
         if status.is_success() {
+            token_manager.mark_success(&account_id, started_at.elapsed().as_millis() as u64);
+            let usage = UsageContext::new(&state.usage, &client, &account_id, &email, ApiProtocol::Openai, &openai_req.model, &config.final_model);
+
             // 5. 处理流式 vs 非流式
             if list_response {
                 use crate::proxy::mappers::openai::streaming::create_openai_sse_stream;
@@ -86,8 +112,8 @@ pub async fn handle_chat_completions(
                 use axum::body::Body;
                 // Removed redundant StreamExt
 
-                let gemini_stream = response.bytes_stream();
-                let openai_stream = create_openai_sse_stream(Box::pin(gemini_stream), openai_req.model.clone());
+                let gemini_stream = permit.attach(usage.tap(response.bytes_stream()));
+                let openai_stream = create_openai_sse_stream(Box::pin(gemini_stream), openai_req.model.clone(), openai_req.include_usage());
                 let body = Body::from_stream(openai_stream);
 
                 return Ok(Response::builder()
@@ -103,6 +129,7 @@ pub async fn handle_chat_completions(
                 .json()
                 .await
                 .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
+            usage.record_response(&gemini_resp);
 
             let openai_response = transform_openai_response(&gemini_resp);
             return Ok(Json(openai_response).into_response());
@@ -115,18 +142,10 @@ pub async fn handle_chat_completions(
  
         // 429 智能处理
         if status_code == 429 {
-            // 1. 优先尝试解析 RetryInfo (由 Google Cloud 直接下发)
-            if let Some(delay_ms) = crate::proxy::upstream::retry::parse_retry_delay(&error_text) {
-                let actual_delay = delay_ms.saturating_add(200).min(10_000);
-                tracing::warn!(
-                    "OpenAI Upstream 429 on attempt {}/{}, waiting {}ms then retrying",
-                    attempt + 1,
-                    max_attempts,
-                    actual_delay
-                );
-                tokio::time::sleep(tokio::time::Duration::from_millis(actual_delay)).await;
-                continue;
-            }
+            // 1. 账号对该模型进入冷却，时长优先取上游下发的 RetryInfo / quotaResetDelay
+            //    下一次选号会自动避开该账号；若所有账号只是短暂冷却，get_token 会等待最早恢复的账号
+            let retry_delay = crate::proxy::upstream::retry::parse_retry_delay(&error_text);
+            token_manager.mark_rate_limited(&account_id, &config.final_model, retry_delay);
 
             // 2. 只有明确包含 "QUOTA_EXHAUSTED" 才停止，避免误判频率提示 (如 "check quota")
             if error_text.contains("QUOTA_EXHAUSTED") {
@@ -141,6 +160,7 @@ pub async fn handle_chat_completions(
 
         // 只有 403 (权限/地区限制) 和 401 (认证失效) 触发账号轮换
         if status_code == 403 || status_code == 401 {
+            token_manager.mark_auth_failure(&account_id, status_code);
             tracing::warn!("OpenAI Upstream {} on attempt {}/{}, rotating account", status_code, attempt + 1, max_attempts);
             continue;
         }
@@ -158,159 +178,14 @@ pub async fn handle_chat_completions(
 /// 将 Prompt 转换为 Chat Message 格式，复用 handle_chat_completions
 pub async fn handle_completions(
     State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
     Json(mut body): Json<Value>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    tracing::info!("Received /v1/completions or /v1/responses payload: {:?}", body);
+    tracing::debug!("Received /v1/completions payload: {:?}", body);
 
-    let is_codex_style = body.get("input").is_some() && body.get("instructions").is_some();
-    
-    // 1. Convert Payload to Messages (Shared Chat Format)
-    if is_codex_style {
-        let instructions = body.get("instructions").and_then(|v| v.as_str()).unwrap_or_default();
-        let input_items = body.get("input").and_then(|v| v.as_array());
-        
-        let mut messages = Vec::new();
-        
-        // System Instructions
-        if !instructions.is_empty() {
-            messages.push(json!({ "role": "system", "content": instructions }));
-        }
-
-        let mut call_id_to_name = std::collections::HashMap::new();
-
-        // Pass 1: Build Call ID to Name Map
-        if let Some(items) = input_items {
-            for item in items {
-                let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
-                 match item_type {
-                    "function_call" | "local_shell_call" | "web_search_call" => {
-                        let call_id = item.get("call_id").and_then(|v| v.as_str())
-                                     .or_else(|| item.get("id").and_then(|v| v.as_str()))
-                                     .unwrap_or("unknown");
-                        
-                        let name = if item_type == "local_shell_call" {
-                            "shell"
-                        } else if item_type == "web_search_call" {
-                            "google_search"
-                        } else {
-                            item.get("name").and_then(|v| v.as_str()).unwrap_or("unknown")
-                        };
-                        
-                        call_id_to_name.insert(call_id.to_string(), name.to_string());
-                        tracing::debug!("Mapped call_id {} to name {}", call_id, name);
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        // Pass 2: Map Input Items to Messages
-        if let Some(items) = input_items {
-            for item in items {
-                let item_type = item.get("type").and_then(|v| v.as_str()).unwrap_or("");
-                match item_type {
-                    "message" => {
-                        let role = item.get("role").and_then(|v| v.as_str()).unwrap_or("user");
-                        let content = item.get("content").and_then(|v| v.as_array());
-                        let mut text_parts = Vec::new();
-                        if let Some(parts) = content {
-                            for part in parts {
-                                if let Some(text) = part.get("text").and_then(|v| v.as_str()) {
-                                    text_parts.push(text);
-                                }
-                            }
-                        }
-                        messages.push(json!({
-                            "role": role,
-                            "content": text_parts.join("\n")
-                        }));
-                    }
-                    "function_call" | "local_shell_call" | "web_search_call" => {
-                        let mut name = item.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
-                        let mut args_str = item.get("arguments").and_then(|v| v.as_str()).unwrap_or("{}").to_string();
-                        let call_id = item.get("call_id").and_then(|v| v.as_str()).or_else(|| item.get("id").and_then(|v| v.as_str())).unwrap_or("unknown");
-                        
-                        // Handle native shell calls
-                        if item_type == "local_shell_call" {
-                            name = "shell";
-                            if let Some(action) = item.get("action") {
-                                if let Some(exec) = action.get("exec") {
-                                    // Map to ShellCommandToolCallParams (string command) or ShellToolCallParams (array command)
-                                    // Most LLMs prefer a single string for shell
-                                    let mut args_obj = serde_json::Map::new();
-                                    if let Some(cmd) = exec.get("command") {
-                                        // CRITICAL FIX: The 'shell' tool schema defines 'command' as an ARRAY of strings.
-                                        // We MUST pass it as an array, not a joined string, otherwise Gemini rejects with 400 INVALID_ARGUMENT.
-                                        let cmd_val = if cmd.is_string() {
-                                             json!([cmd]) // Wrap in array
-                                        } else {
-                                             cmd.clone() // Assume already array
-                                        };
-                                        args_obj.insert("command".to_string(), cmd_val);
-                                    }
-                                    if let Some(wd) = exec.get("working_directory").or(exec.get("workdir")) {
-                                        args_obj.insert("workdir".to_string(), wd.clone());
-                                    }
-                                    args_str = serde_json::to_string(&args_obj).unwrap_or("{}".to_string());
-                                }
-                            }
-                        } else if item_type == "web_search_call" {
-                            name = "google_search";
-                            if let Some(action) = item.get("action") {
-                                let mut args_obj = serde_json::Map::new();
-                                if let Some(q) = action.get("query") {
-                                    args_obj.insert("query".to_string(), q.clone());
-                                }
-                                args_str = serde_json::to_string(&args_obj).unwrap_or("{}".to_string());
-                            }
-                        }
-
-                        messages.push(json!({
-                            "role": "assistant",
-                            "tool_calls": [
-                                {
-                                    "id": call_id,
-                                    "type": "function",
-                                    "function": {
-                                        "name": name,
-                                        "arguments": args_str
-                                    }
-                                }
-                            ]
-                        }));
-                    }
-                    "function_call_output" | "custom_tool_call_output" => {
-                        let call_id = item.get("call_id").and_then(|v| v.as_str()).unwrap_or("unknown");
-                        let output = item.get("output");
-                        let output_str = if let Some(o) = output {
-                            if o.is_string() { o.as_str().unwrap().to_string() }
-                            else if let Some(content) = o.get("content").and_then(|v| v.as_str()) { content.to_string() }
-                            else { o.to_string() }
-                        } else { "".to_string() };
-
-                        let name = call_id_to_name.get(call_id).cloned().unwrap_or_else(|| {
-                            // Fallback: if unknown and we see function_call_output, it's likely "shell" in this context
-                            tracing::warn!("Unknown tool name for call_id {}, defaulting to 'shell'", call_id);
-                            "shell".to_string()
-                        });
-
-                        messages.push(json!({
-                            "role": "tool",
-                            "tool_call_id": call_id,
-                            "name": name,
-                            "content": output_str
-                        }));
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        if let Some(obj) = body.as_object_mut() {
-            obj.insert("messages".to_string(), json!(messages));
-        }
-    } else if let Some(prompt_val) = body.get("prompt") {
-        // Legacy OpenAI Style: prompt -> Chat
+    // 1. Legacy OpenAI Style: prompt -> Chat
+    if let Some(prompt_val) = body.get("prompt") {
         let prompt_str = match prompt_val {
             Value::String(s) => s.clone(),
             Value::Array(arr) => arr.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join("\n"),
@@ -323,10 +198,7 @@ pub async fn handle_completions(
         }
     }
 
-    // 2. Reuse handle_chat_completions logic (wrapping with custom handler or direct call)
-    // Actually, due to SSE handling differences (Codex uses different event format), we replicate the loop here or abstract it.
-    // For now, let's replicate the core loop but with Codex specific SSE mapping.
-
+    // 2. 复用 Chat 请求转换，流式输出为 text_completion 格式
     let mut openai_req: OpenAIRequest = serde_json::from_value(body.clone())
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)))?;
 
@@ -341,6 +213,22 @@ pub async fn handle_completions(
         });
     }
 
+    // 客户端 Key 模型权限
+    if !client.allows_model(&openai_req.model) {
+        return Ok(client_error_response(
+            ApiProtocol::Openai,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", openai_req.model),
+        ));
+    }
+
+    // 会话标识 (会话亲和)：x-session-id > user 字段 > 首条用户消息哈希
+    let session_id = crate::proxy::common::session::resolve_session_id(
+        &headers,
+        openai_req.user.as_deref(),
+        openai_req.messages.iter().find(|m| m.role == "user"),
+    );
+
     let upstream = state.upstream.clone();
     let token_manager = state.token_manager;
     let pool_size = token_manager.len();
@@ -357,7 +245,7 @@ pub async fn handle_completions(
         );
         let config = crate::proxy::mappers::common_utils::resolve_request_config(&openai_req.model, &mapped_model);
 
-        let (access_token, project_id, email) = match token_manager.get_token(&config.request_type, false).await {
+        let SelectedToken { account_id, access_token, project_id, email, permit } = match token_manager.get_token_in_group(&config.request_type, &config.final_model, false, session_id.as_deref(), client.group()).await {
             Ok(t) => t,
             Err(e) => return Err((StatusCode::SERVICE_UNAVAILABLE, format!("Token error: {}", e))),
         };
@@ -369,6 +257,7 @@ pub async fn handle_completions(
         let method = if list_response { "streamGenerateContent" } else { "generateContent" };
         let query_string = if list_response { Some("alt=sse") } else { None };
 
+        let started_at = std::time::Instant::now();
         let response = match upstream.call_v1_internal(method, &access_token, gemini_body, query_string).await {
             Ok(r) => r,
             Err(e) => {
@@ -379,20 +268,16 @@ pub async fn handle_completions(
 
         let status = response.status();
         if status.is_success() {
+            token_manager.mark_success(&account_id, started_at.elapsed().as_millis() as u64);
+            let usage = UsageContext::new(&state.usage, &client, &account_id, &email, ApiProtocol::Openai, &openai_req.model, &config.final_model);
+
             if list_response {
                 use axum::response::Response;
                 use axum::body::Body;
 
-                let gemini_stream = response.bytes_stream();
-                let body = if is_codex_style {
-                    use crate::proxy::mappers::openai::streaming::create_codex_sse_stream;
-                    let s = create_codex_sse_stream(Box::pin(gemini_stream), openai_req.model.clone());
-                    Body::from_stream(s)
-                } else {
-                    use crate::proxy::mappers::openai::streaming::create_legacy_sse_stream;
-                    let s = create_legacy_sse_stream(Box::pin(gemini_stream), openai_req.model.clone());
-                    Body::from_stream(s)
-                };
+                let gemini_stream = permit.attach(usage.tap(response.bytes_stream()));
+                use crate::proxy::mappers::openai::streaming::create_legacy_sse_stream;
+                let body = Body::from_stream(create_legacy_sse_stream(Box::pin(gemini_stream), openai_req.model.clone(), openai_req.include_usage()));
 
                 return Ok(Response::builder()
                     .header("Content-Type", "text/event-stream")
@@ -405,6 +290,7 @@ pub async fn handle_completions(
 
             let gemini_resp: Value = response.json().await
                 .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Parse error: {}", e)))?;
+            usage.record_response(&gemini_resp);
 
             let chat_resp = transform_openai_response(&gemini_resp);
             
@@ -421,13 +307,16 @@ pub async fn handle_completions(
                 })
             }).collect::<Vec<_>>();
 
-            let legacy_resp = json!({
+            let mut legacy_resp = json!({
                 "id": chat_resp.id,
                 "object": "text_completion",
                 "created": chat_resp.created,
                 "model": chat_resp.model,
                 "choices": choices
             });
+            if let Some(usage) = &chat_resp.usage {
+                legacy_resp["usage"] = json!(usage);
+            }
 
             return Ok(axum::Json(legacy_resp).into_response());
         }
@@ -437,7 +326,13 @@ pub async fn handle_completions(
         let error_text = response.text().await.unwrap_or_default();
         last_error = format!("HTTP {}: {}", status_code, error_text);
 
-        if status_code == 429 || status_code == 403 || status_code == 401 {
+        if status_code == 429 {
+            let retry_delay = crate::proxy::upstream::retry::parse_retry_delay(&error_text);
+            token_manager.mark_rate_limited(&account_id, &config.final_model, retry_delay);
+            continue;
+        }
+        if status_code == 403 || status_code == 401 {
+            token_manager.mark_auth_failure(&account_id, status_code);
             continue;
         }
         return Err((status, error_text));
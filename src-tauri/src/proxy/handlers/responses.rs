@@ -0,0 +1,240 @@
+// OpenAI Responses API Handler (/v1/responses)
+use axum::{
+    body::Body,
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::proxy::common::response_store::{turn_contents, StoredResponse};
+use crate::proxy::common::usage::UsageContext;
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::mappers::responses::{
+    create_responses_sse_stream, transform_responses_request, transform_responses_response, OutputBuilder,
+    ResponseContext, ResponsesRequest,
+};
+use crate::proxy::middleware::auth::{client_error_response, ClientIdentity};
+use crate::proxy::server::AppState;
+use crate::proxy::token_manager::SelectedToken;
+
+const MAX_RETRY_ATTEMPTS: usize = 3;
+
+pub async fn handle_responses(
+    State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let request: ResponsesRequest = match serde_json::from_value(body) {
+        Ok(r) => r,
+        Err(e) => {
+            return client_error_response(
+                ApiProtocol::Openai,
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid request: {}", e),
+            )
+        }
+    };
+
+    debug!("Received Responses request for model: {}", request.model);
+
+    // 客户端 Key 模型权限
+    if !client.allows_model(&request.model) {
+        return client_error_response(
+            ApiProtocol::Openai,
+            StatusCode::FORBIDDEN,
+            &format!("Model {} is not allowed for this API key", request.model),
+        );
+    }
+
+    // 续接上一轮：沿链拼回已存储的 contents (仅限同一客户端 Key 创建的响应)
+    let (previous, history) = match &request.previous_response_id {
+        Some(id) => {
+            let Some(p) = state.response_store.get(id).await.filter(|p| p.visible_to(client.key_id())) else {
+                return client_error_response(
+                    ApiProtocol::Openai,
+                    StatusCode::BAD_REQUEST,
+                    &format!("Previous response with id '{}' not found.", id),
+                );
+            };
+            let Some(history) = state.response_store.conversation(&p).await else {
+                return client_error_response(
+                    ApiProtocol::Openai,
+                    StatusCode::BAD_REQUEST,
+                    &format!("Conversation history for previous response '{}' is no longer available.", id),
+                );
+            };
+            (Some(p), history)
+        }
+        None => (None, Vec::new()),
+    };
+
+    // 会话标识 (会话亲和)：x-session-id > user 字段 > 续接的上一轮会话 > 首条用户输入哈希
+    let first_input = request.first_user_input();
+    let resolved = crate::proxy::common::session::resolve_session_id(
+        &headers,
+        request.user.as_deref(),
+        first_input.as_ref(),
+    );
+    let session_id = match (resolved, previous.as_ref().and_then(|p| p.session_id.clone())) {
+        (Some(id), _) if !id.starts_with("hash:") => Some(id),
+        (resolved, inherited) => inherited.or(resolved),
+    };
+
+    let upstream = state.upstream.clone();
+    let token_manager = state.token_manager;
+    let pool_size = token_manager.len();
+    let max_attempts = MAX_RETRY_ATTEMPTS.min(pool_size).max(1);
+
+    let mut last_error = String::new();
+
+    for attempt in 0..max_attempts {
+        let mapped_model = crate::proxy::common::model_mapping::resolve_model_route(
+            &request.model,
+            &*state.custom_mapping.read().await,
+            &*state.openai_mapping.read().await,
+            &*state.anthropic_mapping.read().await,
+        );
+        let config = crate::proxy::mappers::common_utils::resolve_request_config(&request.model, &mapped_model);
+
+        let SelectedToken { account_id, access_token, project_id, email, permit } = match token_manager.get_token_in_group(&config.request_type, &config.final_model, false, session_id.as_deref(), client.group()).await {
+            Ok(t) => t,
+            Err(e) => return openai_error(StatusCode::SERVICE_UNAVAILABLE, &format!("Token error: {}", e)),
+        };
+
+        tracing::info!("Using account: {} for responses request (type: {})", email, config.request_type);
+
+        let gemini_body = transform_responses_request(&request, &history, &project_id, &mapped_model);
+        let input_contents = gemini_body["request"]["contents"].as_array().cloned().unwrap_or_default();
+        let method = if request.stream { "streamGenerateContent" } else { "generateContent" };
+        let query_string = if request.stream { Some("alt=sse") } else { None };
+
+        let started_at = std::time::Instant::now();
+        let response = match upstream.call_v1_internal(method, &access_token, gemini_body, query_string).await {
+            Ok(r) => r,
+            Err(e) => {
+                last_error = e.clone();
+                tracing::warn!("Responses request failed on attempt {}/{}: {}", attempt + 1, max_attempts, e);
+                continue;
+            }
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            token_manager.mark_success(&account_id, started_at.elapsed().as_millis() as u64);
+            let usage = UsageContext::new(&state.usage, &client, &account_id, &email, ApiProtocol::Openai, &request.model, &config.final_model);
+            let ctx = ResponseContext::new(&request);
+            let mut builder = OutputBuilder::new(ctx);
+            if request.should_store() {
+                let store = state.response_store.clone();
+                let owner = client.key_id().map(String::from);
+                let session_id = session_id.clone();
+                let previous_response_id = request.previous_response_id.clone();
+                let turn_input = turn_contents(&history, input_contents);
+                builder = builder.on_finish(Box::new(move |response, model_content| {
+                    let Some(id) = response["id"].as_str().map(String::from) else { return };
+                    let mut contents = turn_input;
+                    contents.extend(model_content);
+                    store.put(id, StoredResponse {
+                        owner,
+                        session_id,
+                        created_at: response["created_at"].as_i64().unwrap_or_default(),
+                        previous_response_id,
+                        response: response.clone(),
+                        contents,
+                    });
+                }));
+            }
+
+            if request.stream {
+                let gemini_stream = permit.attach(usage.tap(response.bytes_stream()));
+                let stream = create_responses_sse_stream(Box::pin(gemini_stream), builder);
+                return Response::builder()
+                    .header("Content-Type", "text/event-stream")
+                    .header("Cache-Control", "no-cache")
+                    .header("Connection", "keep-alive")
+                    .body(Body::from_stream(stream))
+                    .unwrap();
+            }
+
+            let gemini_resp: Value = match response.json().await {
+                Ok(v) => v,
+                Err(e) => return openai_error(StatusCode::BAD_GATEWAY, &format!("Parse error: {}", e)),
+            };
+            usage.record_response(&gemini_resp);
+
+            return Json(transform_responses_response(&gemini_resp, builder)).into_response();
+        }
+
+        let status_code = status.as_u16();
+        let error_text = response.text().await.unwrap_or_default();
+        last_error = format!("HTTP {}: {}", status_code, error_text);
+
+        if status_code == 429 {
+            let retry_delay = crate::proxy::upstream::retry::parse_retry_delay(&error_text);
+            token_manager.mark_rate_limited(&account_id, &config.final_model, retry_delay);
+            if error_text.contains("QUOTA_EXHAUSTED") {
+                error!("Responses quota exhausted (429) on attempt {}/{}, stopping to protect pool.", attempt + 1, max_attempts);
+                return openai_error(status, &error_text);
+            }
+            tracing::warn!("Responses upstream 429 on attempt {}/{}, rotating account", attempt + 1, max_attempts);
+            continue;
+        }
+
+        if status_code == 403 || status_code == 401 {
+            token_manager.mark_auth_failure(&account_id, status_code);
+            tracing::warn!("Responses upstream {} on attempt {}/{}, rotating account", status_code, attempt + 1, max_attempts);
+            continue;
+        }
+
+        error!("Responses upstream non-retryable error {}: {}", status_code, error_text);
+        return openai_error(status, &error_text);
+    }
+
+    openai_error(StatusCode::TOO_MANY_REQUESTS, &format!("All accounts exhausted. Last error: {}", last_error))
+}
+
+/// 查询已存储的响应 (GET /v1/responses/:id)
+pub async fn handle_get_response(
+    State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.response_store.get(&id).await.filter(|p| p.visible_to(client.key_id())) {
+        Some(stored) => Json(stored.response.clone()).into_response(),
+        None => response_not_found(&id),
+    }
+}
+
+/// 删除已存储的响应 (DELETE /v1/responses/:id)
+pub async fn handle_delete_response(
+    State(state): State<AppState>,
+    Extension(client): Extension<ClientIdentity>,
+    Path(id): Path<String>,
+) -> Response {
+    let visible = state
+        .response_store
+        .get(&id)
+        .await
+        .is_some_and(|p| p.visible_to(client.key_id()));
+    if !visible || !state.response_store.delete(&id).await {
+        return response_not_found(&id);
+    }
+    Json(json!({ "id": id, "object": "response", "deleted": true })).into_response()
+}
+
+/// 以 OpenAI 错误格式返回 (上游错误保留原状态码，并以上游响应文本作为 message)
+fn openai_error(status: StatusCode, message: &str) -> Response {
+    client_error_response(ApiProtocol::Openai, status, message)
+}
+
+fn response_not_found(id: &str) -> Response {
+    client_error_response(
+        ApiProtocol::Openai,
+        StatusCode::NOT_FOUND,
+        &format!("Response with id '{}' not found.", id),
+    )
+}
@@ -3,6 +3,8 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::proxy::admin::usage::TokenUsage;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIRequest {
     pub model: String,
@@ -12,6 +14,8 @@ pub struct OpenAIRequest {
     pub prompt: Option<String>,
     #[serde(default)]
     pub stream: bool,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
     #[serde(rename = "max_tokens")]
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
@@ -28,6 +32,22 @@ pub struct OpenAIRequest {
     // Codex proprietary fields
     pub instructions: Option<String>,
     pub input: Option<Value>,
+    /// 终端用户标识，用于会话亲和
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+impl OpenAIRequest {
+    /// 流式请求是否要求在末尾附带用量块 (stream_options.include_usage)
+    pub fn include_usage(&self) -> bool {
+        self.stream_options.as_ref().is_some_and(|o| o.include_usage)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,6 +115,8 @@ pub struct OpenAIResponse {
     pub created: u64,
     pub model: String,
     pub choices: Vec<Choice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,3 +125,38 @@ pub struct Choice {
     pub message: OpenAIMessage,
     pub finish_reason: Option<String>,
 }
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenAIUsage {
+    pub prompt_tokens: u64,
+    /// 含思考 Token
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub prompt_tokens_details: PromptTokensDetails,
+    pub completion_tokens_details: CompletionTokensDetails,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    pub cached_tokens: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletionTokensDetails {
+    pub reasoning_tokens: u64,
+}
+
+impl OpenAIUsage {
+    /// 由 Gemini usageMetadata 转换；响应中没有 usageMetadata 时返回 None
+    pub fn from_gemini(value: &Value) -> Option<Self> {
+        let usage = TokenUsage::from_gemini(value)?;
+        let completion_tokens = usage.output_tokens + usage.thinking_tokens;
+        Some(Self {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens,
+            total_tokens: usage.input_tokens + completion_tokens,
+            prompt_tokens_details: PromptTokensDetails { cached_tokens: usage.cached_tokens },
+            completion_tokens_details: CompletionTokensDetails { reasoning_tokens: usage.thinking_tokens },
+        })
+    }
+}
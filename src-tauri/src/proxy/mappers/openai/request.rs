@@ -272,7 +272,7 @@ pub fn transform_openai_request(request: &OpenAIRequest, project_id: &str, mappe
     })
 }
 
-fn map_json_schema_to_gemini(value: &mut Value) {
+pub fn map_json_schema_to_gemini(value: &mut Value) {
     if let Some(obj) = value.as_object_mut() {
         let allowed_keys = ["type", "description", "properties", "required", "items", "enum", "format", "nullable"];
         obj.retain(|k, _| allowed_keys.contains(&k.as_str()));
@@ -318,6 +318,7 @@ mod tests {
                 name: None,
             }],
             stream: false,
+            stream_options: None,
             max_tokens: None,
             temperature: None,
             top_p: None,
@@ -329,6 +330,7 @@ mod tests {
             instructions: None,
             input: None,
             prompt: None,
+            user: None,
         };
 
         let result = transform_openai_request(&req, "test-v", "gemini-1.5-flash");
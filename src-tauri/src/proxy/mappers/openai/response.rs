@@ -73,6 +73,8 @@ pub fn transform_openai_response(gemini_response: &Value) -> OpenAIResponse {
             _ => "stop",
         })
         .unwrap_or("stop");
+    // 以工具调用结束时返回 tool_calls
+    let finish_reason = if finish_reason == "stop" && !tool_calls.is_empty() { "tool_calls" } else { finish_reason };
 
     OpenAIResponse {
         id: raw.get("responseId").and_then(|v| v.as_str()).unwrap_or("resp_unknown").to_string(),
@@ -90,6 +92,7 @@ pub fn transform_openai_response(gemini_response: &Value) -> OpenAIResponse {
             },
             finish_reason: Some(finish_reason.to_string()),
         }],
+        usage: OpenAIUsage::from_gemini(raw),
     }
 }
 
@@ -108,7 +111,8 @@ mod tests {
                 "finishReason": "STOP"
             }],
             "modelVersion": "gemini-2.5-pro",
-            "responseId": "resp_123"
+            "responseId": "resp_123",
+            "usageMetadata": {"promptTokenCount": 7, "candidatesTokenCount": 3, "thoughtsTokenCount": 2}
         });
 
         let result = transform_openai_response(&gemini_resp);
@@ -120,5 +124,31 @@ mod tests {
         };
         assert_eq!(content, "Hello!");
         assert_eq!(result.choices[0].finish_reason, Some("stop".to_string()));
+
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 7);
+        assert_eq!(usage.completion_tokens, 5);
+        assert_eq!(usage.total_tokens, 12);
+        assert_eq!(usage.completion_tokens_details.reasoning_tokens, 2);
+        assert_eq!(usage.prompt_tokens_details.cached_tokens, 0);
+    }
+
+    #[test]
+    fn test_function_call_finish_reason() {
+        let gemini_resp = json!({
+            "candidates": [{
+                "content": {
+                    "parts": [{"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}]
+                },
+                "finishReason": "STOP"
+            }]
+        });
+
+        let result = transform_openai_response(&gemini_resp);
+        let tool_calls = result.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, "{\"city\":\"Paris\"}");
+        assert_eq!(result.choices[0].finish_reason, Some("tool_calls".to_string()));
+        assert!(result.usage.is_none());
     }
 }
@@ -0,0 +1,12 @@
+// Responses mapper 模块
+// 负责 OpenAI Responses API (/v1/responses) ↔ Gemini 协议转换
+
+pub mod models;
+pub mod request;
+pub mod response;
+pub mod streaming;
+
+pub use models::*;
+pub use request::transform_responses_request;
+pub use response::{transform_responses_response, OutputBuilder, ResponseContext};
+pub use streaming::create_responses_sse_stream;
@@ -0,0 +1,213 @@
+// OpenAI Responses API 数据模型
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesRequest {
+    pub model: String,
+    #[serde(default)]
+    pub input: ResponsesInput,
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub tools: Vec<Value>,
+    #[serde(default)]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(default)]
+    pub parallel_tool_calls: Option<bool>,
+    /// 输出格式 (text.format)
+    #[serde(default)]
+    pub text: Option<Value>,
+    #[serde(default)]
+    pub metadata: Option<Value>,
+    /// 是否存储本次响应 (默认 true)，供后续 previous_response_id 续接
+    #[serde(default)]
+    pub store: Option<bool>,
+    /// 续接的上一轮响应 ID
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+    /// 终端用户标识，用于会话亲和
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+impl ResponsesRequest {
+    /// 首条用户输入，作为会话亲和的哈希种子
+    pub fn first_user_input(&self) -> Option<Value> {
+        match &self.input {
+            ResponsesInput::Text(text) => Some(Value::String(text.clone())),
+            ResponsesInput::Items(items) => items
+                .iter()
+                .find(|item| matches!(item, InputItem::Message(m) if m.role == "user"))
+                .and_then(|item| serde_json::to_value(item).ok()),
+        }
+    }
+
+    pub fn should_store(&self) -> bool {
+        self.store.unwrap_or(true)
+    }
+
+    /// 是否声明了 Codex 内置的 local_shell 工具
+    pub fn has_local_shell(&self) -> bool {
+        self.tools
+            .iter()
+            .any(|t| t.get("type").and_then(|v| v.as_str()) == Some("local_shell"))
+    }
+}
+
+/// input 可以是纯文本，也可以是输入项数组
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponsesInput {
+    Text(String),
+    Items(Vec<InputItem>),
+}
+
+impl Default for ResponsesInput {
+    fn default() -> Self {
+        ResponsesInput::Items(Vec::new())
+    }
+}
+
+/// tool_choice: "auto" / "none" / "required"，或 {"type": "function", "name": ...}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Named {
+        r#type: String,
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputItem {
+    Message(MessageItem),
+    FunctionCall(FunctionCallItem),
+    FunctionCallOutput(FunctionCallOutputItem),
+    Reasoning(ReasoningItem),
+    LocalShellCall(LocalShellCallItem),
+    /// 不支持的输入项 (如托管工具的调用记录)，转换时忽略
+    Unknown,
+}
+
+// 简写形式的消息 ({"role": "user", "content": "..."}) 没有 type 字段，需手动分派
+impl<'de> Deserialize<'de> for InputItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let value = Value::deserialize(deserializer)?;
+        let item_type = value.get("type").and_then(|v| v.as_str()).unwrap_or("message");
+        let item = match item_type {
+            "message" => InputItem::Message(serde_json::from_value(value).map_err(D::Error::custom)?),
+            "function_call" => InputItem::FunctionCall(serde_json::from_value(value).map_err(D::Error::custom)?),
+            "function_call_output" => InputItem::FunctionCallOutput(serde_json::from_value(value).map_err(D::Error::custom)?),
+            "reasoning" => InputItem::Reasoning(serde_json::from_value(value).map_err(D::Error::custom)?),
+            "local_shell_call" => InputItem::LocalShellCall(serde_json::from_value(value).map_err(D::Error::custom)?),
+            _ => InputItem::Unknown,
+        };
+        Ok(item)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageItem {
+    pub role: String,
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    InputImage {
+        #[serde(default)]
+        image_url: Option<String>,
+    },
+    InputFile {
+        #[serde(default)]
+        file_data: Option<String>,
+        #[serde(default)]
+        filename: Option<String>,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub call_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCallOutputItem {
+    pub call_id: String,
+    /// 字符串，或 input_text 等内容块数组
+    #[serde(default)]
+    pub output: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub summary: Vec<ReasoningSummary>,
+    /// Gemini thoughtSignature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningSummary {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalShellCallItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub call_id: String,
+    pub action: LocalShellAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalShellAction {
+    #[serde(default)]
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_directory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
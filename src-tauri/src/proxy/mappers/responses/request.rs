@@ -0,0 +1,414 @@
+// Responses → Gemini 请求转换
+use super::models::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::proxy::mappers::openai::map_json_schema_to_gemini;
+use crate::proxy::mappers::openai::streaming::get_thought_signature;
+
+/// local_shell 工具在 Gemini 侧的函数名
+pub const LOCAL_SHELL_FUNCTION: &str = "shell";
+
+/// history 为 previous_response_id 对应的已存储 Gemini contents (无续接时为空)
+pub fn transform_responses_request(request: &ResponsesRequest, history: &[Value], project_id: &str, mapped_model: &str) -> Value {
+    let config = crate::proxy::mappers::common_utils::resolve_request_config(&request.model, mapped_model);
+
+    // 1. 系统指令：instructions + system / developer 角色消息
+    let mut system_instructions: Vec<String> = request
+        .instructions
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .cloned()
+        .collect();
+
+    let mut contents = ContentsBuilder { contents: history.to_vec(), pending_signature: None };
+    match &request.input {
+        ResponsesInput::Text(text) => contents.push("user", json!({ "text": text })),
+        ResponsesInput::Items(items) => {
+            // 预扫描 call_id -> 函数名 (含历史轮次)，function_call_output 需要带上函数名
+            let history_calls = history
+                .iter()
+                .filter_map(|c| c["parts"].as_array())
+                .flatten()
+                .filter_map(|p| Some((p["functionCall"]["id"].as_str()?, p["functionCall"]["name"].as_str()?)));
+            let call_names: HashMap<&str, &str> = history_calls
+                .chain(items.iter().filter_map(|item| match item {
+                    InputItem::FunctionCall(call) => Some((call.call_id.as_str(), call.name.as_str())),
+                    InputItem::LocalShellCall(call) => Some((call.call_id.as_str(), LOCAL_SHELL_FUNCTION)),
+                    _ => None,
+                }))
+                .collect();
+
+            for item in items {
+                match item {
+                    InputItem::Message(message) if message.role == "system" || message.role == "developer" => {
+                        let text = message_text(&message.content);
+                        if !text.trim().is_empty() {
+                            system_instructions.push(text);
+                        }
+                    }
+                    InputItem::Message(message) => {
+                        let role = if message.role == "assistant" { "model" } else { "user" };
+                        for part in message_parts(&message.content) {
+                            contents.push(role, part);
+                        }
+                    }
+                    InputItem::Reasoning(reasoning) => {
+                        let summary = reasoning.summary.iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join("\n");
+                        if !summary.is_empty() {
+                            contents.push("model", json!({ "text": summary, "thought": true }));
+                        }
+                        // 签名挂到紧随其后的函数调用上 (Gemini 3 工具调用必需)
+                        if let Some(sig) = reasoning.encrypted_content.as_ref().filter(|s| !s.is_empty()) {
+                            contents.pending_signature = Some(sig.clone());
+                        }
+                    }
+                    InputItem::FunctionCall(call) => {
+                        let args = serde_json::from_str::<Value>(&call.arguments).unwrap_or_else(|_| json!({}));
+                        contents.push_call(call.name.as_str(), &call.call_id, args);
+                    }
+                    InputItem::LocalShellCall(call) => {
+                        let mut args = json!({ "command": call.action.command });
+                        if let Some(dir) = &call.action.working_directory {
+                            args["workdir"] = json!(dir);
+                        }
+                        if let Some(timeout) = call.action.timeout_ms {
+                            args["timeout_ms"] = json!(timeout);
+                        }
+                        contents.push_call(LOCAL_SHELL_FUNCTION, &call.call_id, args);
+                    }
+                    InputItem::FunctionCallOutput(output) => {
+                        let name = call_names.get(output.call_id.as_str()).copied().unwrap_or_else(|| {
+                            tracing::warn!("[Responses] 未找到 call_id {} 对应的函数调用", output.call_id);
+                            output.call_id.as_str()
+                        });
+                        contents.push("user", json!({
+                            "functionResponse": {
+                                "name": name,
+                                "id": output.call_id,
+                                "response": { "result": output_text(&output.output) }
+                            }
+                        }));
+                    }
+                    InputItem::Unknown => {}
+                }
+            }
+        }
+    }
+
+    let mut contents = contents.contents;
+    if contents.is_empty() {
+        contents.push(json!({ "role": "user", "parts": [{ "text": " " }] }));
+    }
+
+    // 2. 生成参数
+    let mut gen_config = json!({
+        "maxOutputTokens": request.max_output_tokens.unwrap_or(64000),
+        "temperature": request.temperature.unwrap_or(1.0),
+        "topP": request.top_p.unwrap_or(1.0),
+    });
+    let format_type = request
+        .text
+        .as_ref()
+        .and_then(|t| t.get("format"))
+        .and_then(|f| f.get("type"))
+        .and_then(|t| t.as_str());
+    if matches!(format_type, Some("json_object") | Some("json_schema")) {
+        gen_config["responseMimeType"] = json!("application/json");
+    }
+
+    let mut inner_request = json!({
+        "contents": contents,
+        "generationConfig": gen_config,
+        "safetySettings": [
+            { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
+            { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
+            { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "OFF" },
+            { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "OFF" },
+            { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" },
+        ]
+    });
+
+    // 3. 工具声明与 tool_choice
+    let (function_declarations, wants_search) = build_function_declarations(&request.tools);
+    if !function_declarations.is_empty() {
+        inner_request["tools"] = json!([{ "functionDeclarations": function_declarations }]);
+        if let Some(choice) = &request.tool_choice {
+            inner_request["toolConfig"] = json!({ "functionCallingConfig": function_calling_config(choice) });
+        }
+    }
+
+    if !system_instructions.is_empty() {
+        inner_request["systemInstruction"] = json!({ "parts": [{ "text": system_instructions.join("\n\n") }] });
+    }
+
+    if config.inject_google_search || wants_search {
+        crate::proxy::mappers::common_utils::inject_google_search_tool(&mut inner_request);
+    }
+
+    if let Some(image_config) = config.image_config {
+        if let Some(obj) = inner_request.as_object_mut() {
+            obj.remove("tools");
+            obj.remove("toolConfig");
+            obj.remove("systemInstruction");
+            let gen_config = obj.entry("generationConfig").or_insert_with(|| json!({}));
+            if let Some(gen_obj) = gen_config.as_object_mut() {
+                gen_obj.remove("responseMimeType");
+                gen_obj.insert("imageConfig".to_string(), image_config);
+            }
+        }
+    }
+
+    json!({
+        "project": project_id,
+        "requestId": format!("openai-{}", uuid::Uuid::new_v4()),
+        "request": inner_request,
+        "model": config.final_model,
+        "userAgent": "antigravity",
+        "requestType": config.request_type
+    })
+}
+
+/// 按角色合并相邻 content (并行工具调用的多个 functionResponse 必须位于同一条 content)
+#[derive(Default)]
+struct ContentsBuilder {
+    contents: Vec<Value>,
+    /// 上一个 reasoning 项携带、尚未使用的 thoughtSignature
+    pending_signature: Option<String>,
+}
+
+impl ContentsBuilder {
+    fn push(&mut self, role: &str, part: Value) {
+        if let Some(last) = self.contents.last_mut().filter(|c| c["role"] == role) {
+            if let Some(parts) = last["parts"].as_array_mut() {
+                parts.push(part);
+                return;
+            }
+        }
+        self.contents.push(json!({ "role": role, "parts": [part] }));
+    }
+
+    fn push_call(&mut self, name: &str, call_id: &str, args: Value) {
+        let mut part = json!({ "functionCall": { "name": name, "args": args, "id": call_id } });
+        // 客户端未回传 reasoning 项时，与 Chat 接口一致回退到全局签名
+        let is_first_call = !self
+            .contents
+            .last()
+            .filter(|c| c["role"] == "model")
+            .and_then(|c| c["parts"].as_array())
+            .is_some_and(|parts| parts.iter().any(|p| p.get("functionCall").is_some()));
+        let signature = self
+            .pending_signature
+            .take()
+            .or_else(|| if is_first_call { get_thought_signature() } else { None });
+        if let Some(sig) = signature {
+            part["thoughtSignature"] = json!(sig);
+        }
+        self.push("model", part);
+    }
+}
+
+fn message_text(content: &MessageContent) -> String {
+    match content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::Parts(parts) => parts
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::InputText { text } | ContentPart::OutputText { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// data URL 拆分为 (mimeType, base64)
+fn split_data_url(url: &str) -> Option<(&str, &str)> {
+    let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+    Some((meta.split(';').next().unwrap_or("application/octet-stream"), data))
+}
+
+fn message_parts(content: &MessageContent) -> Vec<Value> {
+    let parts = match content {
+        MessageContent::Text(text) => return vec![json!({ "text": text })],
+        MessageContent::Parts(parts) => parts,
+    };
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::InputText { text } | ContentPart::OutputText { text } => Some(json!({ "text": text })),
+            ContentPart::Refusal { refusal } => Some(json!({ "text": refusal })),
+            ContentPart::InputImage { image_url: Some(url) } => {
+                if let Some((mime_type, data)) = split_data_url(url) {
+                    Some(json!({ "inlineData": { "mimeType": mime_type, "data": data } }))
+                } else if url.starts_with("http") {
+                    Some(json!({ "fileData": { "fileUri": url, "mimeType": "image/jpeg" } }))
+                } else {
+                    None
+                }
+            }
+            ContentPart::InputFile { file_data: Some(data), filename } => {
+                let (mime_type, data) = split_data_url(data).unwrap_or(("application/pdf", data));
+                tracing::debug!("[Responses] 附带文件 {:?} ({})", filename, mime_type);
+                Some(json!({ "inlineData": { "mimeType": mime_type, "data": data } }))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// function_call_output 的 output 可能是字符串或内容块数组
+fn output_text(output: &Value) -> String {
+    match output {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// 转换工具声明，返回 (functionDeclarations, 是否请求了 web_search)
+fn build_function_declarations(tools: &[Value]) -> (Vec<Value>, bool) {
+    let mut declarations = Vec::new();
+    let mut wants_search = false;
+    for tool in tools {
+        match tool.get("type").and_then(|t| t.as_str()).unwrap_or("function") {
+            "function" => {
+                let Some(name) = tool.get("name").and_then(|n| n.as_str()) else { continue };
+                let mut parameters = tool
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "type": "object", "properties": {} }));
+                crate::proxy::common::json_schema::clean_json_schema(&mut parameters);
+                if let Some(obj) = parameters.as_object_mut() {
+                    obj.entry("type").or_insert_with(|| json!("OBJECT"));
+                }
+                map_json_schema_to_gemini(&mut parameters);
+
+                let mut declaration = json!({ "name": name, "parameters": parameters });
+                if let Some(description) = tool.get("description") {
+                    declaration["description"] = description.clone();
+                }
+                declarations.push(declaration);
+            }
+            "local_shell" => declarations.push(json!({
+                "name": LOCAL_SHELL_FUNCTION,
+                "description": "Runs a shell command and returns its output.",
+                "parameters": {
+                    "type": "OBJECT",
+                    "properties": {
+                        "command": { "type": "ARRAY", "items": { "type": "STRING" }, "description": "The command and its arguments." },
+                        "workdir": { "type": "STRING", "description": "The working directory to execute the command in." },
+                        "timeout_ms": { "type": "NUMBER", "description": "The timeout for the command in milliseconds." }
+                    },
+                    "required": ["command"]
+                }
+            })),
+            "web_search" | "web_search_preview" => wants_search = true,
+            other => tracing::warn!("[Responses] 不支持的工具类型 {}，已忽略", other),
+        }
+    }
+    (declarations, wants_search)
+}
+
+fn function_calling_config(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Mode(mode) => match mode.as_str() {
+            "none" => json!({ "mode": "NONE" }),
+            "required" => json!({ "mode": "ANY" }),
+            _ => json!({ "mode": "AUTO" }),
+        },
+        ToolChoice::Named { r#type, name } => match (r#type.as_str(), name) {
+            ("function", Some(name)) => json!({ "mode": "ANY", "allowedFunctionNames": [name] }),
+            ("local_shell", _) => json!({ "mode": "ANY", "allowedFunctionNames": [LOCAL_SHELL_FUNCTION] }),
+            _ => json!({ "mode": "AUTO" }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: Value) -> ResponsesRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_string_input_without_instructions() {
+        let req = parse(json!({ "model": "gpt-5", "input": "Hello", "max_output_tokens": 256 }));
+        let body = transform_responses_request(&req, &[], "p", "gemini-2.5-pro");
+        let inner = &body["request"];
+        assert_eq!(inner["contents"][0]["role"], "user");
+        assert_eq!(inner["contents"][0]["parts"][0]["text"], "Hello");
+        assert_eq!(inner["generationConfig"]["maxOutputTokens"], 256);
+        assert!(inner.get("systemInstruction").is_none());
+    }
+
+    #[test]
+    fn test_typed_items_round_trip_tool_calls() {
+        let req = parse(json!({
+            "model": "gpt-5",
+            "instructions": "Be brief.",
+            "input": [
+                { "role": "developer", "content": "Use tools." },
+                { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": "List files" }] },
+                { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "Need ls" }], "encrypted_content": "sig-1" },
+                { "type": "function_call", "call_id": "call_a", "name": "ls", "arguments": "{\"path\":\".\"}" },
+                { "type": "function_call", "call_id": "call_b", "name": "pwd", "arguments": "{}" },
+                { "type": "function_call_output", "call_id": "call_a", "output": "a.txt" },
+                { "type": "function_call_output", "call_id": "call_b", "output": [{ "type": "input_text", "text": "/tmp" }] },
+                { "type": "web_search_call", "id": "ws_1" }
+            ],
+            "tools": [{ "type": "function", "name": "ls", "parameters": { "type": "object", "properties": { "path": { "type": "string" } } } }],
+            "tool_choice": { "type": "function", "name": "ls" }
+        }));
+        let body = transform_responses_request(&req, &[], "p", "gemini-2.5-pro");
+        let inner = &body["request"];
+
+        assert_eq!(inner["systemInstruction"]["parts"][0]["text"], "Be brief.\n\nUse tools.");
+        let contents = inner["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+
+        let model_parts = contents[1]["parts"].as_array().unwrap();
+        assert_eq!(model_parts[0]["thought"], true);
+        assert_eq!(model_parts[1]["functionCall"]["args"]["path"], ".");
+        assert_eq!(model_parts[1]["thoughtSignature"], "sig-1");
+        assert!(model_parts[2].get("thoughtSignature").is_none());
+
+        let responses = contents[2]["parts"].as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["functionResponse"]["name"], "ls");
+        assert_eq!(responses[1]["functionResponse"]["response"]["result"], "/tmp");
+
+        assert_eq!(inner["tools"][0]["functionDeclarations"][0]["parameters"]["type"], "OBJECT");
+        assert_eq!(inner["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+        assert_eq!(inner["toolConfig"]["functionCallingConfig"]["allowedFunctionNames"][0], "ls");
+    }
+
+    #[test]
+    fn test_local_shell_tool_and_call() {
+        let req = parse(json!({
+            "model": "codex-mini",
+            "input": [
+                { "type": "local_shell_call", "call_id": "c1", "action": { "type": "exec", "command": ["ls", "-la"], "working_directory": "/tmp" } },
+                { "type": "function_call_output", "call_id": "c1", "output": "ok" }
+            ],
+            "tools": [{ "type": "local_shell" }],
+            "tool_choice": "none"
+        }));
+        let body = transform_responses_request(&req, &[], "p", "gemini-2.5-pro");
+        let inner = &body["request"];
+        let call = &inner["contents"][0]["parts"][0]["functionCall"];
+        assert_eq!(call["name"], LOCAL_SHELL_FUNCTION);
+        assert_eq!(call["args"]["command"][1], "-la");
+        assert_eq!(call["args"]["workdir"], "/tmp");
+        assert_eq!(inner["contents"][1]["parts"][0]["functionResponse"]["name"], LOCAL_SHELL_FUNCTION);
+        assert_eq!(inner["tools"][0]["functionDeclarations"][0]["name"], LOCAL_SHELL_FUNCTION);
+        assert_eq!(inner["toolConfig"]["functionCallingConfig"]["mode"], "NONE");
+    }
+}
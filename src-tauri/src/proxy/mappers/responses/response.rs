@@ -0,0 +1,559 @@
+// Gemini → Responses 输出转换
+// 流式与非流式共用 OutputBuilder：非流式只取最终的 response 对象，流式额外下发过程事件
+use super::models::*;
+use super::request::LOCAL_SHELL_FUNCTION;
+use rand::Rng;
+use serde_json::{json, Value};
+
+use crate::proxy::admin::usage::TokenUsage;
+use crate::proxy::mappers::openai::streaming::store_thought_signature;
+
+/// 生成 Responses ID (resp-<24 位字母数字>)
+pub fn generate_response_id() -> String {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let mut rng = rand::thread_rng();
+    let suffix: String = (0..24)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect();
+    format!("resp-{}", suffix)
+}
+
+fn item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, uuid::Uuid::new_v4().simple())
+}
+
+/// 响应对象中需要回显的请求参数
+#[derive(Debug, Clone)]
+pub struct ResponseContext {
+    pub id: String,
+    pub created_at: i64,
+    pub model: String,
+    instructions: Option<String>,
+    max_output_tokens: Option<u32>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    tool_choice: Value,
+    tools: Vec<Value>,
+    parallel_tool_calls: bool,
+    text: Value,
+    metadata: Value,
+    user: Option<String>,
+    previous_response_id: Option<String>,
+    store: bool,
+    /// 客户端声明了 local_shell 时，shell 调用以 local_shell_call 输出
+    local_shell: bool,
+}
+
+impl ResponseContext {
+    pub fn new(request: &ResponsesRequest) -> Self {
+        Self {
+            id: generate_response_id(),
+            created_at: chrono::Utc::now().timestamp(),
+            model: request.model.clone(),
+            instructions: request.instructions.clone(),
+            max_output_tokens: request.max_output_tokens,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            tool_choice: request
+                .tool_choice
+                .as_ref()
+                .and_then(|c| serde_json::to_value(c).ok())
+                .unwrap_or_else(|| json!("auto")),
+            tools: request.tools.clone(),
+            parallel_tool_calls: request.parallel_tool_calls.unwrap_or(true),
+            text: request.text.clone().unwrap_or_else(|| json!({ "format": { "type": "text" } })),
+            metadata: request.metadata.clone().unwrap_or_else(|| json!({})),
+            user: request.user.clone(),
+            previous_response_id: request.previous_response_id.clone(),
+            store: request.should_store(),
+            local_shell: request.has_local_shell(),
+        }
+    }
+
+    /// 构造 response 对象
+    pub fn envelope(&self, status: &str, output: &[Value], usage: Option<Value>, incomplete_reason: Option<&str>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "error": null,
+            "incomplete_details": incomplete_reason.map(|reason| json!({ "reason": reason })),
+            "instructions": self.instructions,
+            "max_output_tokens": self.max_output_tokens,
+            "model": self.model,
+            "output": output,
+            "parallel_tool_calls": self.parallel_tool_calls,
+            "previous_response_id": self.previous_response_id,
+            "reasoning": { "effort": null, "summary": null },
+            "store": self.store,
+            "temperature": self.temperature.unwrap_or(1.0),
+            "text": self.text,
+            "tool_choice": self.tool_choice,
+            "tools": self.tools,
+            "top_p": self.top_p.unwrap_or(1.0),
+            "truncation": "disabled",
+            "usage": usage,
+            "user": self.user,
+            "metadata": self.metadata,
+        })
+    }
+}
+
+/// Gemini usageMetadata → Responses usage (output_tokens 包含思考 Token)
+pub fn map_usage(usage: &TokenUsage) -> Value {
+    let output_tokens = usage.output_tokens + usage.thinking_tokens;
+    json!({
+        "input_tokens": usage.input_tokens,
+        "input_tokens_details": { "cached_tokens": usage.cached_tokens },
+        "output_tokens": output_tokens,
+        "output_tokens_details": { "reasoning_tokens": usage.thinking_tokens },
+        "total_tokens": usage.input_tokens + output_tokens,
+    })
+}
+
+/// 正在输出中的消息 / 思考项
+enum OpenItem {
+    Message { id: String, text: String },
+    Reasoning { id: String, text: String },
+}
+
+/// 输出结束时的回调，参数为 (最终 response 对象, 本轮模型输出的 Gemini content)
+pub type FinishCallback = Box<dyn FnOnce(&Value, Option<Value>) + Send>;
+
+/// 将 Gemini 响应片段逐个转换为 Responses 输出项，并生成对应的流式事件
+pub struct OutputBuilder {
+    ctx: ResponseContext,
+    output: Vec<Value>,
+    open: Option<OpenItem>,
+    finish_reason: Option<String>,
+    usage: Option<TokenUsage>,
+    sequence_number: u64,
+    /// 原始 Gemini parts (相邻文本片段已合并)，用于存储后续接
+    model_parts: Vec<Value>,
+    on_finish: Option<FinishCallback>,
+}
+
+impl OutputBuilder {
+    pub fn new(ctx: ResponseContext) -> Self {
+        Self {
+            ctx,
+            output: Vec::new(),
+            open: None,
+            finish_reason: None,
+            usage: None,
+            sequence_number: 0,
+            model_parts: Vec::new(),
+            on_finish: None,
+        }
+    }
+
+    /// 输出正常结束 (completed / incomplete) 后调用，上游中断时不调用
+    pub fn on_finish(mut self, callback: FinishCallback) -> Self {
+        self.on_finish = Some(callback);
+        self
+    }
+
+    pub fn response_id(&self) -> &str {
+        &self.ctx.id
+    }
+
+    fn event(&mut self, event_type: &str, mut payload: Value) -> Value {
+        payload["type"] = json!(event_type);
+        payload["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        payload
+    }
+
+    /// response.created + response.in_progress
+    pub fn start(&mut self) -> Vec<Value> {
+        let response = self.ctx.envelope("in_progress", &[], None, None);
+        vec![
+            self.event("response.created", json!({ "response": response })),
+            self.event("response.in_progress", json!({ "response": response })),
+        ]
+    }
+
+    /// 处理一个 Gemini 响应 (流式片段或完整响应，兼容 v1internal 的 response 包装)
+    pub fn process(&mut self, gemini_response: &Value) -> Vec<Value> {
+        let raw = gemini_response.get("response").unwrap_or(gemini_response);
+        let mut events = Vec::new();
+
+        if let Some(usage) = TokenUsage::from_gemini(raw) {
+            self.usage = Some(usage);
+        }
+        let candidate = raw.get("candidates").and_then(|c| c.get(0));
+        if let Some(reason) = candidate.and_then(|c| c.get("finishReason")).and_then(|f| f.as_str()) {
+            self.finish_reason = Some(reason.to_string());
+        }
+
+        let parts = candidate
+            .and_then(|c| c.get("content"))
+            .and_then(|c| c.get("parts"))
+            .and_then(|p| p.as_array());
+        for part in parts.into_iter().flatten() {
+            let signature = part
+                .get("thoughtSignature")
+                .or_else(|| part.get("thought_signature"))
+                .and_then(|s| s.as_str())
+                .filter(|s| !s.is_empty());
+            if let Some(sig) = signature {
+                store_thought_signature(sig);
+            }
+
+            if let Some(call) = part.get("functionCall") {
+                events.extend(self.push_function_call(call, signature));
+                continue;
+            }
+            self.record_part(part);
+            if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+                if part.get("thought").and_then(|t| t.as_bool()).unwrap_or(false) {
+                    events.extend(self.push_reasoning_delta(text));
+                } else {
+                    events.extend(self.push_text_delta(text));
+                }
+            } else if let Some(img) = part.get("inlineData") {
+                let mime_type = img.get("mimeType").and_then(|v| v.as_str()).unwrap_or("image/png");
+                let data = img.get("data").and_then(|v| v.as_str()).unwrap_or("");
+                if !data.is_empty() {
+                    events.extend(self.push_text_delta(&format!("![image](data:{};base64,{})", mime_type, data)));
+                }
+            }
+        }
+        events
+    }
+
+    /// 记录模型输出的 part，流式下同类文本片段合并为一个 part
+    fn record_part(&mut self, part: &Value) {
+        let is_thought = |p: &Value| p.get("thought").and_then(|t| t.as_bool()).unwrap_or(false);
+        if let (Some(last), Some(text)) = (self.model_parts.last_mut(), part.get("text").and_then(|t| t.as_str())) {
+            let mergeable = last.get("text").is_some()
+                && last.get("thoughtSignature").is_none()
+                && is_thought(last) == is_thought(part);
+            if mergeable {
+                let merged = format!("{}{}", last["text"].as_str().unwrap_or(""), text);
+                last["text"] = json!(merged);
+                if let Some(sig) = part.get("thoughtSignature") {
+                    last["thoughtSignature"] = sig.clone();
+                }
+                return;
+            }
+        }
+        self.model_parts.push(part.clone());
+    }
+
+    fn push_text_delta(&mut self, delta: &str) -> Vec<Value> {
+        if delta.is_empty() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if !matches!(self.open, Some(OpenItem::Message { .. })) {
+            events.extend(self.close_open(None));
+            let id = item_id("msg");
+            let output_index = self.output.len();
+            events.push(self.event("response.output_item.added", json!({
+                "output_index": output_index,
+                "item": { "id": id, "type": "message", "status": "in_progress", "role": "assistant", "content": [] }
+            })));
+            events.push(self.event("response.content_part.added", json!({
+                "item_id": id,
+                "output_index": output_index,
+                "content_index": 0,
+                "part": { "type": "output_text", "text": "", "annotations": [] }
+            })));
+            self.open = Some(OpenItem::Message { id, text: String::new() });
+        }
+        let Some(OpenItem::Message { id, text }) = &mut self.open else { unreachable!() };
+        text.push_str(delta);
+        let id = id.clone();
+        let output_index = self.output.len();
+        events.push(self.event("response.output_text.delta", json!({
+            "item_id": id,
+            "output_index": output_index,
+            "content_index": 0,
+            "delta": delta
+        })));
+        events
+    }
+
+    fn push_reasoning_delta(&mut self, delta: &str) -> Vec<Value> {
+        if delta.is_empty() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if !matches!(self.open, Some(OpenItem::Reasoning { .. })) {
+            events.extend(self.close_open(None));
+            let id = item_id("rs");
+            let output_index = self.output.len();
+            events.push(self.event("response.output_item.added", json!({
+                "output_index": output_index,
+                "item": { "id": id, "type": "reasoning", "summary": [] }
+            })));
+            events.push(self.event("response.reasoning_summary_part.added", json!({
+                "item_id": id,
+                "output_index": output_index,
+                "summary_index": 0,
+                "part": { "type": "summary_text", "text": "" }
+            })));
+            self.open = Some(OpenItem::Reasoning { id, text: String::new() });
+        }
+        let Some(OpenItem::Reasoning { id, text }) = &mut self.open else { unreachable!() };
+        text.push_str(delta);
+        let id = id.clone();
+        let output_index = self.output.len();
+        events.push(self.event("response.reasoning_summary_text.delta", json!({
+            "item_id": id,
+            "output_index": output_index,
+            "summary_index": 0,
+            "delta": delta
+        })));
+        events
+    }
+
+    /// 结束当前打开的项；signature 写入思考项的 encrypted_content
+    fn close_open(&mut self, signature: Option<&str>) -> Vec<Value> {
+        let output_index = self.output.len();
+        let mut events = Vec::new();
+        match self.open.take() {
+            Some(OpenItem::Message { id, text }) => {
+                let part = json!({ "type": "output_text", "text": text, "annotations": [] });
+                events.push(self.event("response.output_text.done", json!({
+                    "item_id": id, "output_index": output_index, "content_index": 0, "text": text
+                })));
+                events.push(self.event("response.content_part.done", json!({
+                    "item_id": id, "output_index": output_index, "content_index": 0, "part": part
+                })));
+                let item = json!({ "id": id, "type": "message", "status": "completed", "role": "assistant", "content": [part] });
+                events.push(self.event("response.output_item.done", json!({ "output_index": output_index, "item": item })));
+                self.output.push(item);
+                // 签名只随函数调用回传，消息之后的签名单独成项
+                if signature.is_some() {
+                    events.extend(self.push_signature_item(signature));
+                }
+            }
+            Some(OpenItem::Reasoning { id, text }) => {
+                let part = json!({ "type": "summary_text", "text": text });
+                events.push(self.event("response.reasoning_summary_text.done", json!({
+                    "item_id": id, "output_index": output_index, "summary_index": 0, "text": text
+                })));
+                events.push(self.event("response.reasoning_summary_part.done", json!({
+                    "item_id": id, "output_index": output_index, "summary_index": 0, "part": part
+                })));
+                let mut item = json!({ "id": id, "type": "reasoning", "summary": [part] });
+                if let Some(sig) = signature {
+                    item["encrypted_content"] = json!(sig);
+                }
+                events.push(self.event("response.output_item.done", json!({ "output_index": output_index, "item": item })));
+                self.output.push(item);
+            }
+            None => {
+                if signature.is_some() {
+                    events.extend(self.push_signature_item(signature));
+                }
+            }
+        }
+        events
+    }
+
+    /// 仅携带 thoughtSignature 的 reasoning 项
+    fn push_signature_item(&mut self, signature: Option<&str>) -> Vec<Value> {
+        let output_index = self.output.len();
+        let item = json!({ "id": item_id("rs"), "type": "reasoning", "summary": [], "encrypted_content": signature });
+        let events = vec![
+            self.event("response.output_item.added", json!({ "output_index": output_index, "item": item })),
+            self.event("response.output_item.done", json!({ "output_index": output_index, "item": item })),
+        ];
+        self.output.push(item);
+        events
+    }
+
+    fn push_function_call(&mut self, call: &Value, signature: Option<&str>) -> Vec<Value> {
+        let mut events = self.close_open(signature);
+        let name = call.get("name").and_then(|v| v.as_str()).unwrap_or("unknown");
+        let args = call.get("args").cloned().unwrap_or_else(|| json!({}));
+        let call_id = call
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .unwrap_or_else(|| item_id("call"));
+        let output_index = self.output.len();
+
+        let mut part = json!({ "functionCall": { "name": name, "args": args, "id": call_id } });
+        if let Some(sig) = signature {
+            part["thoughtSignature"] = json!(sig);
+        }
+        self.model_parts.push(part);
+
+        if self.ctx.local_shell && name == LOCAL_SHELL_FUNCTION {
+            let command: Vec<String> = match args.get("command") {
+                Some(Value::Array(parts)) => parts.iter().filter_map(|v| v.as_str().map(String::from)).collect(),
+                Some(Value::String(cmd)) => vec![cmd.clone()],
+                _ => Vec::new(),
+            };
+            let item = json!({
+                "id": item_id("lsh"),
+                "type": "local_shell_call",
+                "status": "completed",
+                "call_id": call_id,
+                "action": {
+                    "type": "exec",
+                    "command": command,
+                    "working_directory": args.get("workdir"),
+                    "timeout_ms": args.get("timeout_ms"),
+                    "env": {},
+                    "user": null
+                }
+            });
+            events.push(self.event("response.output_item.added", json!({ "output_index": output_index, "item": item })));
+            events.push(self.event("response.output_item.done", json!({ "output_index": output_index, "item": item })));
+            self.output.push(item);
+            return events;
+        }
+
+        let id = item_id("fc");
+        let arguments = args.to_string();
+        events.push(self.event("response.output_item.added", json!({
+            "output_index": output_index,
+            "item": { "id": id, "type": "function_call", "status": "in_progress", "call_id": call_id, "name": name, "arguments": "" }
+        })));
+        events.push(self.event("response.function_call_arguments.delta", json!({
+            "item_id": id, "output_index": output_index, "delta": arguments
+        })));
+        events.push(self.event("response.function_call_arguments.done", json!({
+            "item_id": id, "output_index": output_index, "arguments": arguments
+        })));
+        let item = json!({ "id": id, "type": "function_call", "status": "completed", "call_id": call_id, "name": name, "arguments": arguments });
+        events.push(self.event("response.output_item.done", json!({ "output_index": output_index, "item": item })));
+        self.output.push(item);
+        events
+    }
+
+    /// 结束输出，返回 (剩余事件, 最终 response 对象)
+    pub fn finish(&mut self) -> (Vec<Value>, Value) {
+        let mut events = self.close_open(None);
+        let incomplete_reason = match self.finish_reason.as_deref() {
+            Some("MAX_TOKENS") => Some("max_output_tokens"),
+            Some("SAFETY") | Some("RECITATION") | Some("BLOCKLIST") | Some("PROHIBITED_CONTENT") => Some("content_filter"),
+            _ => None,
+        };
+        let status = if incomplete_reason.is_some() { "incomplete" } else { "completed" };
+        let usage = self.usage.as_ref().map(map_usage);
+        let response = self.ctx.envelope(status, &self.output, usage, incomplete_reason);
+        let event_type = if incomplete_reason.is_some() { "response.incomplete" } else { "response.completed" };
+        events.push(self.event(event_type, json!({ "response": response })));
+        if let Some(callback) = self.on_finish.take() {
+            let content = (!self.model_parts.is_empty())
+                .then(|| json!({ "role": "model", "parts": std::mem::take(&mut self.model_parts) }));
+            callback(&response, content);
+        }
+        (events, response)
+    }
+
+    /// 上游中断时的终止事件
+    pub fn fail(&mut self, message: &str) -> Value {
+        let mut response = self.ctx.envelope("failed", &self.output, self.usage.as_ref().map(map_usage), None);
+        response["error"] = json!({ "code": "server_error", "message": message });
+        self.event("response.failed", json!({ "response": response }))
+    }
+}
+
+/// 非流式：完整 Gemini 响应 → response 对象
+pub fn transform_responses_response(gemini_response: &Value, mut builder: OutputBuilder) -> Value {
+    builder.process(gemini_response);
+    builder.finish().1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(body: Value) -> ResponseContext {
+        ResponseContext::new(&serde_json::from_value(body).unwrap())
+    }
+
+    fn builder(body: Value) -> OutputBuilder {
+        OutputBuilder::new(context(body))
+    }
+
+    #[test]
+    fn test_non_stream_response_object() {
+        let gemini = json!({
+            "response": {
+                "candidates": [{
+                    "content": { "parts": [
+                        { "text": "Checking", "thought": true },
+                        { "functionCall": { "name": "ls", "args": { "path": "." } }, "thoughtSignature": "sig-x" },
+                        { "text": "Done." }
+                    ] },
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": { "promptTokenCount": 10, "candidatesTokenCount": 5, "thoughtsTokenCount": 3, "cachedContentTokenCount": 4 }
+            }
+        });
+        let response = transform_responses_response(&gemini, builder(json!({ "model": "gpt-5", "input": "hi" })));
+
+        assert!(response["id"].as_str().unwrap().starts_with("resp-"));
+        assert_eq!(response["object"], "response");
+        assert_eq!(response["status"], "completed");
+        let output = response["output"].as_array().unwrap();
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[0]["type"], "reasoning");
+        assert_eq!(output[0]["summary"][0]["text"], "Checking");
+        assert_eq!(output[0]["encrypted_content"], "sig-x");
+        assert_eq!(output[1]["type"], "function_call");
+        assert_eq!(output[1]["arguments"], "{\"path\":\".\"}");
+        assert_eq!(output[2]["content"][0]["text"], "Done.");
+
+        assert_eq!(response["usage"]["input_tokens"], 10);
+        assert_eq!(response["usage"]["output_tokens"], 8);
+        assert_eq!(response["usage"]["output_tokens_details"]["reasoning_tokens"], 3);
+        assert_eq!(response["usage"]["input_tokens_details"]["cached_tokens"], 4);
+        assert_eq!(response["usage"]["total_tokens"], 18);
+    }
+
+    #[test]
+    fn test_max_tokens_is_incomplete() {
+        let gemini = json!({ "candidates": [{ "content": { "parts": [{ "text": "abc" }] }, "finishReason": "MAX_TOKENS" }] });
+        let response = transform_responses_response(&gemini, builder(json!({ "model": "gpt-5", "max_output_tokens": 3 })));
+        assert_eq!(response["status"], "incomplete");
+        assert_eq!(response["incomplete_details"]["reason"], "max_output_tokens");
+        assert_eq!(response["max_output_tokens"], 3);
+    }
+
+    #[test]
+    fn test_shell_call_maps_to_local_shell_when_declared() {
+        let gemini = json!({ "candidates": [{ "content": { "parts": [
+            { "functionCall": { "name": "shell", "args": { "command": ["ls"], "workdir": "/tmp" } } }
+        ] } }] });
+        let ctx = context(json!({ "model": "codex-mini", "tools": [{ "type": "local_shell" }] }));
+        let response = transform_responses_response(&gemini, OutputBuilder::new(ctx));
+        let item = &response["output"][0];
+        assert_eq!(item["type"], "local_shell_call");
+        assert_eq!(item["action"]["command"][0], "ls");
+        assert_eq!(item["action"]["working_directory"], "/tmp");
+    }
+
+    #[test]
+    fn test_finish_callback_receives_merged_model_content() {
+        let captured = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let sink = captured.clone();
+        let mut builder = builder(json!({ "model": "gpt-5", "previous_response_id": "resp-prev" }))
+            .on_finish(Box::new(move |response, content| {
+                *sink.lock().unwrap() = Some((response["previous_response_id"].clone(), content));
+            }));
+
+        builder.process(&json!({ "candidates": [{ "content": { "parts": [{ "text": "Hel" }] } }] }));
+        builder.process(&json!({ "candidates": [{ "content": { "parts": [{ "text": "lo", "thoughtSignature": "sig-t" }] } }] }));
+        builder.process(&json!({ "candidates": [{ "content": { "parts": [{ "functionCall": { "name": "ls", "args": {} } }] } }] }));
+        let (_, response) = builder.finish();
+
+        let (previous, content) = captured.lock().unwrap().take().unwrap();
+        assert_eq!(previous, "resp-prev");
+        let parts = content.unwrap()["parts"].as_array().unwrap().clone();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0]["text"], "Hello");
+        assert_eq!(parts[0]["thoughtSignature"], "sig-t");
+        // 存储的 functionCall.id 与返回给客户端的 call_id 一致，便于续接时匹配 function_call_output
+        assert_eq!(parts[1]["functionCall"]["id"], response["output"][1]["call_id"]);
+    }
+}
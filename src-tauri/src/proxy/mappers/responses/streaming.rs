@@ -0,0 +1,113 @@
+// Responses 流式转换
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::pin::Pin;
+
+use super::response::OutputBuilder;
+
+/// 格式化为 SSE 事件 (event 行 + data 行)
+fn sse_event(event: &Value) -> Bytes {
+    let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("message");
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event_type, event))
+}
+
+/// 创建从 Gemini SSE 流到 Responses SSE 流的转换
+pub fn create_responses_sse_stream(
+    mut gemini_stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
+    mut builder: OutputBuilder,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, String>> + Send>> {
+    let mut buffer = BytesMut::new();
+
+    let stream = async_stream::stream! {
+        for event in builder.start() {
+            yield Ok::<Bytes, String>(sse_event(&event));
+        }
+
+        while let Some(item) = gemini_stream.next().await {
+            match item {
+                Ok(bytes) => {
+                    buffer.extend_from_slice(&bytes);
+                    while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                        let line_raw = buffer.split_to(pos + 1);
+                        let Ok(line) = std::str::from_utf8(&line_raw) else { continue };
+                        let Some(data) = line.trim().strip_prefix("data:") else { continue };
+                        let data = data.trim();
+                        if data.is_empty() || data == "[DONE]" {
+                            continue;
+                        }
+                        if let Ok(json) = serde_json::from_str::<Value>(data) {
+                            for event in builder.process(&json) {
+                                yield Ok::<Bytes, String>(sse_event(&event));
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("[Responses-SSE] 上游流中断 ({}): {}", builder.response_id(), e);
+                    yield Ok::<Bytes, String>(sse_event(&builder.fail(&format!("Upstream error: {}", e))));
+                    return;
+                }
+            }
+        }
+
+        let (events, _) = builder.finish();
+        for event in events {
+            yield Ok::<Bytes, String>(sse_event(&event));
+        }
+    };
+
+    Box::pin(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::mappers::responses::{ResponseContext, ResponsesRequest};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_stream_event_sequence() {
+        let request: ResponsesRequest = serde_json::from_value(json!({ "model": "gpt-5", "input": "hi", "stream": true })).unwrap();
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = vec![
+            Ok(Bytes::from("data: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Hel\"}]}}]}}\n\ndata: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"te")),
+            Ok(Bytes::from("xt\":\"lo\"}]}}]}}\n\ndata: {\"response\":{\"candidates\":[{\"content\":{\"parts\":[{\"functionCall\":{\"name\":\"ls\",\"args\":{}}}]},\"finishReason\":\"STOP\"}],\"usageMetadata\":{\"promptTokenCount\":7,\"candidatesTokenCount\":2}}}\n\n")),
+        ];
+        let builder = OutputBuilder::new(ResponseContext::new(&request));
+        let output: Vec<String> = create_responses_sse_stream(Box::pin(futures::stream::iter(chunks)), builder)
+            .map(|b| String::from_utf8(b.unwrap().to_vec()).unwrap())
+            .collect()
+            .await;
+
+        let events: Vec<Value> = output
+            .iter()
+            .map(|e| serde_json::from_str(e.split("data: ").nth(1).unwrap().trim()).unwrap())
+            .collect();
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(types, vec![
+            "response.created",
+            "response.in_progress",
+            "response.output_item.added",
+            "response.content_part.added",
+            "response.output_text.delta",
+            "response.output_text.delta",
+            "response.output_text.done",
+            "response.content_part.done",
+            "response.output_item.done",
+            "response.output_item.added",
+            "response.function_call_arguments.delta",
+            "response.function_call_arguments.done",
+            "response.output_item.done",
+            "response.completed",
+        ]);
+        assert!(output[0].starts_with("event: response.created\n"));
+        assert_eq!(events[6]["text"], "Hello");
+        assert_eq!(events[10]["delta"], "{}");
+        assert!(events.iter().enumerate().all(|(i, e)| e["sequence_number"] == i as u64));
+
+        let completed = &events[13]["response"];
+        assert_eq!(completed["status"], "completed");
+        assert_eq!(completed["output"].as_array().unwrap().len(), 2);
+        assert_eq!(completed["usage"]["total_tokens"], 9);
+    }
+}
@@ -1,79 +1,227 @@
 // Admin认证中间件
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use axum::{
-    extract::{Request, State},
+    extract::{Path, Request, State},
     middleware::Next,
     response::{Response, IntoResponse},
-    http::{StatusCode, HeaderMap},
-    Json,
+    http::{header, StatusCode, HeaderMap, HeaderValue},
+    Extension, Json,
 };
+use dashmap::DashMap;
+use rand::RngCore;
+use serde::Serialize;
 use serde_json::json;
+use subtle::ConstantTimeEq;
 
+use crate::proxy::middleware::ip_filter::ClientIp;
 use crate::proxy::server::AppState;
 
-/// Admin面板认证中间件
-///
-/// 验证方式：
-/// 1. Cookie: admin_token=<api_key>
-/// 2. Header: Authorization: Bearer <api_key>
-/// 3. Query: ?token=<api_key>
-pub async fn admin_auth_middleware(
-    State(_state): State<AppState>,
-    headers: HeaderMap,
-    request: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    // 加载配置获取API Key
-    let config = match crate::modules::config::load_app_config() {
-        Ok(cfg) => cfg,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+/// 会话 Cookie 名称
+const SESSION_COOKIE: &str = "admin_session";
+/// 会话有效期 (秒)，到期后需重新登录
+const SESSION_TTL_SECS: i64 = 12 * 3600;
+/// 同一来源在统计窗口内登录尝试 (未成功) 达到该次数后锁定
+const MAX_LOGIN_FAILURES: u32 = 5;
+/// 登录锁定时长 (秒)，同时也是尝试计数的统计窗口
+const LOGIN_LOCKOUT_SECS: i64 = 15 * 60;
+/// 管理密码最小长度
+const MIN_PASSWORD_LEN: usize = 8;
 
-    let expected_key = &config.proxy.api_key;
-    let mut authenticated = false;
-
-    // 方式1: 检查Cookie
-    if let Some(cookie_header) = headers.get("cookie") {
-        if let Ok(cookie_str) = cookie_header.to_str() {
-            for cookie in cookie_str.split(';') {
-                let parts: Vec<&str> = cookie.trim().split('=').collect();
-                if parts.len() == 2 && parts[0] == "admin_token" {
-                    if parts[1] == expected_key {
-                        authenticated = true;
-                        break;
-                    }
-                }
-            }
+/// 生成管理密码的 Argon2 哈希 (PHC 字符串)
+pub fn hash_admin_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| format!("密码哈希失败: {}", e))
+}
+
+/// 校验管理密码
+pub fn verify_admin_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+/// 管理后台会话
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminSession {
+    /// 会话 ID (用于列表与吊销，不等同于会话令牌)
+    pub id: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub last_seen_at: i64,
+    /// 登录来源 IP
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoginAttempts {
+    count: u32,
+    window_start: i64,
+    locked_until: Option<i64>,
+}
+
+impl LoginAttempts {
+    fn new(now: i64) -> Self {
+        Self { count: 0, window_start: now, locked_until: None }
+    }
+
+    /// 统计窗口已过期或锁定已结束
+    fn expired(&self, now: i64) -> bool {
+        match self.locked_until {
+            Some(until) => until <= now,
+            None => now - self.window_start > LOGIN_LOCKOUT_SECS,
         }
     }
+}
+
+/// 内存中的管理会话与登录失败计数 (重启后需重新登录)
+#[derive(Debug, Default)]
+pub struct AdminSessions {
+    /// 会话令牌 -> 会话
+    sessions: DashMap<String, AdminSession>,
+    /// 来源 IP -> 登录尝试记录 (登录成功时清除)
+    attempts: DashMap<String, LoginAttempts>,
+}
+
+impl AdminSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建会话，返回 (会话令牌, 会话)
+    pub fn create(&self, ip: Option<String>, now: i64) -> (String, AdminSession) {
+        self.sessions.retain(|_, s| s.expires_at > now);
 
-    // 方式2: 检查Authorization Header
-    if !authenticated {
-        if let Some(auth_header) = headers.get("authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                    if token == expected_key {
-                        authenticated = true;
-                    }
-                }
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let session = AdminSession {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: now,
+            expires_at: now + SESSION_TTL_SECS,
+            last_seen_at: now,
+            ip,
+        };
+        self.sessions.insert(token.clone(), session.clone());
+        (token, session)
+    }
+
+    /// 校验会话令牌，过期的会话会被移除
+    pub fn validate(&self, token: &str, now: i64) -> bool {
+        let valid = match self.sessions.get_mut(token) {
+            Some(mut session) if session.expires_at > now => {
+                session.last_seen_at = now;
+                true
             }
+            Some(_) => false,
+            None => return false,
+        };
+        if !valid {
+            self.sessions.remove(token);
         }
+        valid
     }
 
-    // 方式3: 检查Query参数
-    if !authenticated {
-        let uri = request.uri();
-        if let Some(query) = uri.query() {
-            for param in query.split('&') {
-                let parts: Vec<&str> = param.split('=').collect();
-                if parts.len() == 2 && parts[0] == "token" {
-                    if parts[1] == expected_key {
-                        authenticated = true;
-                        break;
-                    }
-                }
-            }
+    pub fn session_id(&self, token: &str) -> Option<String> {
+        self.sessions.get(token).map(|s| s.id.clone())
+    }
+
+    pub fn revoke_token(&self, token: &str) -> bool {
+        self.sessions.remove(token).is_some()
+    }
+
+    /// 按会话 ID 吊销
+    pub fn revoke(&self, id: &str) -> bool {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, s| s.id != id);
+        self.sessions.len() < before
+    }
+
+    /// 吊销除 `keep_id` 以外的全部会话，返回吊销数量
+    pub fn revoke_all_except(&self, keep_id: Option<&str>) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, s| Some(s.id.as_str()) == keep_id);
+        before - self.sessions.len()
+    }
+
+    pub fn list(&self, now: i64) -> Vec<AdminSession> {
+        let mut sessions: Vec<AdminSession> = self.sessions
+            .iter()
+            .filter(|s| s.expires_at > now)
+            .map(|s| s.value().clone())
+            .collect();
+        sessions.sort_by_key(|s| s.created_at);
+        sessions
+    }
+
+    /// 登录前计入一次尝试 (计数与检查在同一次 entry 操作中完成，并发请求无法绕过上限)；
+    /// 被锁定时返回剩余锁定秒数
+    pub fn begin_login_attempt(&self, client: &str, now: i64) -> Result<(), i64> {
+        self.attempts.retain(|_, a| !a.expired(now));
+
+        let mut entry = self.attempts.entry(client.to_string()).or_insert(LoginAttempts::new(now));
+        if let Some(until) = entry.locked_until {
+            return Err(until - now);
         }
+        entry.count += 1;
+        if entry.count >= MAX_LOGIN_FAILURES {
+            entry.locked_until = Some(now + LOGIN_LOCKOUT_SECS);
+            tracing::warn!("管理后台登录尝试次数过多，锁定来源 {} {} 秒", client, LOGIN_LOCKOUT_SECS);
+        }
+        Ok(())
+    }
+
+    /// 登录成功后清除尝试计数
+    pub fn clear_login_attempts(&self, client: &str) {
+        self.attempts.remove(client);
     }
+}
+
+/// 从 Cookie 或 Authorization 头中提取会话令牌
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    let from_cookie = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|s| s.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string());
+
+    from_cookie.or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .map(|s| s.trim().to_string())
+    })
+}
+
+fn session_cookie(token: &str, max_age: i64, secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        SESSION_COOKIE, token, max_age, if secure { "; Secure" } else { "" }
+    );
+    HeaderValue::from_str(&cookie).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+/// Admin面板认证中间件
+///
+/// 验证方式 (会话令牌由 /api/admin/login 签发，保存在内存中)：
+/// 1. Cookie: admin_session=<session_token> (HttpOnly)
+/// 2. Header: Authorization: Bearer <session_token> (脚本调用)
+pub async fn admin_auth_middleware(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    let authenticated = session_token(&headers)
+        .is_some_and(|token| state.admin_sessions.validate(&token, now));
 
     if authenticated {
         Ok(next.run(request).await)
@@ -83,36 +231,228 @@ pub async fn admin_auth_middleware(
 }
 
 /// Admin登录处理
+///
+/// 已设置管理密码时校验密码 (Argon2)；未设置时兼容使用 API Key 登录
 pub async fn admin_login(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    client_ip: Option<Extension<ClientIp>>,
     Json(payload): Json<LoginRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // 验证API Key
+) -> Result<Response, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    let client_ip = client_ip.map(|Extension(ClientIp(ip))| ip.to_string());
+    let throttle_key = client_ip.clone().unwrap_or_else(|| "unknown".to_string());
+
+    if let Err(retry_after) = state.admin_sessions.begin_login_attempt(&throttle_key, now) {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({
+                "success": false,
+                "message": format!("Too many failed attempts, try again in {} seconds", retry_after)
+            }))
+        ).into_response();
+        if let Ok(value) = HeaderValue::from_str(&retry_after.to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+        return Ok(response);
+    }
+
     let config = crate::modules::config::load_app_config()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    if payload.api_key != config.proxy.api_key {
+    let valid = match config.proxy.admin_password_hash.clone() {
+        Some(hash) => {
+            let password = payload.password.clone();
+            tokio::task::spawn_blocking(move || verify_admin_password(&hash, &password))
+                .await
+                .unwrap_or(false)
+        }
+        None => {
+            tracing::warn!("尚未设置管理密码，使用 API Key 登录管理后台；建议通过 /api/admin/password 设置独立密码");
+            let api_key = state.auth.read().await.api_key.clone();
+            !api_key.is_empty() && bool::from(payload.password.as_bytes().ct_eq(api_key.as_bytes()))
+        }
+    };
+
+    if !valid {
         return Ok((
             StatusCode::UNAUTHORIZED,
             Json(json!({
                 "success": false,
-                "message": "Invalid API Key"
+                "message": "Invalid credentials"
             }))
-        ));
+        ).into_response());
     }
 
-    // 返回成功，前端会存储token
-    Ok((
+    state.admin_sessions.clear_login_attempts(&throttle_key);
+    let (token, session) = state.admin_sessions.create(client_ip, now);
+    tracing::info!("管理后台登录成功 (会话 {})", session.id);
+
+    let mut response = (
         StatusCode::OK,
         Json(json!({
             "success": true,
-            "token": config.proxy.api_key,
-            "message": "Login successful"
+            "message": "Login successful",
+            "expires_at": session.expires_at,
+            "password_set": config.proxy.admin_password_hash.is_some()
         }))
-    ))
+    ).into_response();
+    response.headers_mut().insert(header::SET_COOKIE, session_cookie(&token, SESSION_TTL_SECS, state.tls_enabled));
+    Ok(response)
 }
 
 #[derive(serde::Deserialize)]
 pub struct LoginRequest {
-    api_key: String,
+    /// 管理密码 (兼容旧版前端的 api_key 字段)
+    #[serde(alias = "api_key")]
+    password: String,
+}
+
+/// 退出登录：吊销当前会话并清除 Cookie
+pub async fn admin_logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = session_token(&headers) {
+        state.admin_sessions.revoke_token(&token);
+    }
+    let mut response = Json(json!({ "success": true, "message": "Logged out" })).into_response();
+    response.headers_mut().insert(header::SET_COOKIE, session_cookie("", 0, state.tls_enabled));
+    response
+}
+
+/// 列出有效会话
+pub async fn list_admin_sessions(State(state): State<AppState>) -> Json<Vec<AdminSession>> {
+    Json(state.admin_sessions.list(chrono::Utc::now().timestamp()))
+}
+
+/// 吊销指定会话
+pub async fn revoke_admin_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !state.admin_sessions.revoke(&session_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(json!({ "success": true, "message": "Session revoked" })))
+}
+
+/// 吊销除当前会话以外的全部会话
+pub async fn revoke_other_admin_sessions(State(state): State<AppState>, headers: HeaderMap) -> Json<serde_json::Value> {
+    let current = session_token(&headers).and_then(|t| state.admin_sessions.session_id(&t));
+    let revoked = state.admin_sessions.revoke_all_except(current.as_deref());
+    Json(json!({ "success": true, "revoked": revoked }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChangePasswordRequest {
+    /// 当前密码 (首次设置时可省略)
+    #[serde(default)]
+    current_password: Option<String>,
+    new_password: String,
+}
+
+/// 设置 / 修改管理密码；成功后吊销其他会话
+pub async fn change_admin_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let fail = |status: StatusCode, message: &str| (status, Json(json!({ "success": false, "message": message })));
+
+    if req.new_password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(fail(StatusCode::BAD_REQUEST, "Password must be at least 8 characters"));
+    }
+
+    let mut config = crate::modules::config::load_app_config()
+        .map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load config"))?;
+
+    let current_hash = config.proxy.admin_password_hash.clone();
+    let new_password = req.new_password;
+    let current_password = req.current_password.unwrap_or_default();
+    let hashed = tokio::task::spawn_blocking(move || {
+        if let Some(hash) = current_hash {
+            if !verify_admin_password(&hash, &current_password) {
+                return Err(false);
+            }
+        }
+        hash_admin_password(&new_password).map_err(|e| {
+            tracing::error!("{}", e);
+            true
+        })
+    })
+    .await
+    .map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR, "Password hashing failed"))?;
+
+    let hash = match hashed {
+        Ok(hash) => hash,
+        Err(false) => return Err(fail(StatusCode::UNAUTHORIZED, "Current password is incorrect")),
+        Err(true) => return Err(fail(StatusCode::INTERNAL_SERVER_ERROR, "Password hashing failed")),
+    };
+
+    config.proxy.admin_password_hash = Some(hash);
+    crate::modules::config::save_app_config(&config)
+        .map_err(|_| fail(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save config"))?;
+
+    let current = session_token(&headers).and_then(|t| state.admin_sessions.session_id(&t));
+    let revoked = state.admin_sessions.revoke_all_except(current.as_deref());
+    tracing::info!("管理密码已更新，吊销其他会话 {} 个", revoked);
+
+    Ok(Json(json!({ "success": true, "message": "Password updated" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = hash_admin_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_admin_password(&hash, "correct horse"));
+        assert!(!verify_admin_password(&hash, "wrong horse"));
+        assert!(!verify_admin_password("not-a-hash", "correct horse"));
+    }
+
+    #[test]
+    fn test_sessions_expire_and_revoke() {
+        let sessions = AdminSessions::new();
+        let (token, session) = sessions.create(Some("127.0.0.1".to_string()), 1_000);
+        let (other, _) = sessions.create(None, 1_000);
+        assert_eq!(token.len(), 64);
+        assert!(sessions.validate(&token, 1_001));
+        assert!(!sessions.validate(&token, 1_000 + SESSION_TTL_SECS));
+        assert!(!sessions.validate("unknown", 1_001));
+
+        assert_eq!(sessions.revoke_all_except(Some(&session.id)), 1);
+        assert!(!sessions.validate(&other, 1_001));
+    }
+
+    #[test]
+    fn test_login_throttle_locks_after_repeated_attempts() {
+        let sessions = AdminSessions::new();
+        // 并发请求在校验密码前即计数，超过上限的尝试直接被拒绝
+        for _ in 0..MAX_LOGIN_FAILURES {
+            assert!(sessions.begin_login_attempt("1.2.3.4", 100).is_ok());
+        }
+        assert_eq!(sessions.begin_login_attempt("1.2.3.4", 110), Err(LOGIN_LOCKOUT_SECS - 10));
+        assert!(sessions.begin_login_attempt("5.6.7.8", 110).is_ok());
+
+        // 锁定结束后重新计数；窗口过期的记录被清理
+        assert!(sessions.begin_login_attempt("1.2.3.4", 100 + LOGIN_LOCKOUT_SECS).is_ok());
+        assert_eq!(sessions.attempts.get("1.2.3.4").unwrap().count, 1);
+        assert!(sessions.begin_login_attempt("9.9.9.9", 111 + LOGIN_LOCKOUT_SECS).is_ok());
+        assert!(!sessions.attempts.contains_key("5.6.7.8"));
+
+        // 登录成功清除计数
+        sessions.clear_login_attempts("1.2.3.4");
+        assert!(!sessions.attempts.contains_key("1.2.3.4"));
+    }
+
+    #[test]
+    fn test_session_token_sources() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark; admin_session=abc"));
+        assert_eq!(session_token(&headers).as_deref(), Some("abc"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer xyz"));
+        assert_eq!(session_token(&headers).as_deref(), Some("xyz"));
+    }
 }
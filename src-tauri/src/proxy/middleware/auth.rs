@@ -1,45 +1,314 @@
 // API Key 认证中间件
 use axum::{
-    extract::Request,
-    http::{header, StatusCode},
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::json;
+use subtle::ConstantTimeEq;
 
-/// API Key 认证中间件
-pub async fn auth_middleware(request: Request, next: Next) -> Result<Response, StatusCode> {
-    // Log the request method and URI
-    tracing::info!("Request: {} {}", request.method(), request.uri());
-    
-    // 从 header 中提取 API key
-    let api_key = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer "))
-        .or_else(|| {
-            request
-                .headers()
-                .get("x-api-key")
-                .and_then(|h| h.to_str().ok())
-        });
-
-    // TODO: 实际验证 API key
-    // 目前暂时允许所有请求通过
-    if api_key.is_some() || true {
-        Ok(next.run(request).await)
+use crate::proxy::config::{ApiProtocol, ClientApiKey, ProxyConfig};
+use crate::proxy::server::AppState;
+
+/// 客户端认证配置 (随配置保存热更新)
+#[derive(Debug, Clone, Default)]
+pub struct ProxyAuthConfig {
+    /// 主 API Key (不受限制)
+    pub api_key: String,
+    /// 托管的客户端 Key
+    pub client_keys: Vec<ClientApiKey>,
+    /// 是否需要校验
+    pub required: bool,
+}
+
+/// 已认证的客户端身份 (由认证中间件写入请求扩展，供 handler 做策略检查)
+#[derive(Debug, Clone, Default)]
+pub struct ClientIdentity {
+    /// 命中的托管 Key；None 表示主 API Key 或未启用校验，不受策略限制
+    pub key: Option<ClientApiKey>,
+}
+
+impl ClientIdentity {
+    pub fn key_id(&self) -> Option<&str> {
+        self.key.as_ref().map(|k| k.id.as_str())
+    }
+
+    pub fn name(&self) -> &str {
+        self.key.as_ref().map(|k| k.name.as_str()).unwrap_or("default")
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.key.as_ref().is_none_or(|k| k.allows_model(model))
+    }
+
+    /// 固定的账号分组
+    pub fn group(&self) -> Option<&str> {
+        self.key.as_ref().and_then(|k| k.group.as_deref())
+    }
+}
+
+impl ProxyAuthConfig {
+    pub fn from_proxy_config(config: &ProxyConfig) -> Self {
+        if config.disable_auth && config.allow_lan_access {
+            tracing::warn!("已开启局域网访问，忽略 disable_auth，继续校验 API Key");
+        }
+        Self {
+            api_key: config.api_key.clone(),
+            client_keys: config.client_keys.clone(),
+            required: config.auth_required(),
+        }
+    }
+
+    /// 常量时间比较，避免通过响应耗时推测密钥
+    fn key_matches(expected: &str, provided: &str) -> bool {
+        !expected.is_empty() && bool::from(provided.as_bytes().ct_eq(expected.as_bytes()))
+    }
+
+    /// 校验 Key 并解析客户端身份；失败时返回错误描述
+    pub fn authenticate(&self, provided: &str, now: i64) -> Result<ClientIdentity, &'static str> {
+        if Self::key_matches(&self.api_key, provided) {
+            return Ok(ClientIdentity::default());
+        }
+        let key = self.client_keys
+            .iter()
+            .find(|k| Self::key_matches(&k.key, provided))
+            .ok_or("Invalid API key")?;
+        if key.disabled {
+            return Err("API key is disabled");
+        }
+        if key.is_expired(now) {
+            return Err("API key has expired");
+        }
+        Ok(ClientIdentity { key: Some(key.clone()) })
+    }
+}
+
+/// 按请求路径判断客户端协议 (决定错误响应体格式与协议权限)
+pub fn protocol_from_path(path: &str) -> ApiProtocol {
+    if path.starts_with("/v1beta/") || path == "/v1beta" {
+        ApiProtocol::Gemini
+    } else if path.starts_with("/v1/messages") || path == "/v1/models/claude" {
+        ApiProtocol::Claude
     } else {
-        Err(StatusCode::UNAUTHORIZED)
+        ApiProtocol::Openai
+    }
+}
+
+/// 生成符合各协议格式的错误响应 (鉴权/限流/参数错误，以及透传的上游与服务端错误)
+pub fn client_error_response(protocol: ApiProtocol, status: StatusCode, message: &str) -> Response {
+    // (OpenAI type, OpenAI code, Anthropic type, Google status)
+    let (openai_type, openai_code, anthropic_type, google_status) = match status {
+        StatusCode::FORBIDDEN => ("permission_error", "permission_denied", "permission_error", "PERMISSION_DENIED"),
+        StatusCode::TOO_MANY_REQUESTS => ("rate_limit_error", "rate_limit_exceeded", "rate_limit_error", "RESOURCE_EXHAUSTED"),
+        StatusCode::BAD_REQUEST => ("invalid_request_error", "invalid_request", "invalid_request_error", "INVALID_ARGUMENT"),
+        StatusCode::NOT_FOUND => ("invalid_request_error", "not_found", "not_found_error", "NOT_FOUND"),
+        StatusCode::UNAUTHORIZED => ("invalid_request_error", "invalid_api_key", "authentication_error", "UNAUTHENTICATED"),
+        StatusCode::SERVICE_UNAVAILABLE => ("server_error", "service_unavailable", "overloaded_error", "UNAVAILABLE"),
+        s if s.is_server_error() => ("server_error", "server_error", "api_error", "INTERNAL"),
+        _ => ("invalid_request_error", "invalid_request", "invalid_request_error", "FAILED_PRECONDITION"),
+    };
+    let body = match protocol {
+        ApiProtocol::Openai => json!({
+            "error": {
+                "message": message,
+                "type": openai_type,
+                "param": null,
+                "code": openai_code
+            }
+        }),
+        ApiProtocol::Claude => json!({
+            "type": "error",
+            "error": {
+                "type": anthropic_type,
+                "message": message
+            }
+        }),
+        ApiProtocol::Gemini => json!({
+            "error": {
+                "code": status.as_u16(),
+                "message": message,
+                "status": google_status
+            }
+        }),
+    };
+    (status, Json(body)).into_response()
+}
+
+/// 从请求中提取客户端 API Key
+///
+/// 支持：
+/// 1. Header: Authorization: Bearer <key>
+/// 2. Header: x-api-key (Anthropic)
+/// 3. Header: x-goog-api-key (Gemini)
+/// 4. Query: ?key=<key> (Gemini)
+fn extract_api_key(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    let header_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    header_value(header::AUTHORIZATION.as_str())
+        .and_then(|s| s.strip_prefix("Bearer ").map(|k| k.trim().to_string()))
+        .or_else(|| header_value("x-api-key"))
+        .or_else(|| header_value("x-goog-api-key"))
+        .or_else(|| {
+            query.and_then(|q| {
+                url::form_urlencoded::parse(q.as_bytes())
+                    .find(|(k, _)| k == "key")
+                    .map(|(_, v)| v.into_owned())
+            })
+        })
+}
+
+/// API Key 认证中间件
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    // 只记录路径：查询串中可能带有 ?key= 形式的 API Key
+    tracing::info!("Request: {} {}", request.method(), request.uri().path());
+
+    let auth = state.auth.read().await.clone();
+    let protocol = protocol_from_path(request.uri().path());
+    let provided = extract_api_key(request.headers(), request.uri().query());
+    let now = chrono::Utc::now().timestamp();
+
+    let identity = match provided.as_deref().map(|key| auth.authenticate(key, now)) {
+        Some(Ok(identity)) => identity,
+        // 未启用校验时仍尽量识别托管 Key，便于按 Key 区分调用方
+        _ if !auth.required => ClientIdentity::default(),
+        Some(Err(message)) => {
+            tracing::warn!("拒绝未认证请求: {} {} ({})", request.method(), request.uri().path(), message);
+            return client_error_response(protocol, StatusCode::UNAUTHORIZED, message);
+        }
+        None => {
+            tracing::warn!("拒绝未认证请求: {} {} (缺少 API Key)", request.method(), request.uri().path());
+            return client_error_response(protocol, StatusCode::UNAUTHORIZED, "Missing API key");
+        }
+    };
+
+    if identity.key.as_ref().is_some_and(|k| !k.allows_protocol(protocol)) {
+        tracing::warn!("客户端 Key [{}] 无权使用 {:?} 协议", identity.name(), protocol);
+        return client_error_response(
+            protocol,
+            StatusCode::FORBIDDEN,
+            "This API key is not allowed to use this API",
+        );
     }
+
+    request.extensions_mut().insert(identity);
+    next.run(request).await
 }
 
 #[cfg(test)]
 mod tests {
-    // 移除未使用的 use super::*;
+    use super::*;
+    use axum::http::HeaderValue;
 
     #[test]
-    fn test_auth_placeholder() {
-        // Placeholder test
-        assert!(true);
+    fn test_extract_api_key_sources() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(extract_api_key(&headers, None), None);
+        assert_eq!(extract_api_key(&headers, Some("alt=sse&key=sk-q")), Some("sk-q".to_string()));
+
+        headers.insert("x-goog-api-key", HeaderValue::from_static("sk-goog"));
+        assert_eq!(extract_api_key(&headers, Some("key=sk-q")), Some("sk-goog".to_string()));
+
+        headers.insert("x-api-key", HeaderValue::from_static("sk-anthropic"));
+        assert_eq!(extract_api_key(&headers, None), Some("sk-anthropic".to_string()));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer sk-openai"));
+        assert_eq!(extract_api_key(&headers, None), Some("sk-openai".to_string()));
+    }
+
+    #[test]
+    fn test_authenticate_and_opt_out() {
+        let mut restricted = ClientApiKey::new("alice".to_string());
+        restricted.allowed_protocols = vec![ApiProtocol::Claude];
+        let mut revoked = ClientApiKey::new("bob".to_string());
+        revoked.disabled = true;
+        let mut expired = ClientApiKey::new("carol".to_string());
+        expired.expires_at = Some(1_000);
+
+        let mut config = ProxyConfig {
+            api_key: "sk-secret".to_string(),
+            client_keys: vec![restricted.clone(), revoked.clone(), expired.clone()],
+            ..Default::default()
+        };
+        let auth = ProxyAuthConfig::from_proxy_config(&config);
+        assert!(auth.required);
+        assert!(auth.authenticate("sk-secret", 0).unwrap().key.is_none());
+        assert!(auth.authenticate("sk-secre", 0).is_err());
+        assert!(auth.authenticate("", 0).is_err());
+
+        let identity = auth.authenticate(&restricted.key, 0).unwrap();
+        assert_eq!(identity.key_id(), Some(restricted.id.as_str()));
+        assert!(!identity.key.unwrap().allows_protocol(ApiProtocol::Openai));
+
+        assert_eq!(auth.authenticate(&revoked.key, 0).unwrap_err(), "API key is disabled");
+        assert!(auth.authenticate(&expired.key, 999).is_ok());
+        assert_eq!(auth.authenticate(&expired.key, 1_000).unwrap_err(), "API key has expired");
+
+        config.disable_auth = true;
+        assert!(!ProxyAuthConfig::from_proxy_config(&config).required);
+
+        // 局域网访问时不允许关闭校验
+        config.allow_lan_access = true;
+        assert!(ProxyAuthConfig::from_proxy_config(&config).required);
+    }
+
+    #[test]
+    fn test_identity_model_policy() {
+        let mut key = ClientApiKey::new("alice".to_string());
+        key.allowed_models = vec!["claude-sonnet-*".to_string(), "gemini-2.5-flash".to_string()];
+        key.group = Some("team-a".to_string());
+        let identity = ClientIdentity { key: Some(key) };
+        assert!(identity.allows_model("claude-sonnet-4-5"));
+        assert!(identity.allows_model("gemini-2.5-flash"));
+        assert!(!identity.allows_model("claude-opus-4"));
+        assert_eq!(identity.group(), Some("team-a"));
+
+        assert!(ClientIdentity::default().allows_model("anything"));
+    }
+
+    #[tokio::test]
+    async fn test_error_body_per_protocol() {
+        let body = |path: &str, status: StatusCode| {
+            let response = client_error_response(protocol_from_path(path), status, "x");
+            assert_eq!(response.status(), status);
+            async move {
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+        assert_eq!(protocol_from_path("/v1/chat/completions"), ApiProtocol::Openai);
+        assert_eq!(protocol_from_path("/v1/messages/count_tokens"), ApiProtocol::Claude);
+        assert_eq!(protocol_from_path("/v1beta/models/gemini-pro:generateContent"), ApiProtocol::Gemini);
+
+        let openai = body("/v1/chat/completions", StatusCode::UNAUTHORIZED).await;
+        assert_eq!(openai["error"]["type"], "invalid_request_error");
+        assert_eq!(openai["error"]["code"], "invalid_api_key");
+        assert_eq!(openai["error"]["message"], "x");
+
+        let anthropic = body("/v1/messages", StatusCode::TOO_MANY_REQUESTS).await;
+        assert_eq!(anthropic["type"], "error");
+        assert_eq!(anthropic["error"]["type"], "rate_limit_error");
+
+        let gemini = body("/v1beta/models", StatusCode::FORBIDDEN).await;
+        assert_eq!(gemini["error"]["status"], "PERMISSION_DENIED");
+        assert_eq!(gemini["error"]["code"], 403);
+
+        let openai = body("/v1/responses", StatusCode::SERVICE_UNAVAILABLE).await;
+        assert_eq!(openai["error"]["type"], "server_error");
+        let anthropic = body("/v1/messages", StatusCode::BAD_GATEWAY).await;
+        assert_eq!(anthropic["error"]["type"], "api_error");
+        let gemini = body("/v1beta/models", StatusCode::INTERNAL_SERVER_ERROR).await;
+        assert_eq!(gemini["error"]["status"], "INTERNAL");
     }
 }
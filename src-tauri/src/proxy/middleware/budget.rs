@@ -0,0 +1,75 @@
+// 客户端 Key 预算检查中间件
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::proxy::common::budget::KeyBudgetStatus;
+use crate::proxy::middleware::auth::{client_error_response, protocol_from_path, ClientIdentity};
+use crate::proxy::server::AppState;
+
+/// 预算检查中间件 (需位于认证中间件之后)
+///
+/// 在进入 handler 选号之前检查 Key 当日 / 当月用量，超出时返回 429 并通过 Retry-After 告知重置时间；
+/// 放行的请求立即计入请求数预算 (上游失败或中途断开的请求同样计数)
+pub async fn budget_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = request
+        .extensions()
+        .get::<ClientIdentity>()
+        .and_then(|identity| identity.key.clone())
+    else {
+        return next.run(request).await;
+    };
+
+    let store = state.usage.clone();
+    let now = chrono::Utc::now();
+    if key.budget.is_unlimited() {
+        store.record_request(key.id, now.timestamp());
+        return next.run(request).await;
+    }
+
+    let key_id = key.id.clone();
+    let status = tokio::task::spawn_blocking(move || KeyBudgetStatus::load(&store, &key, now))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r);
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            // 用量库不可用时不阻断请求
+            tracing::warn!("预算检查失败，放行请求: {}", e);
+            state.usage.record_request(key_id, now.timestamp());
+            return next.run(request).await;
+        }
+    };
+
+    if let Some((period, metric, limit)) = status.exceeded() {
+        let retry_after = (period.reset_at - now.timestamp()).max(1);
+        tracing::warn!(
+            "客户端 Key [{}] 超出 {} 预算 ({} 上限 {})，{} 秒后重置",
+            status.name, period.period.as_str(), metric, limit, retry_after
+        );
+        let message = format!(
+            "Budget exceeded for this API key: {} {} limit of {} reached, resets in {} seconds",
+            period.period.as_str(), metric, limit, retry_after
+        );
+        let mut response = client_error_response(
+            protocol_from_path(request.uri().path()),
+            StatusCode::TOO_MANY_REQUESTS,
+            &message,
+        );
+        if let Ok(value) = HeaderValue::from_str(&retry_after.to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+        return response;
+    }
+
+    state.usage.record_request(key_id, now.timestamp());
+    next.run(request).await
+}
@@ -1,22 +1,103 @@
 // CORS 中间件
-use tower_http::cors::{CorsLayer, Any};
-
-/// 创建 CORS layer
-pub fn cors_layer() -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any)
+use axum::http::{HeaderName, HeaderValue};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use crate::proxy::config::CorsPolicy;
+
+/// 按策略创建 CORS layer
+///
+/// 请求方法与请求头按预检请求原样放行，由 allowed_origins 决定是否允许跨域
+pub fn cors_layer(policy: &CorsPolicy) -> Result<CorsLayer, String> {
+    let any_origin = policy.allowed_origins.iter().any(|o| o.trim() == "*");
+    if any_origin && policy.allow_credentials {
+        return Err("CORS 配置无效: allow_credentials 不能与 \"*\" 来源同时使用".to_string());
+    }
+
+    let origin = if any_origin {
+        AllowOrigin::any()
+    } else {
+        let origins = policy
+            .allowed_origins
+            .iter()
+            .map(|o| {
+                HeaderValue::from_str(o.trim().trim_end_matches('/'))
+                    .map_err(|_| format!("CORS 配置无效: 来源 {:?} 不合法", o))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let exposed = policy
+        .exposed_headers
+        .iter()
+        .map(|h| {
+            HeaderName::from_bytes(h.trim().as_bytes())
+                .map_err(|_| format!("CORS 配置无效: 响应头 {:?} 不合法", h))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers(ExposeHeaders::list(exposed))
+        .allow_credentials(policy.allow_credentials))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::config::CorsConfig;
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use tower::ServiceExt;
+
+    async fn preflight(policy: &CorsPolicy, origin: &str) -> Option<String> {
+        let app = Router::new()
+            .route("/v1/messages", post(|| async { "ok" }))
+            .layer(cors_layer(policy).unwrap());
+        let request = Request::builder()
+            .method("OPTIONS")
+            .uri("/v1/messages")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_default_policies() {
+        let config = CorsConfig::default();
+        assert_eq!(preflight(&config.api, "https://chat.example.com").await.as_deref(), Some("*"));
+        // 管理后台默认不允许跨域
+        assert_eq!(preflight(&config.admin, "https://evil.example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_origin_list_with_credentials() {
+        let policy = CorsPolicy {
+            allowed_origins: vec!["https://ops.example.com/".to_string()],
+            allow_credentials: true,
+            exposed_headers: vec![],
+        };
+        assert_eq!(preflight(&policy, "https://ops.example.com").await.as_deref(), Some("https://ops.example.com"));
+        assert_eq!(preflight(&policy, "https://evil.example.com").await, None);
+    }
 
     #[test]
-    fn test_cors_layer_creation() {
-        let _layer = cors_layer();
-        // Layer 创建成功
-        assert!(true);
+    fn test_invalid_policies_rejected() {
+        let wildcard_credentials = CorsPolicy {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: true,
+            exposed_headers: vec![],
+        };
+        assert!(cors_layer(&wildcard_credentials).is_err());
+
+        let bad_header = CorsPolicy { exposed_headers: vec!["bad header".to_string()], ..Default::default() };
+        assert!(cors_layer(&bad_header).is_err());
     }
 }
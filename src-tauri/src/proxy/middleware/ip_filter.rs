@@ -0,0 +1,64 @@
+// 来源 IP 访问控制中间件
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::proxy::common::ip_filter::IpScope;
+use crate::proxy::middleware::auth::{client_error_response, protocol_from_path};
+use crate::proxy::server::AppState;
+
+/// 解析后的真实客户端 IP (经受信任代理的 X-Forwarded-For 还原)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// 按请求路径套用协议路由 / 管理后台的 IP 规则，并写入 ClientIp 供后续中间件使用
+pub async fn ip_filter_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(peer) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+    else {
+        return next.run(request).await;
+    };
+
+    let path = request.uri().path().to_string();
+    let scope = IpScope::from_path(&path);
+    // X-Forwarded-For 无法解析，或真实来源不在允许范围内时拒绝
+    let checked = {
+        let acl = state.ip_access.read().await;
+        acl.client_ip(peer, request.headers()).and_then(|client| {
+            if acl.allows(scope, client) {
+                Ok(client)
+            } else {
+                Err(format!("来源 {} 不在允许范围内", client.ip))
+            }
+        })
+    };
+    let client = match checked {
+        Ok(client) => client,
+        Err(reason) => {
+            tracing::warn!("拒绝访问 {}: {} (IP 访问控制)", path, reason);
+            let message = "Access from this IP address is not allowed";
+            return match scope {
+                IpScope::Api => client_error_response(protocol_from_path(&path), StatusCode::FORBIDDEN, message),
+                IpScope::Admin => (
+                    StatusCode::FORBIDDEN,
+                    Json(serde_json::json!({ "success": false, "message": message })),
+                )
+                    .into_response(),
+            };
+        }
+    };
+
+    request.extensions_mut().insert(ClientIp(client.ip));
+    next.run(request).await
+}
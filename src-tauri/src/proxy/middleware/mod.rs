@@ -1,12 +1,18 @@
 // Middleware 模块 - Axum 中间件
 
 pub mod auth;
+pub mod budget;
+pub mod rate_limit;
+pub mod ip_filter;
 pub mod cors;
 pub mod logging;
 pub mod admin_auth;
 pub mod stats;
 
 pub use auth::auth_middleware;
+pub use budget::budget_middleware;
+pub use rate_limit::rate_limit_middleware;
+pub use ip_filter::{ip_filter_middleware, ClientIp};
 pub use cors::cors_layer;
-pub use admin_auth::{admin_auth_middleware, admin_login};
+pub use admin_auth::{admin_auth_middleware, admin_login, AdminSessions};
 pub use stats::stats_middleware;
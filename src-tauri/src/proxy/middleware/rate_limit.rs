@@ -0,0 +1,177 @@
+// 请求限流中间件
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::proxy::common::rate_limiter::{RateLimitRejection, RateLimitStatus, StreamPermit};
+use crate::proxy::config::ApiProtocol;
+use crate::proxy::middleware::auth::{client_error_response, protocol_from_path, ClientIdentity};
+use crate::proxy::middleware::ip_filter::ClientIp;
+use crate::proxy::server::AppState;
+
+/// 请求限流中间件 (需位于认证中间件之后)
+///
+/// 分别按来源 IP 与客户端 Key 计数，两者都通过才放行 (任一维度拒绝时退还已占用的配额)；
+/// 并发流名额在响应体传输结束后释放
+pub async fn rate_limit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.rate_limit.read().await.clone();
+    let protocol = protocol_from_path(request.uri().path());
+    let identity = request.extensions().get::<ClientIdentity>().cloned().unwrap_or_default();
+    let key_limit = identity.key.as_ref().and_then(|k| k.rate_limit).unwrap_or(config.per_key);
+    let ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip)
+        .or_else(|| request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip()));
+
+    let mut checks = Vec::new();
+    if let Some(ip) = ip.filter(|_| !config.per_ip.is_unlimited()) {
+        checks.push((format!("ip:{}", ip), config.per_ip));
+    }
+    if !key_limit.is_unlimited() {
+        checks.push((format!("key:{}", identity.key_id().unwrap_or("default")), key_limit));
+    }
+
+    let mut permits: Vec<StreamPermit> = Vec::new();
+    let mut reported: Option<RateLimitStatus> = None;
+    for (index, (key, limit)) in checks.iter().enumerate() {
+        match state.rate_limiter.check(key, *limit) {
+            Ok((status, permit)) => {
+                permits.push(permit);
+                // 响应头报告剩余额度最少的一项
+                if let Some(status) = status {
+                    if reported.is_none_or(|r| status.remaining < r.remaining) {
+                        reported = Some(status);
+                    }
+                }
+            }
+            Err(rejection) => {
+                tracing::warn!("请求被限流 [{}]: {:?}", key, rejection);
+                // 被拒绝的请求不应消耗其他维度 (如同一 IP 下其他调用方共享的额度)
+                for (charged_key, charged_limit) in &checks[..index] {
+                    state.rate_limiter.refund(charged_key, *charged_limit);
+                }
+                return rejected_response(protocol, rejection);
+            }
+        }
+    }
+
+    let mut response = next.run(request).await;
+    if let Some(status) = reported {
+        apply_rate_limit_headers(response.headers_mut(), protocol, &status);
+    }
+    if permits.is_empty() {
+        return response;
+    }
+
+    // 将并发名额绑定到响应体上，流式响应结束 (或客户端断开) 时才释放
+    let (parts, body) = response.into_parts();
+    let stream = body.into_data_stream().map(move |chunk| {
+        let _ = &permits;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+fn rejected_response(protocol: ApiProtocol, rejection: RateLimitRejection) -> Response {
+    let (message, retry_after, status) = match rejection {
+        RateLimitRejection::Requests { status, retry_after } => (
+            format!("Rate limit reached: {} requests per minute", status.limit),
+            retry_after,
+            Some(status),
+        ),
+        RateLimitRejection::Streams { limit } => (
+            format!("Too many concurrent requests: limit is {}", limit),
+            Duration::from_secs(1),
+            None,
+        ),
+    };
+    let mut response = client_error_response(protocol, StatusCode::TOO_MANY_REQUESTS, &message);
+    let headers = response.headers_mut();
+    if let Some(status) = status {
+        apply_rate_limit_headers(headers, protocol, &status);
+    }
+    let retry_ms = retry_after.as_millis().max(1);
+    insert_header(headers, "retry-after", &retry_ms.div_ceil(1000).to_string());
+    insert_header(headers, "retry-after-ms", &retry_ms.to_string());
+    response
+}
+
+/// 写入限流响应头
+///
+/// - OpenAI: x-ratelimit-{limit,remaining,reset}-requests (reset 形如 "1m30s")
+/// - Anthropic: anthropic-ratelimit-requests-{limit,remaining,reset} (reset 为 RFC 3339 时间)
+fn apply_rate_limit_headers(headers: &mut HeaderMap, protocol: ApiProtocol, status: &RateLimitStatus) {
+    insert_header(headers, "x-ratelimit-limit-requests", &status.limit.to_string());
+    insert_header(headers, "x-ratelimit-remaining-requests", &status.remaining.to_string());
+    insert_header(headers, "x-ratelimit-reset-requests", &format_reset(status.reset_after));
+    if protocol == ApiProtocol::Claude {
+        let reset_at = chrono::Utc::now()
+            + chrono::Duration::from_std(status.reset_after).unwrap_or_default();
+        insert_header(headers, "anthropic-ratelimit-requests-limit", &status.limit.to_string());
+        insert_header(headers, "anthropic-ratelimit-requests-remaining", &status.remaining.to_string());
+        insert_header(
+            headers,
+            "anthropic-ratelimit-requests-reset",
+            &reset_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        );
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// OpenAI 风格的时长格式: "250ms" / "12s" / "1m30s"
+fn format_reset(duration: Duration) -> String {
+    let ms = duration.as_millis();
+    if ms < 1000 {
+        return format!("{}ms", ms);
+    }
+    let secs = ms.div_ceil(1000);
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m{}s", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_reset() {
+        assert_eq!(format_reset(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_reset(Duration::from_millis(11_200)), "12s");
+        assert_eq!(format_reset(Duration::from_secs(90)), "1m30s");
+    }
+
+    #[test]
+    fn test_rejection_sets_retry_headers() {
+        let status = RateLimitStatus { limit: 60, remaining: 0, reset_after: Duration::from_secs(60) };
+        let response = rejected_response(
+            ApiProtocol::Claude,
+            RateLimitRejection::Requests { status, retry_after: Duration::from_millis(1500) },
+        );
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers();
+        assert_eq!(headers["retry-after"], "2");
+        assert_eq!(headers["retry-after-ms"], "1500");
+        assert_eq!(headers["x-ratelimit-remaining-requests"], "0");
+        assert_eq!(headers["anthropic-ratelimit-requests-limit"], "60");
+    }
+}
@@ -5,6 +5,7 @@ pub mod config;
 pub mod token_manager;
 pub mod project_resolver;
 pub mod server;
+pub mod tls;
 
 // 新架构模块
 pub mod mappers;           // 协议转换器
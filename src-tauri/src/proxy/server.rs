@@ -9,6 +9,12 @@ use tower_http::trace::TraceLayer;
 use std::sync::Arc;
 use tokio::sync::oneshot;
 use crate::proxy::TokenManager;
+use crate::proxy::middleware::auth::ProxyAuthConfig;
+use crate::proxy::common::ip_filter::IpAccessControl;
+use crate::proxy::tls::TlsState;
+
+/// TLS 握手超时
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 
 /// Axum 应用状态
@@ -25,6 +31,15 @@ pub struct AppState {
     #[allow(dead_code)]
     pub upstream_proxy: Arc<tokio::sync::RwLock<crate::proxy::config::UpstreamProxyConfig>>,
     pub upstream: Arc<crate::proxy::upstream::client::UpstreamClient>,
+    pub auth: Arc<tokio::sync::RwLock<ProxyAuthConfig>>,
+    pub usage: crate::proxy::admin::UsageStore,
+    pub rate_limit: Arc<tokio::sync::RwLock<crate::proxy::config::RateLimitConfig>>,
+    pub rate_limiter: Arc<crate::proxy::common::rate_limiter::RateLimiter>,
+    pub admin_sessions: Arc<crate::proxy::middleware::AdminSessions>,
+    pub ip_access: Arc<tokio::sync::RwLock<IpAccessControl>>,
+    pub response_store: crate::proxy::common::response_store::ResponseStore,
+    /// 监听端口是否启用 TLS (用于管理后台 Cookie 的 Secure 标记)
+    pub tls_enabled: bool,
 }
 
 /// Axum 服务器实例
@@ -34,6 +49,12 @@ pub struct AxumServer {
     openai_mapping: Arc<tokio::sync::RwLock<std::collections::HashMap<String, String>>>,
     custom_mapping: Arc<tokio::sync::RwLock<std::collections::HashMap<String, String>>>,
     proxy_state: Arc<tokio::sync::RwLock<crate::proxy::config::UpstreamProxyConfig>>,
+    token_manager: Arc<TokenManager>,
+    auth: Arc<tokio::sync::RwLock<ProxyAuthConfig>>,
+    rate_limit: Arc<tokio::sync::RwLock<crate::proxy::config::RateLimitConfig>>,
+    ip_access: Arc<tokio::sync::RwLock<IpAccessControl>>,
+    tls: Option<Arc<TlsState>>,
+    background_tasks: Vec<tokio::task::JoinHandle<()>>,
 }
 
 impl AxumServer {
@@ -59,21 +80,65 @@ impl AxumServer {
         *proxy = new_config;
         tracing::info!("上游代理配置已热更新");
     }
-    /// 启动 Axum 服务器
+
+    /// 更新账号调度策略
+    pub async fn update_scheduling(&self, config: &crate::proxy::config::ProxyConfig) {
+        self.token_manager.update_scheduling(config.scheduling.clone()).await;
+    }
+
+    /// 更新账号分组路由规则
+    pub async fn update_routing_rules(&self, config: &crate::proxy::config::ProxyConfig) {
+        self.token_manager.update_routing_rules(config.routing_rules.clone()).await;
+    }
+
+    /// 更新客户端认证配置 (API Key / 关闭校验)
+    pub async fn update_auth(&self, config: &crate::proxy::config::ProxyConfig) {
+        let mut auth = self.auth.write().await;
+        *auth = ProxyAuthConfig::from_proxy_config(config);
+        tracing::info!("客户端认证配置已热更新");
+    }
+
+    /// 更新请求限流配置
+    pub async fn update_rate_limit(&self, config: &crate::proxy::config::ProxyConfig) {
+        let mut rate_limit = self.rate_limit.write().await;
+        *rate_limit = config.rate_limit.clone();
+        tracing::info!("请求限流配置已热更新");
+    }
+
+    /// 更新来源 IP 访问控制；规则无效时保留原配置
+    pub async fn update_ip_access(&self, config: &crate::proxy::config::ProxyConfig) -> Result<(), String> {
+        let acl = IpAccessControl::from_config(&config.ip_access)?;
+        *self.ip_access.write().await = acl;
+        tracing::info!("IP 访问控制已热更新");
+        Ok(())
+    }
+
+    /// 当前 TLS 证书的 SHA-256 指纹 (未启用 TLS 时为 None)
+    pub fn tls_fingerprint(&self) -> Option<String> {
+        self.tls.as_ref().map(|tls| tls.fingerprint())
+    }
+
+    /// 启动 Axum 服务器 (各项配置均取自 ProxyConfig，后续新增配置无需改动签名)
     pub async fn start(
-        host: String,
-        port: u16,
+        config: &crate::proxy::config::ProxyConfig,
         token_manager: Arc<TokenManager>,
-        anthropic_mapping: std::collections::HashMap<String, String>,
-        openai_mapping: std::collections::HashMap<String, String>,
-        custom_mapping: std::collections::HashMap<String, String>,
-        _request_timeout: u64,
-        upstream_proxy: crate::proxy::config::UpstreamProxyConfig,
     ) -> Result<(Self, tokio::task::JoinHandle<()>), String> {
-        let mapping_state = Arc::new(tokio::sync::RwLock::new(anthropic_mapping));
-        let openai_mapping_state = Arc::new(tokio::sync::RwLock::new(openai_mapping));
-        let custom_mapping_state = Arc::new(tokio::sync::RwLock::new(custom_mapping));
+        let host = config.get_bind_address().to_string();
+        let port = config.port;
+        let upstream_proxy = config.upstream_proxy.clone();
+        let cors = &config.cors;
+        let mapping_state = Arc::new(tokio::sync::RwLock::new(config.anthropic_mapping.clone()));
+        let openai_mapping_state = Arc::new(tokio::sync::RwLock::new(config.openai_mapping.clone()));
+        let custom_mapping_state = Arc::new(tokio::sync::RwLock::new(config.custom_mapping.clone()));
         let proxy_state = Arc::new(tokio::sync::RwLock::new(upstream_proxy.clone()));
+        let auth_state = Arc::new(tokio::sync::RwLock::new(ProxyAuthConfig::from_proxy_config(config)));
+        let data_dir = crate::modules::account::get_data_dir()?;
+        let usage_db = data_dir.join("usage.db");
+        let usage = crate::proxy::admin::UsageStore::open(&usage_db)?;
+        let rate_limit_state = Arc::new(tokio::sync::RwLock::new(config.rate_limit.clone()));
+        let ip_access_state = Arc::new(tokio::sync::RwLock::new(IpAccessControl::from_config(&config.ip_access)?));
+        let tls_state = TlsState::from_config(&config.tls, &data_dir)?;
+        let response_store = crate::proxy::common::response_store::ResponseStore::open(&config.response_store, &data_dir)?;
 
         let state = AppState {
             token_manager: token_manager.clone(),
@@ -84,11 +149,19 @@ impl AxumServer {
             thought_signature_map: Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new())),
             upstream_proxy: proxy_state.clone(),
             upstream: Arc::new(crate::proxy::upstream::client::UpstreamClient::new(Some(upstream_proxy.clone()))),
+            auth: auth_state.clone(),
+            usage,
+            rate_limit: rate_limit_state.clone(),
+            rate_limiter: Arc::new(crate::proxy::common::rate_limiter::RateLimiter::new()),
+            admin_sessions: Arc::new(crate::proxy::middleware::AdminSessions::new()),
+            ip_access: ip_access_state.clone(),
+            response_store,
+            tls_enabled: tls_state.is_some(),
         };
 
         // 构建路由 - 使用新架构的 handlers！
         use crate::proxy::handlers;
-        use crate::proxy::middleware::admin_login;
+        use crate::proxy::middleware::{admin_auth, admin_login};
 
         // 无需认证的路由
         let public_routes = Router::new()
@@ -97,7 +170,8 @@ impl AxumServer {
             .route("/admin/", get(handlers::admin::serve_admin_ui))
             .route("/admin/icon.png", get(handlers::admin::serve_icon))
             // 登录API
-            .route("/api/admin/login", post(admin_login));
+            .route("/api/admin/login", post(admin_login))
+            .route("/api/admin/logout", post(admin_auth::admin_logout));
 
         // 需要认证的Admin API路由
         let admin_routes = Router::new()
@@ -105,28 +179,38 @@ impl AxumServer {
             .route("/api/admin/config", post(handlers::admin::update_config))
             .route("/api/admin/config/export", get(handlers::admin::export_config))
             .route("/api/admin/config/import", post(handlers::admin::import_config))
+            .route("/api/admin/keys", get(handlers::admin::list_client_keys).post(handlers::admin::create_client_key))
+            .route("/api/admin/keys/:id", axum::routing::put(handlers::admin::update_client_key).delete(handlers::admin::delete_client_key))
             .route("/api/admin/stats", get(handlers::admin::get_stats))
+            .route("/api/admin/usage", get(handlers::admin::get_usage))
             .route("/api/admin/accounts", get(handlers::admin::list_accounts))
             .route("/api/admin/accounts", post(handlers::admin::add_account))
+            .route("/api/admin/accounts/health", get(handlers::admin::get_accounts_health))
             .route("/api/admin/accounts/:id", axum::routing::delete(handlers::admin::delete_account))
             .route("/api/admin/accounts/:id/switch", post(handlers::admin::switch_account))
             .route("/api/admin/accounts/:id/refresh-quota", post(handlers::admin::refresh_account_quota))
+            .route("/api/admin/accounts/:id/tags", axum::routing::put(handlers::admin::update_account_tags))
+            .route("/api/admin/accounts/:id/disable", post(handlers::admin::disable_account))
+            .route("/api/admin/accounts/:id/enable", post(handlers::admin::enable_account))
+            .route("/api/admin/accounts/:id/drain", post(handlers::admin::drain_account))
+            .route("/api/admin/accounts/:id/health/reset", post(handlers::admin::reset_account_health))
             .route("/api/admin/status", get(handlers::admin::get_status))
+            .route("/api/admin/password", post(admin_auth::change_admin_password))
+            .route("/api/admin/sessions", get(admin_auth::list_admin_sessions).delete(admin_auth::revoke_other_admin_sessions))
+            .route("/api/admin/sessions/:id", axum::routing::delete(admin_auth::revoke_admin_session))
             .layer(axum::middleware::from_fn_with_state(
                 state.clone(),
                 crate::proxy::middleware::admin_auth_middleware
             ));
 
-        // 构建完整路由
-        let app = Router::new()
-            .merge(public_routes)
-            .merge(admin_routes)
-
+        // 需要客户端 API Key 的协议路由
+        let api_routes = Router::new()
             // OpenAI Protocol
             .route("/v1/models", get(handlers::openai::handle_list_models))
             .route("/v1/chat/completions", post(handlers::openai::handle_chat_completions))
             .route("/v1/completions", post(handlers::openai::handle_completions))
-            .route("/v1/responses", post(handlers::openai::handle_completions)) // 兼容 Codex CLI
+            .route("/v1/responses", post(handlers::responses::handle_responses))
+            .route("/v1/responses/:id", get(handlers::responses::handle_get_response).delete(handlers::responses::handle_delete_response))
 
             // Claude Protocol
             .route("/v1/messages", post(handlers::claude::handle_messages))
@@ -138,12 +222,36 @@ impl AxumServer {
             // Handle both GET (get info) and POST (generateContent with colon) at the same route
             .route("/v1beta/models/:model", get(handlers::gemini::handle_get_model).post(handlers::gemini::handle_generate))
             .route("/v1beta/models/:model/countTokens", post(handlers::gemini::handle_count_tokens)) // Specific route priority
+            // 后添加的 layer 先执行：认证 -> 限流 -> 预算
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::proxy::middleware::budget_middleware
+            ))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::proxy::middleware::rate_limit_middleware
+            ))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::proxy::middleware::auth_middleware
+            ))
+            .layer(crate::proxy::middleware::cors_layer(&cors.api)?);
+
+        // 管理后台 (含登录页) 使用独立的、默认更严格的跨域策略
+        let admin_cors = crate::proxy::middleware::cors_layer(&cors.admin)?;
+
+        // 构建完整路由
+        let app = Router::new()
+            .merge(public_routes.merge(admin_routes).layer(admin_cors))
+            .merge(api_routes)
             .route("/healthz", get(health_check_handler))
             .layer(DefaultBodyLimit::max(100 * 1024 * 1024))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::proxy::middleware::ip_filter_middleware
+            ))
             .layer(TraceLayer::new_for_http())
             .layer(axum::middleware::from_fn(crate::proxy::middleware::stats_middleware))
-            .layer(axum::middleware::from_fn(crate::proxy::middleware::auth_middleware))
-            .layer(crate::proxy::middleware::cors_layer())
             .with_state(state);
 
         // 绑定地址
@@ -152,42 +260,77 @@ impl AxumServer {
             .await
             .map_err(|e| format!("地址 {} 绑定失败: {}", addr, e))?;
         
-        tracing::info!("反代服务器启动在 http://{}", addr);
+        match &tls_state {
+            Some(tls) => {
+                tracing::info!("反代服务器启动在 https://{}", addr);
+                tracing::info!("TLS 证书 SHA-256 指纹: {}", tls.fingerprint());
+            }
+            None => tracing::info!("反代服务器启动在 http://{}", addr),
+        }
         
         // 创建关闭通道
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
         
+        let mut background_tasks = vec![
+            token_manager.start_background_refresher(),
+            token_manager.start_quota_scheduler(),
+        ];
+        match token_manager.start_account_watcher() {
+            Ok(handle) => background_tasks.push(handle),
+            Err(e) => tracing::warn!("账号目录热重载不可用: {}", e),
+        }
+        if let Some(tls) = &tls_state {
+            match tls.start_watcher() {
+                Ok(handle) => background_tasks.push(handle),
+                Err(e) => tracing::warn!("TLS 证书热重载不可用: {}", e),
+            }
+        }
+
         let server_instance = Self {
             shutdown_tx: Some(shutdown_tx),
             anthropic_mapping: mapping_state.clone(),
             openai_mapping: openai_mapping_state.clone(),
             custom_mapping: custom_mapping_state.clone(),
             proxy_state,
+            token_manager: token_manager.clone(),
+            auth: auth_state,
+            rate_limit: rate_limit_state,
+            ip_access: ip_access_state.clone(),
+            tls: tls_state.clone(),
+            background_tasks,
         };
         
         // 在新任务中启动服务器
         let handle = tokio::spawn(async move {
             use hyper_util::rt::TokioIo;
-            use hyper::server::conn::http1;
-            use hyper_util::service::TowerToHyperService;
 
             loop {
                 tokio::select! {
                     res = listener.accept() => {
                         match res {
-                            Ok((stream, _)) => {
-                                let io = TokioIo::new(stream);
-                                let service = TowerToHyperService::new(app.clone());
-                                
-                                tokio::task::spawn(async move {
-                                    if let Err(err) = http1::Builder::new()
-                                        .serve_connection(io, service)
-                                        .with_upgrades() // 支持 WebSocket (如果以后需要)
-                                        .await
-                                    {
-                                        debug!("连接处理结束或出错: {:?}", err);
+                            Ok((stream, remote_addr)) => {
+                                // 不在任何允许列表中的来源直接断开 (受信任代理在请求级按 X-Forwarded-For 检查)
+                                if !ip_access_state.read().await.accepts_connection(remote_addr.ip()) {
+                                    debug!("拒绝来自 {} 的连接 (IP 访问控制)", remote_addr);
+                                    drop(stream);
+                                    continue;
+                                }
+                                let app = app.clone();
+                                match tls_state.as_ref().map(|tls| tls.acceptor()) {
+                                    Some(acceptor) => {
+                                        tokio::task::spawn(async move {
+                                            // 握手放在连接任务中，避免慢客户端阻塞 accept 循环
+                                            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                                Ok(Ok(tls_stream)) => serve_connection(TokioIo::new(tls_stream), app, remote_addr).await,
+                                                Ok(Err(e)) => debug!("TLS 握手失败 {}: {}", remote_addr, e),
+                                                Err(_) => debug!("TLS 握手超时 {}", remote_addr),
+                                            }
+                                        });
                                     }
-                                });
+                                    None => {
+                                        tokio::task::spawn(serve_connection(TokioIo::new(stream), app, remote_addr));
+                                    }
+                                }
                             }
                             Err(e) => {
                                 error!("接收连接失败: {:?}", e);
@@ -213,6 +356,34 @@ impl AxumServer {
         if let Some(tx) = self.shutdown_tx.take() {
             let _ = tx.send(());
         }
+        for handle in self.background_tasks.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+/// 在单个连接上运行 HTTP/1.1 服务 (明文或 TLS)
+async fn serve_connection<I>(io: I, app: Router, remote_addr: std::net::SocketAddr)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    use hyper::server::conn::http1;
+    use hyper_util::service::TowerToHyperService;
+
+    // 注入来源地址，供限流等中间件按 IP 区分
+    let service = TowerToHyperService::new(tower::ServiceExt::map_request(
+        app,
+        move |mut req: axum::http::Request<hyper::body::Incoming>| {
+            req.extensions_mut().insert(axum::extract::ConnectInfo(remote_addr));
+            req
+        },
+    ));
+    if let Err(err) = http1::Builder::new()
+        .serve_connection(io, service)
+        .with_upgrades() // 支持 WebSocket (如果以后需要)
+        .await
+    {
+        debug!("连接处理结束或出错: {:?}", err);
     }
 }
 
@@ -0,0 +1,266 @@
+// 监听端口 TLS：加载 PEM 证书 / 生成自签名证书，证书文件变更时自动重新加载
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{crypto::ring, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::proxy::config::{TlsConfig, TlsMode};
+
+/// 证书文件变更后的防抖时间
+const CERT_WATCH_DEBOUNCE_MS: u64 = 500;
+
+/// 证书 SHA-256 指纹 (AA:BB:... 格式，便于客户端核对自签名证书)
+pub fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// 读取 PEM 证书链与私钥，返回 (TLS 配置, 叶子证书指纹)
+fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<(Arc<ServerConfig>, String), String> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("读取证书失败 {:?}: {}", cert_path, e))?;
+    let leaf = certs.first().ok_or_else(|| format!("证书文件中没有证书: {:?}", cert_path))?;
+    let fingerprint = fingerprint(leaf);
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("读取私钥失败 {:?}: {}", key_path, e))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS 配置失败: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("证书与私钥不匹配: {}", e))?;
+    // 服务端仅支持 HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok((Arc::new(config), fingerprint))
+}
+
+/// 确保自签名证书存在 (SAN 未变化时复用，保证重启后指纹不变；SAN 变化时重新生成)
+fn ensure_self_signed(dir: &Path, hosts: &[String]) -> Result<(PathBuf, PathBuf), String> {
+    let cert_path = dir.join("self_signed_cert.pem");
+    let key_path = dir.join("self_signed_key.pem");
+    // 证书旁记录生成时使用的 SAN 列表 (每行一个)
+    let sans_path = dir.join("self_signed_sans.txt");
+
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    for host in hosts.iter().map(|h| h.trim()).filter(|h| !h.is_empty()) {
+        if !names.iter().any(|n| n == host) {
+            names.push(host.to_string());
+        }
+    }
+    let sans = names.join("\n");
+
+    if cert_path.exists() && key_path.exists() {
+        match std::fs::read_to_string(&sans_path) {
+            Ok(existing) if existing == sans => return Ok((cert_path, key_path)),
+            _ => tracing::info!("自签名证书的 SAN 与配置不一致，重新生成"),
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names.clone())
+        .map_err(|e| format!("生成自签名证书失败: {}", e))?;
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("创建证书目录失败: {}", e))?;
+    write_private_key(&key_path, &certified.key_pair.serialize_pem())?;
+    std::fs::write(&cert_path, certified.cert.pem()).map_err(|e| format!("保存证书失败: {}", e))?;
+    std::fs::write(&sans_path, &sans).map_err(|e| format!("保存证书 SAN 列表失败: {}", e))?;
+    tracing::info!("已生成自签名证书 {:?} (SAN: {})", cert_path, names.join(", "));
+    Ok((cert_path, key_path))
+}
+
+/// 写入私钥；Unix 下文件创建时即为 0600，不经过默认 umask 权限
+fn write_private_key(path: &Path, pem: &str) -> Result<(), String> {
+    use std::io::Write;
+
+    // 移除旧文件，确保新文件按下方权限创建
+    let _ = std::fs::remove_file(path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(pem.as_bytes()))
+        .map_err(|e| format!("保存私钥失败: {}", e))
+}
+
+/// 运行中的 TLS 状态；证书重新加载后新连接立即使用新证书
+pub struct TlsState {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+    fingerprint: RwLock<String>,
+}
+
+impl TlsState {
+    /// 按配置加载证书；未启用 TLS 时返回 None
+    pub fn from_config(config: &TlsConfig, data_dir: &Path) -> Result<Option<Arc<Self>>, String> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let (cert_path, key_path) = match config.mode {
+            TlsMode::Files => match (&config.cert_path, &config.key_path) {
+                (Some(cert), Some(key)) if !cert.is_empty() && !key.is_empty() => {
+                    (PathBuf::from(cert), PathBuf::from(key))
+                }
+                _ => return Err("已启用 TLS，但未配置 cert_path / key_path".to_string()),
+            },
+            TlsMode::SelfSigned => ensure_self_signed(&data_dir.join("tls"), &config.self_signed_hosts)?,
+        };
+
+        let (server_config, fingerprint) = load_server_config(&cert_path, &key_path)?;
+        Ok(Some(Arc::new(Self {
+            cert_path,
+            key_path,
+            acceptor: RwLock::new(TlsAcceptor::from(server_config)),
+            fingerprint: RwLock::new(fingerprint),
+        })))
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.fingerprint.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// 重新读取证书文件；失败时保留当前证书
+    pub fn reload(&self) -> Result<(), String> {
+        let (server_config, fingerprint) = load_server_config(&self.cert_path, &self.key_path)?;
+        *self.acceptor.write().unwrap_or_else(|e| e.into_inner()) = TlsAcceptor::from(server_config);
+        *self.fingerprint.write().unwrap_or_else(|e| e.into_inner()) = fingerprint;
+        Ok(())
+    }
+
+    /// 监听证书 / 私钥文件变更并自动重新加载
+    pub fn start_watcher(self: &Arc<Self>) -> Result<tokio::task::JoinHandle<()>, String> {
+        use notify::{EventKind, RecursiveMode, Watcher};
+
+        // 监听所在目录而非文件本身：证书续期工具通常以替换 / 重命名的方式写入
+        let mut dirs: Vec<PathBuf> = [&self.cert_path, &self.key_path]
+            .iter()
+            .filter_map(|p| p.parent().map(|d| if d.as_os_str().is_empty() { PathBuf::from(".") } else { d.to_path_buf() }))
+            .collect();
+        dirs.dedup();
+
+        let watched: Vec<PathBuf> = [&self.cert_path, &self.key_path]
+            .iter()
+            .filter_map(|p| p.file_name().map(PathBuf::from))
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            match res {
+                Ok(event) => {
+                    let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                        && event.paths.iter().any(|p| p.file_name().is_some_and(|n| watched.iter().any(|w| w.as_os_str() == n)));
+                    if relevant {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(e) => tracing::warn!("证书文件监听出错: {}", e),
+            }
+        }).map_err(|e| format!("创建文件监听失败: {}", e))?;
+        for dir in &dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("监听证书目录失败 {:?}: {}", dir, e))?;
+        }
+
+        let state = self.clone();
+        Ok(tokio::spawn(async move {
+            let _watcher = watcher;
+            while rx.recv().await.is_some() {
+                // 防抖：证书与私钥通常先后写入，等两者都写完再加载
+                loop {
+                    match tokio::time::timeout(std::time::Duration::from_millis(CERT_WATCH_DEBOUNCE_MS), rx.recv()).await {
+                        Ok(Some(())) => continue,
+                        Ok(None) => return,
+                        Err(_) => break,
+                    }
+                }
+                match state.reload() {
+                    Ok(()) => tracing::info!("TLS 证书已重新加载，SHA-256 指纹: {}", state.fingerprint()),
+                    Err(e) => tracing::error!("TLS 证书重新加载失败，继续使用旧证书: {}", e),
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_generated_once_and_reloadable() {
+        let dir = std::env::temp_dir().join(format!("ag-tls-test-{}", uuid::Uuid::new_v4()));
+        let config = TlsConfig {
+            enabled: true,
+            mode: TlsMode::SelfSigned,
+            self_signed_hosts: vec!["192.168.1.10".to_string()],
+            ..Default::default()
+        };
+
+        let state = TlsState::from_config(&config, &dir).unwrap().unwrap();
+        let first = state.fingerprint();
+        assert_eq!(first.len(), 32 * 3 - 1);
+
+        // 再次启动复用已有证书，指纹不变
+        let again = TlsState::from_config(&config, &dir).unwrap().unwrap();
+        assert_eq!(again.fingerprint(), first);
+
+        // 替换证书文件后重新加载得到新指纹
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.join("tls/self_signed_cert.pem"), certified.cert.pem()).unwrap();
+        std::fs::write(dir.join("tls/self_signed_key.pem"), certified.key_pair.serialize_pem()).unwrap();
+        state.reload().unwrap();
+        assert_ne!(state.fingerprint(), first);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_self_signed_regenerated_when_hosts_change() {
+        let dir = std::env::temp_dir().join(format!("ag-tls-test-{}", uuid::Uuid::new_v4()));
+        let mut config = TlsConfig {
+            enabled: true,
+            mode: TlsMode::SelfSigned,
+            self_signed_hosts: vec!["192.168.1.10".to_string()],
+            ..Default::default()
+        };
+        let first = TlsState::from_config(&config, &dir).unwrap().unwrap().fingerprint();
+
+        config.self_signed_hosts.push("192.168.1.20".to_string());
+        let second = TlsState::from_config(&config, &dir).unwrap().unwrap().fingerprint();
+        assert_ne!(second, first);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("tls/self_signed_sans.txt")).unwrap(),
+            "localhost\n127.0.0.1\n192.168.1.10\n192.168.1.20"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.join("tls/self_signed_key.pem")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_disabled_or_missing_paths() {
+        assert!(TlsState::from_config(&TlsConfig::default(), Path::new(".")).unwrap().is_none());
+        let config = TlsConfig { enabled: true, ..Default::default() };
+        assert!(TlsState::from_config(&config, Path::new(".")).is_err());
+    }
+}
@@ -1,9 +1,151 @@
 // 移除冗余的顶层导入，因为这些在代码中已由 full path 或局部导入处理
 use dashmap::DashMap;
-use std::path::PathBuf;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use rand::Rng;
+use tokio::sync::{Notify, OwnedSemaphorePermit, RwLock, Semaphore};
+
+use crate::models::AccountStatus;
+use crate::models::quota::ModelQuota;
+use crate::proxy::config::{RoutingRule, SchedulingConfig, SchedulingStrategy};
+
+/// 配额相近的账号视为同一梯队，在梯队内轮询以分摊负载 (单位: 百分点)
+const QUOTA_TIER_TOLERANCE: i32 = 10;
+
+/// 上游 429 未给出 retryDelay 时的默认冷却时长
+const DEFAULT_RATE_LIMIT_COOLDOWN_MS: u64 = 30_000;
+/// 连续多少次 401/403 后打开熔断
+const CIRCUIT_BREAKER_THRESHOLD: u32 = 3;
+/// 熔断打开时长，到期后进入半开状态：放行请求，成功则关闭，再次失败立即重新打开
+const CIRCUIT_OPEN_MS: i64 = 5 * 60 * 1000;
+/// 所有账号都在冷却时，若最早的冷却在该时长内结束则等待，而不是直接报错
+const MAX_COOLDOWN_WAIT_MS: i64 = 10_000;
+/// 延迟 EWMA 的平滑系数 (越大越偏向最近的样本)
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
+
+/// token 提前刷新的余量 (秒)
+const REFRESH_SKEW_SECS: i64 = 300;
+/// 会话亲和表的最大条目数，超出时先清理过期会话，再淘汰最久未活跃的会话
+const MAX_SESSION_BINDINGS: usize = 10_000;
+/// 后台刷新的检查间隔 (秒)
+const BACKGROUND_REFRESH_INTERVAL_SECS: u64 = 60;
+/// 后台刷新的提前量 (秒)：比请求路径上的懒刷新更早，避免请求承担 OAuth 往返
+const BACKGROUND_REFRESH_AHEAD_SECS: i64 = 15 * 60;
+/// 后台刷新的最大并发数
+const BACKGROUND_REFRESH_CONCURRENCY: usize = 4;
+/// 账号目录变更的防抖时长 (毫秒)：目录安静这么久之后才重新加载
+const ACCOUNT_WATCH_DEBOUNCE_MS: u64 = 500;
+/// 配额复查调度的检查间隔 (秒)
+const QUOTA_RECHECK_INTERVAL_SECS: u64 = 60;
+/// 配额重置后延迟多久复查 (秒)，给上游留出刷新配额的时间
+const QUOTA_RECHECK_DELAY_SECS: i64 = 60;
+/// 同一账号两次配额复查的最小间隔 (毫秒)，避免上游重置时间异常时反复查询
+const QUOTA_RECHECK_MIN_GAP_MS: i64 = 5 * 60 * 1000;
+/// 被标记为 forbidden 的账号的复查间隔 (毫秒)
+const FORBIDDEN_RECHECK_MS: i64 = 60 * 60 * 1000;
+
+/// 一次 token 刷新的结果 (在并发等待者之间共享)
+#[derive(Debug, Clone)]
+struct RefreshedToken {
+    access_token: String,
+    refresh_token: String,
+    expires_in: i64,
+    timestamp: i64,
+}
+
+type RefreshFuture = Shared<BoxFuture<'static, Result<RefreshedToken, String>>>;
+
+/// 会话 -> 账号的绑定 (会话亲和)
+#[derive(Debug, Clone)]
+struct SessionBinding {
+    account_id: String,
+    last_seen: std::time::Instant,
+}
+
+/// get_token 的选号结果
+#[derive(Debug)]
+pub struct SelectedToken {
+    pub account_id: String,
+    pub access_token: String,
+    pub project_id: String,
+    pub email: String,
+    /// 账号并发槽位，需持有到上游响应 (含流式响应) 结束
+    pub permit: AccountPermit,
+}
+
+/// 账号并发槽位；释放时唤醒排队等待的请求
+#[derive(Debug)]
+pub struct AccountPermit {
+    _permit: OwnedSemaphorePermit,
+    released: Arc<Notify>,
+}
+
+impl AccountPermit {
+    /// 将槽位绑定到响应流上，流结束 (或客户端断开) 时才释放
+    pub fn attach<S: futures::Stream>(self, stream: S) -> impl futures::Stream<Item = S::Item> {
+        use futures::StreamExt;
+        stream.map(move |item| {
+            let _ = &self;
+            item
+        })
+    }
+}
+
+impl Drop for AccountPermit {
+    fn drop(&mut self) {
+        self.released.notify_waiters();
+    }
+}
+
+/// 账号运行时状态 (健康状态由上游 429/401/403 响应驱动，仅保存在内存中)
+#[derive(Debug, Clone, Default)]
+pub struct AccountHealth {
+    /// 模型 -> 冷却结束时间 (毫秒时间戳)；空字符串表示整个账号冷却
+    pub cooldowns: HashMap<String, i64>,
+    /// 连续认证失败 (401/403) 次数
+    pub consecutive_auth_failures: u32,
+    /// 熔断打开截止时间 (毫秒时间戳)
+    pub circuit_open_until: Option<i64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+    /// 上游响应延迟的指数加权移动平均 (毫秒)
+    pub latency_ewma_ms: Option<f64>,
+    /// 最近一次被选中的时间 (毫秒时间戳)
+    pub last_selected_at: Option<i64>,
+    /// refresh_token 已失效 (invalid_grant)，需要重新登录；不再参与调度
+    pub invalid: bool,
+    /// 最近一次刷新 token 的时间 (毫秒时间戳)
+    pub last_refresh_at: Option<i64>,
+    /// 最近一次刷新失败的原因，成功时清空
+    pub last_refresh_error: Option<String>,
+    /// 最近一次由调度器复查配额的时间 (毫秒时间戳)
+    pub last_quota_check_at: Option<i64>,
+}
+
+impl AccountHealth {
+    /// 指定模型的冷却结束时间 (取账号级与模型级中较晚者)，未冷却返回 None
+    pub fn cooldown_until(&self, model: &str, now_ms: i64) -> Option<i64> {
+        [self.cooldowns.get(""), self.cooldowns.get(model)]
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|until| *until > now_ms)
+            .max()
+    }
+
+    pub fn is_circuit_open(&self, now_ms: i64) -> bool {
+        self.circuit_open_until.is_some_and(|until| until > now_ms)
+    }
+
+    /// 账号当前是否可用于指定模型；返回不可用的截止时间
+    pub fn blocked_until(&self, model: &str, now_ms: i64) -> Option<i64> {
+        let circuit = self.circuit_open_until.filter(|until| *until > now_ms);
+        circuit.into_iter().chain(self.cooldown_until(model, now_ms)).max()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ProxyToken {
@@ -15,13 +157,135 @@ pub struct ProxyToken {
     pub email: String,
     pub account_path: PathBuf,  // 账号文件路径，用于更新
     pub project_id: Option<String>,
+    pub quotas: Vec<ModelQuota>,  // 账号文件中缓存的各模型配额 (来自 fetch_quota)
+    pub tags: Vec<String>,  // 账号分组标签
+    pub draining: bool,  // 排空中：仅服务已绑定的会话，不接收新会话
+    pub forbidden: bool,  // 配额查询返回 403，暂时隔离
+    pub slots: Arc<Semaphore>,  // 并发槽位 (进行中的上游请求)，重新加载账号时沿用
+}
+
+/// 比较账号数据 (不含运行时的并发槽位)
+impl PartialEq for ProxyToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.account_id == other.account_id
+            && self.access_token == other.access_token
+            && self.refresh_token == other.refresh_token
+            && self.expires_in == other.expires_in
+            && self.timestamp == other.timestamp
+            && self.email == other.email
+            && self.account_path == other.account_path
+            && self.project_id == other.project_id
+            && self.quotas == other.quotas
+            && self.tags == other.tags
+            && self.draining == other.draining
+            && self.forbidden == other.forbidden
+    }
+}
+
+impl ProxyToken {
+    /// 是否需要复查配额：forbidden 账号定期复查；配额耗尽的模型在重置时间过后复查
+    fn quota_recheck_due(&self, last_check_ms: Option<i64>, now_ms: i64) -> bool {
+        let checked_within = |gap_ms: i64| last_check_ms.is_some_and(|t| now_ms - t < gap_ms);
+        if self.forbidden {
+            return !checked_within(FORBIDDEN_RECHECK_MS);
+        }
+        let now = now_ms / 1000;
+        let reset_passed = self.quotas.iter().any(|q| {
+            q.percentage <= 0
+                && parse_reset_time(&q.reset_time).is_some_and(|reset| now >= reset + QUOTA_RECHECK_DELAY_SECS)
+        });
+        reset_passed && !checked_within(QUOTA_RECHECK_MIN_GAP_MS)
+    }
+
+    fn model_quota(&self, model: &str) -> Option<&ModelQuota> {
+        self.quotas.iter().find(|q| q.name == model)
+    }
+
+    /// 指定模型的配额是否已耗尽且尚未到重置时间
+    /// 没有该模型的配额记录时视为可用
+    pub fn is_quota_exhausted(&self, model: &str, now: i64) -> bool {
+        match self.model_quota(model) {
+            Some(q) if q.percentage <= 0 => match parse_reset_time(&q.reset_time) {
+                Some(reset_at) => now < reset_at,
+                // 没有重置时间时无法判断何时恢复，保守地视为耗尽，等待下一次配额刷新
+                None => true,
+            },
+            _ => false,
+        }
+    }
+
+    /// 指定模型的剩余配额百分比
+    /// 没有配额记录、或耗尽后已过重置时间的账号按满额计算
+    pub fn remaining_quota(&self, model: &str, now: i64) -> i32 {
+        match self.model_quota(model) {
+            Some(q) if q.percentage > 0 => q.percentage,
+            Some(_) if !self.is_quota_exhausted(model, now) => 100,
+            Some(_) => 0,
+            None => 100,
+        }
+    }
+}
+
+/// 账号是否属于指定分组；未指定分组时所有账号都可用
+fn in_group(token: &ProxyToken, group: Option<&str>) -> bool {
+    group.is_none_or(|g| token.tags.iter().any(|t| t == g))
+}
+
+/// 按并发上限创建槽位，0 表示不限制
+fn new_slots(max_concurrency: usize) -> Arc<Semaphore> {
+    Arc::new(Semaphore::new(slot_capacity(max_concurrency)))
+}
+
+fn slot_capacity(max_concurrency: usize) -> usize {
+    if max_concurrency == 0 { Semaphore::MAX_PERMITS } else { max_concurrency }
+}
+
+/// 原地调整并发槽位容量，进行中的请求继续计入上限
+/// 降低上限时先回收空闲槽位，不足部分待进行中的请求释放后再回收
+fn resize_slots(slots: &Arc<Semaphore>, from: usize, to: usize) {
+    let (from, to) = (slot_capacity(from), slot_capacity(to));
+    if to > from {
+        slots.add_permits(to - from);
+        return;
+    }
+    let deficit = from - to - slots.forget_permits(from - to);
+    if deficit > 0 {
+        let slots = slots.clone();
+        let deficit = u32::try_from(deficit).unwrap_or(u32::MAX);
+        tokio::spawn(async move {
+            if let Ok(permits) = slots.acquire_many_owned(deficit).await {
+                permits.forget();
+            }
+        });
+    }
+}
+
+/// 原子写入账号文件 (先写临时文件再重命名)，避免目录监听读到写了一半的文件
+fn write_account_file(path: &Path, content: &serde_json::Value) -> Result<(), String> {
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(content).unwrap())
+        .map_err(|e| format!("写入临时文件失败: {}", e))?;
+    std::fs::rename(&temp_path, path)
+        .map_err(|e| format!("替换账号文件失败: {}", e))
+}
+
+/// 解析配额重置时间 (RFC 3339)，返回 Unix 时间戳
+fn parse_reset_time(reset_time: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(reset_time)
+        .ok()
+        .map(|t| t.timestamp())
 }
 
 pub struct TokenManager {
     tokens: Arc<DashMap<String, ProxyToken>>,  // account_id -> ProxyToken
+    health: Arc<DashMap<String, AccountHealth>>,  // account_id -> 健康状态
     current_index: Arc<AtomicUsize>,
-    last_used_account: Arc<tokio::sync::Mutex<Option<(String, std::time::Instant)>>>,
+    sessions: Arc<DashMap<String, SessionBinding>>,  // session_id -> 绑定账号 (会话亲和)
     pinned_account: Arc<RwLock<Option<String>>>,
+    scheduling: Arc<RwLock<SchedulingConfig>>,
+    routing_rules: Arc<RwLock<Vec<RoutingRule>>>,
+    refreshing: Arc<DashMap<String, RefreshFuture>>,  // account_id -> 正在进行的刷新 (single-flight)
+    slot_released: Arc<Notify>,  // 任一账号释放并发槽位时通知排队中的请求
     data_dir: PathBuf,
 }
 
@@ -30,9 +294,14 @@ impl TokenManager {
     pub fn new(data_dir: PathBuf) -> Self {
         Self {
             tokens: Arc::new(DashMap::new()),
+            health: Arc::new(DashMap::new()),
             current_index: Arc::new(AtomicUsize::new(0)),
-            last_used_account: Arc::new(tokio::sync::Mutex::new(None)),
+            sessions: Arc::new(DashMap::new()),
             pinned_account: Arc::new(RwLock::new(None)),
+            scheduling: Arc::new(RwLock::new(SchedulingConfig::default())),
+            routing_rules: Arc::new(RwLock::new(Vec::new())),
+            refreshing: Arc::new(DashMap::new()),
+            slot_released: Arc::new(Notify::new()),
             data_dir,
         }
     }
@@ -84,8 +353,8 @@ impl TokenManager {
         let accounts_dir = self.data_dir.join("accounts");
         if !accounts_dir.exists() {
             self.tokens.clear();
-            let mut last_used = self.last_used_account.lock().await;
-            *last_used = None;
+            self.health.clear();
+            self.sessions.clear();
             let mut pinned = self.pinned_account.write().await;
             *pinned = None;
             return Ok(0);
@@ -95,6 +364,8 @@ impl TokenManager {
             .map_err(|e| format!("读取账号目录失败: {}", e))?;
 
         let mut next: HashMap<String, ProxyToken> = HashMap::new();
+        // 文件仍在但读取 / 解析失败 (手动编辑出错或读到写了一半的文件)，保留原账号及其健康状态
+        let mut failed: HashSet<PathBuf> = HashSet::new();
         for entry in entries {
             let entry = entry.map_err(|e| format!("读取目录项失败: {}", e))?;
             let path = entry.path();
@@ -107,35 +378,64 @@ impl TokenManager {
                 }
                 Ok(None) => {}
                 Err(e) => {
-                    tracing::warn!("加载账号失败 {:?}: {}", path, e);
+                    tracing::warn!("加载账号失败 {:?}: {}，保留原有账号状态", path, e);
+                    if path.exists() {
+                        failed.insert(path);
+                    }
                 }
             }
         }
 
         let next_keys: HashSet<String> = next.keys().cloned().collect();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
 
-        // 删除不存在的旧 token
-        let existing_keys: Vec<String> = self.tokens.iter().map(|e| e.key().clone()).collect();
+        // 删除不存在的旧 token (加载失败的文件对应账号保持不变)
+        let existing_keys: Vec<String> = self.tokens.iter()
+            .filter(|e| !failed.contains(&e.account_path))
+            .map(|e| e.key().clone())
+            .collect();
         for key in existing_keys {
             if !next_keys.contains(&key) {
-                self.tokens.remove(&key);
+                if let Some((_, old)) = self.tokens.remove(&key) {
+                    removed.push(old.email);
+                }
+                self.health.remove(&key);
             }
         }
 
-        // 覆盖/新增
-        for (key, token) in next {
+        // 覆盖/新增；refresh_token 变化 (重新登录) 时解除 invalid 标记
+        for (key, mut token) in next {
+            let old = self.tokens.get(&key).map(|old| old.value().clone());
+            if let Some(old) = &old {
+                // 沿用原有并发槽位，保证进行中的请求仍被计数
+                token.slots = old.slots.clone();
+            }
+            match old {
+                None => added.push(token.email.clone()),
+                Some(old) if old != token => {
+                    if old.refresh_token != token.refresh_token {
+                        if let Some(mut health) = self.health.get_mut(&key) {
+                            health.invalid = false;
+                        }
+                    }
+                    changed.push(token.email.clone());
+                }
+                Some(_) => {}
+            }
             self.tokens.insert(key, token);
         }
 
-        // 清理 last_used / pinned 指向的无效账号
-        {
-            let mut last_used = self.last_used_account.lock().await;
-            if let Some((account_id, _)) = &*last_used {
-                if !self.tokens.contains_key(account_id) {
-                    *last_used = None;
-                }
-            }
+        if !(added.is_empty() && removed.is_empty() && changed.is_empty()) {
+            tracing::info!(
+                "账号池已重新加载: 新增 {:?}, 移除 {:?}, 变更 {:?} (共 {} 个)",
+                added, removed, changed, self.tokens.len()
+            );
         }
+
+        // 清理会话绑定 / pinned 指向的无效账号
+        self.sessions.retain(|_, binding| self.tokens.contains_key(&binding.account_id));
         {
             let mut pinned = self.pinned_account.write().await;
             if let Some(account_id) = pinned.as_ref() {
@@ -158,6 +458,42 @@ impl TokenManager {
         self.pinned_account.read().await.clone()
     }
 
+    /// 更新调度策略 (热更新，立即对后续请求生效)
+    pub async fn update_scheduling(&self, config: SchedulingConfig) {
+        let mut scheduling = self.scheduling.write().await;
+        tracing::info!(
+            "调度策略已更新: {:?} (会话亲和窗口 {}s, 单账号并发上限 {})",
+            config.strategy, config.sticky_window_secs, config.max_concurrency_per_account
+        );
+        // 并发上限变化时原地调整槽位，避免进行中的请求不再计数导致瞬时超发
+        if config.max_concurrency_per_account != scheduling.max_concurrency_per_account {
+            for entry in self.tokens.iter() {
+                resize_slots(&entry.slots, scheduling.max_concurrency_per_account, config.max_concurrency_per_account);
+            }
+            self.slot_released.notify_waiters();
+        }
+        *scheduling = config;
+    }
+
+    pub async fn scheduling(&self) -> SchedulingConfig {
+        self.scheduling.read().await.clone()
+    }
+
+    /// 更新账号分组路由规则 (热更新)
+    pub async fn update_routing_rules(&self, rules: Vec<RoutingRule>) {
+        let mut routing_rules = self.routing_rules.write().await;
+        tracing::info!("账号分组路由规则已更新: {} 条", rules.len());
+        *routing_rules = rules;
+    }
+
+    /// 按路由规则解析请求允许使用的账号分组；未命中任何规则时不限制
+    async fn resolve_group(&self, request_type: &str, target_model: &str) -> Option<String> {
+        self.routing_rules.read().await
+            .iter()
+            .find(|rule| rule.matches(target_model, request_type))
+            .map(|rule| rule.group.clone())
+    }
+
     /// 加载单个账号
     async fn load_single_account(&self, path: &PathBuf) -> Result<Option<ProxyToken>, String> {
         let content = std::fs::read_to_string(path)
@@ -169,6 +505,15 @@ impl TokenManager {
         let account_id = account["id"].as_str()
             .ok_or("缺少 id 字段")?
             .to_string();
+
+        // 停用的账号不进入账号池
+        let status: AccountStatus = account.get("status")
+            .and_then(|s| serde_json::from_value(s.clone()).ok())
+            .unwrap_or_default();
+        if status == AccountStatus::Disabled {
+            tracing::debug!("账号 {} 已停用，跳过加载", account_id);
+            return Ok(None);
+        }
         
         let email = account["email"].as_str()
             .ok_or("缺少 email 字段")?
@@ -195,6 +540,21 @@ impl TokenManager {
         let project_id = token_obj.get("project_id")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
+
+        // 配额信息是可选的 (尚未查询过配额的账号没有该字段)
+        let quotas: Vec<ModelQuota> = account.get("quota")
+            .and_then(|q| q.get("models"))
+            .and_then(|m| serde_json::from_value(m.clone()).ok())
+            .unwrap_or_default();
+
+        let forbidden = account.get("quota")
+            .and_then(|q| q.get("is_forbidden"))
+            .and_then(|f| f.as_bool())
+            .unwrap_or(false);
+
+        let tags: Vec<String> = account.get("tags")
+            .and_then(|t| serde_json::from_value(t.clone()).ok())
+            .unwrap_or_default();
         
         Ok(Some(ProxyToken {
             account_id,
@@ -205,93 +565,111 @@ impl TokenManager {
             email,
             account_path: path.clone(),
             project_id,
+            quotas,
+            tags,
+            draining: status == AccountStatus::Draining,
+            forbidden,
+            slots: new_slots(self.scheduling.read().await.max_concurrency_per_account),
         }))
     }
     
-    /// 获取当前可用的 Token（带 60s 时间窗口锁定机制 + pin 账号支持）
-    /// 参数 `quota_group` 用于区分 "claude" vs "gemini" 组
+    /// 获取当前可用的 Token（会话亲和 + pin 账号支持，其余按调度策略选择）
+    /// 参数 `quota_group` 为请求类型 (agent / web_search / image_gen)，同时用于匹配账号分组路由规则
+    /// 参数 `target_model` 为映射后的上游模型名，用于按配额与冷却状态筛选账号 (传空串表示不区分模型)
     /// 参数 `force_rotate` 为 true 时将忽略锁定，强制切换账号
-    pub async fn get_token(&self, quota_group: &str, force_rotate: bool) -> Result<(String, String, String), String> {
+    /// 参数 `session_id` 为会话标识：同一会话在空闲窗口内固定使用同一账号 (保持 prompt cache 与 thought signature)，
+    /// 不同会话之间按调度策略分散到整个账号池
+    pub async fn get_token(&self, quota_group: &str, target_model: &str, force_rotate: bool, session_id: Option<&str>) -> Result<SelectedToken, String> {
+        self.get_token_in_group(quota_group, target_model, force_rotate, session_id, None).await
+    }
+
+    /// 同 `get_token`，`pinned_group` 不为空时只在该账号分组内选号 (客户端 Key 固定分组，优先于分组路由规则)
+    pub async fn get_token_in_group(
+        &self,
+        quota_group: &str,
+        target_model: &str,
+        force_rotate: bool,
+        session_id: Option<&str>,
+        pinned_group: Option<&str>,
+    ) -> Result<SelectedToken, String> {
         let total = self.tokens.len();
         if total == 0 {
             return Err("Token pool is empty".to_string());
         }
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let scheduling = self.scheduling.read().await.clone();
+        let group = match pinned_group {
+            Some(g) => Some(g.to_string()),
+            None => self.resolve_group(quota_group, target_model).await,
+        };
+        let group = group.as_deref();
 
         // 0. 如果有 pin 且不强制轮换，优先使用指定账号
-        let mut target_token: Option<ProxyToken> = None;
+        let mut target: Option<(ProxyToken, AccountPermit)> = None;
         if !force_rotate {
             if let Some(pinned_id) = self.pinned_account.read().await.clone() {
                 if let Some(entry) = self.tokens.get(&pinned_id) {
-                    tracing::info!("Pinned 账号生效: {}", entry.email);
-                    target_token = Some(entry.value().clone());
+                    let permit = (!entry.draining && self.is_selectable(entry.value(), target_model, group, now_ms))
+                        .then(|| self.try_acquire_slot(entry.value()))
+                        .flatten();
+                    if let Some(permit) = permit {
+                        tracing::info!("Pinned 账号生效: {}", entry.email);
+                        target = Some((entry.value().clone(), permit));
+                    } else {
+                        tracing::warn!("Pinned 账号 {} 对 {} 暂不可用 (配额耗尽/冷却/熔断/不在允许分组/排空中/并发已满)，临时改用其他账号", entry.email, target_model);
+                    }
                 } else {
                     tracing::warn!("Pinned 账号不存在于池中: {}", pinned_id);
                 }
             }
         }
 
-        // 1. 检查时间窗口锁定 (60秒内强制复用上一个账号)
-        // 优化策略: 画图请求 (image_gen) 默认不锁定，以最大化并发能力
-        if target_token.is_none() && !force_rotate && quota_group != "image_gen" {
-            let last_used = self.last_used_account.lock().await;
-            if let Some((account_id, last_time)) = &*last_used {
-                if last_time.elapsed().as_secs() < 60 {
-                    if let Some(entry) = self.tokens.get(account_id) {
-                        tracing::info!("60s 时间窗口内，强制复用上一个账号: {}", entry.email);
-                        target_token = Some(entry.value().clone());
+        // 1. 会话亲和：同一会话在空闲窗口内复用已绑定的账号 (窗口长度可配置，0 表示关闭)
+        // 优化策略: 画图请求 (image_gen) 不绑定，以最大化并发能力
+        let session_id = session_id
+            .filter(|_| quota_group != "image_gen" && scheduling.sticky_window_secs > 0);
+        if target.is_none() && !force_rotate {
+            if let Some(sid) = session_id {
+                let bound = self.sessions.get(sid)
+                    .filter(|b| b.last_seen.elapsed().as_secs() < scheduling.sticky_window_secs)
+                    .map(|b| b.account_id.clone());
+                if let Some(entry) = bound.and_then(|account_id| self.tokens.get(&account_id)) {
+                    // 绑定账号对当前模型已无配额、处于冷却或并发已满时改绑其他账号
+                    if self.is_selectable(entry.value(), target_model, group, now_ms) {
+                        if let Some(permit) = self.try_acquire_slot(entry.value()) {
+                            tracing::debug!("会话 {} 复用绑定账号: {}", sid, entry.email);
+                            target = Some((entry.value().clone(), permit));
+                        }
                     }
                 }
             }
         }
 
-        // 2. 如果没有锁定、锁定失效或强制轮换，则进行轮询记录并更新锁定信息
-        let mut token = if let Some(t) = target_token {
-            // 如果是 pin 模式，同样更新 last_used（用于复用统计/日志一致性）
-            if !force_rotate && quota_group != "image_gen" {
-                let mut last_used = self.last_used_account.lock().await;
-                *last_used = Some((t.account_id.clone(), std::time::Instant::now()));
+        // 2. 如果没有绑定、绑定失效或强制轮换，则按调度策略选择账号
+        let (mut token, permit) = match target {
+            Some(t) => t,
+            None => {
+                let (selected_token, permit) = self.select_with_slot(&scheduling, target_model, group).await?;
+                let action_msg = if force_rotate { "强制切换" } else { "切换" };
+                tracing::info!("{}到账号: {} (策略: {:?})", action_msg, selected_token.email, scheduling.strategy);
+                (selected_token, permit)
             }
-            t
-        } else {
-            // 简单轮换策略 (Round Robin)
-            let idx = self.current_index.fetch_add(1, Ordering::SeqCst) % total;
-            let selected_token = self.tokens.iter()
-                .nth(idx)
-                .map(|entry| entry.value().clone())
-                .ok_or("Failed to retrieve token from pool")?;
-
-            // 更新最后使用的账号及时间 (如果是普通对话请求)
-            if quota_group != "image_gen" {
-                let mut last_used = self.last_used_account.lock().await;
-                *last_used = Some((selected_token.account_id.clone(), std::time::Instant::now()));
-            }
-
-            let action_msg = if force_rotate { "强制切换" } else { "切换" };
-            tracing::info!("{}到账号: {}", action_msg, selected_token.email);
-            selected_token
         };
+        self.health.entry(token.account_id.clone()).or_default().last_selected_at = Some(now_ms);
+        if let Some(sid) = session_id {
+            self.bind_session(sid, &token.account_id, scheduling.sticky_window_secs);
+        }
         
         // 3. 检查 token 是否过期（提前5分钟刷新）
         let now = chrono::Utc::now().timestamp();
-        if now >= token.timestamp - 300 {
-            tracing::info!("账号 {} 的 token 即将过期，正在刷新...", token.email);
-            
-            // 调用 OAuth 刷新 token
-            match crate::modules::oauth::refresh_access_token(&token.refresh_token).await {
-                Ok(token_response) => {
-                    tracing::info!("Token 刷新成功！");
-                    
+        if now >= token.timestamp - REFRESH_SKEW_SECS {
+            match self.refresh_token(&token, REFRESH_SKEW_SECS).await {
+                Ok(refreshed) => {
                     // 更新本地内存对象供后续使用
-                    token.access_token = token_response.access_token.clone();
-                    token.expires_in = token_response.expires_in;
-                    token.timestamp = now + token_response.expires_in;
-                    
-                    // 同步更新跨线程共享的 DashMap
-                    if let Some(mut entry) = self.tokens.get_mut(&token.account_id) {
-                        entry.access_token = token.access_token.clone();
-                        entry.expires_in = token.expires_in;
-                        entry.timestamp = token.timestamp;
-                    }
+                    token.access_token = refreshed.access_token;
+                    token.refresh_token = refreshed.refresh_token;
+                    token.expires_in = refreshed.expires_in;
+                    token.timestamp = refreshed.timestamp;
                 }
                 Err(e) => {
                     tracing::error!("Token 刷新失败: {}，尝试下一个账号", e);
@@ -320,9 +698,514 @@ impl TokenManager {
             }
         };
 
-        Ok((token.access_token, project_id, token.email))
+        Ok(SelectedToken {
+            account_id: token.account_id,
+            access_token: token.access_token,
+            project_id,
+            email: token.email,
+            permit,
+        })
+    }
+
+    /// 非阻塞地挑选一个当前有空闲槽位的账号，返回 (access_token, project_id)
+    ///
+    /// 用于 countTokens 等不计费的辅助请求：不占用并发槽位、不排队、不等待冷却、不刷新 token，
+    /// 也不更新会话绑定与选号记录；没有立即可用的账号时返回 None，由调用方退回本地估算
+    pub async fn peek_token(&self, quota_group: &str, target_model: &str, pinned_group: Option<&str>) -> Option<(String, String)> {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let group = match pinned_group {
+            Some(g) => Some(g.to_string()),
+            None => self.resolve_group(quota_group, target_model).await,
+        };
+        self.tokens.iter()
+            .filter(|entry| !entry.draining && entry.slots.available_permits() > 0)
+            .filter(|entry| entry.timestamp - REFRESH_SKEW_SECS > now_ms / 1000)
+            .filter(|entry| self.is_selectable(entry.value(), target_model, group.as_deref(), now_ms))
+            .filter_map(|entry| {
+                let project_id = entry.project_id.clone()?;
+                Some((entry.slots.available_permits(), entry.access_token.clone(), project_id))
+            })
+            .max_by_key(|(available, _, _)| *available)
+            .map(|(_, access_token, project_id)| (access_token, project_id))
+    }
+
+    /// 账号是否可被选中：未被 forbidden 隔离、属于允许的分组、配额未耗尽、未冷却、熔断未打开
+    fn is_selectable(&self, token: &ProxyToken, target_model: &str, group: Option<&str>, now_ms: i64) -> bool {
+        if token.forbidden || !in_group(token, group) || token.is_quota_exhausted(target_model, now_ms / 1000) {
+            return false;
+        }
+        match self.health.get(&token.account_id) {
+            Some(health) => !health.invalid && health.blocked_until(target_model, now_ms).is_none(),
+            None => true,
+        }
+    }
+
+    /// 尝试占用账号的一个并发槽位
+    fn try_acquire_slot(&self, token: &ProxyToken) -> Option<AccountPermit> {
+        token.slots.clone().try_acquire_owned().ok().map(|permit| AccountPermit {
+            _permit: permit,
+            released: self.slot_released.clone(),
+        })
+    }
+
+    /// 按调度策略选号并占用并发槽位
+    /// - 所有可用账号都满载时排队，直到有槽位释放或超过 `queue_timeout_secs`
+    /// - 所有账号都在短暂冷却中时等待最早的一个恢复，避免把瞬时限流直接暴露给客户端
+    async fn select_with_slot(
+        &self,
+        scheduling: &SchedulingConfig,
+        target_model: &str,
+        group: Option<&str>,
+    ) -> Result<(ProxyToken, AccountPermit), String> {
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(scheduling.queue_timeout_secs);
+        let mut cooldown_waited = false;
+        let mut queued = false;
+
+        loop {
+            // 先注册通知再检查，避免错过检查与等待之间释放的槽位
+            let released = self.slot_released.notified();
+            let now_ms = chrono::Utc::now().timestamp_millis();
+
+            let err = match self.select_account(scheduling.strategy, target_model, group, now_ms) {
+                Ok(token) => match self.try_acquire_slot(&token) {
+                    Some(permit) => return Ok((token, permit)),
+                    // 选中后槽位被并发请求抢走，重新选号
+                    None => continue,
+                },
+                Err(e) => e,
+            };
+
+            if self.has_saturated_candidates(target_model, group, now_ms) {
+                if tokio::time::Instant::now() >= deadline {
+                    return Err(format!(
+                        "Timed out after {}s waiting for a free account slot (all accounts are at max concurrency)",
+                        scheduling.queue_timeout_secs
+                    ));
+                }
+                if !queued {
+                    tracing::warn!("所有可用账号并发已满，请求排队等待 (最长 {}s)", scheduling.queue_timeout_secs);
+                    queued = true;
+                }
+                let _ = tokio::time::timeout_at(deadline, released).await;
+                continue;
+            }
+
+            if cooldown_waited {
+                return Err(err);
+            }
+            let wait_ms = self.earliest_recovery(target_model, group, now_ms)
+                .map(|until| until - now_ms)
+                .filter(|wait| *wait <= MAX_COOLDOWN_WAIT_MS)
+                .ok_or(err)?;
+            tracing::warn!("所有账号均在冷却中，等待 {}ms 后重试选号", wait_ms);
+            tokio::time::sleep(std::time::Duration::from_millis(wait_ms as u64)).await;
+            cooldown_waited = true;
+        }
+    }
+
+    /// 是否存在仅因并发已满而无法选中的账号
+    fn has_saturated_candidates(&self, target_model: &str, group: Option<&str>, now_ms: i64) -> bool {
+        self.tokens.iter().any(|entry| {
+            !entry.draining
+                && entry.slots.available_permits() == 0
+                && self.is_selectable(entry.value(), target_model, group, now_ms)
+        })
+    }
+
+    /// 绑定/续期会话到账号；表满时先清理过期会话，仍然超限则淘汰最久未活跃的会话
+    fn bind_session(&self, session_id: &str, account_id: &str, ttl_secs: u64) {
+        self.sessions.insert(session_id.to_string(), SessionBinding {
+            account_id: account_id.to_string(),
+            last_seen: std::time::Instant::now(),
+        });
+
+        if self.sessions.len() > MAX_SESSION_BINDINGS {
+            self.sessions.retain(|_, b| b.last_seen.elapsed().as_secs() < ttl_secs);
+        }
+        while self.sessions.len() > MAX_SESSION_BINDINGS {
+            let oldest = self.sessions.iter()
+                .min_by_key(|b| b.last_seen)
+                .map(|b| b.key().clone());
+            match oldest {
+                Some(key) => { self.sessions.remove(&key); }
+                None => break,
+            }
+        }
+    }
+
+    /// 因冷却/熔断而不可用的账号中，最早恢复的时间
+    fn earliest_recovery(&self, target_model: &str, group: Option<&str>, now_ms: i64) -> Option<i64> {
+        self.tokens.iter()
+            .filter(|entry| !entry.draining && !entry.forbidden && in_group(entry.value(), group))
+            .filter(|entry| !entry.is_quota_exhausted(target_model, now_ms / 1000))
+            .filter_map(|entry| {
+                self.health.get(entry.key())
+                    .and_then(|h| h.blocked_until(target_model, now_ms))
+            })
+            .min()
+    }
+
+    /// 记录上游 429：账号对该模型进入冷却，时长取上游下发的 retryDelay/quotaResetDelay
+    pub fn mark_rate_limited(&self, account_id: &str, model: &str, retry_delay_ms: Option<u64>) {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let delay = retry_delay_ms.unwrap_or(DEFAULT_RATE_LIMIT_COOLDOWN_MS);
+        let until = now_ms + delay as i64;
+
+        let mut health = self.health.entry(account_id.to_string()).or_default();
+        let slot = health.cooldowns.entry(model.to_string()).or_insert(until);
+        *slot = (*slot).max(until);
+        health.last_error = Some(format!("429 rate limited on {} for {}ms", model, delay));
+        health.last_error_at = Some(now_ms);
+        tracing::warn!("账号 {} 对 {} 进入冷却 {}ms", account_id, model, delay);
+    }
+
+    /// 记录上游 401/403：连续失败达到阈值后打开熔断
+    pub fn mark_auth_failure(&self, account_id: &str, status: u16) {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+
+        let mut health = self.health.entry(account_id.to_string()).or_default();
+        health.consecutive_auth_failures += 1;
+        health.last_error = Some(format!("HTTP {}", status));
+        health.last_error_at = Some(now_ms);
+        if health.consecutive_auth_failures >= CIRCUIT_BREAKER_THRESHOLD {
+            health.circuit_open_until = Some(now_ms + CIRCUIT_OPEN_MS);
+            tracing::error!(
+                "账号 {} 连续 {} 次认证失败 ({}), 熔断 {}s",
+                account_id, health.consecutive_auth_failures, status, CIRCUIT_OPEN_MS / 1000
+            );
+        }
+    }
+
+    /// 记录上游成功响应：清零认证失败计数、关闭熔断，并更新延迟 EWMA
+    pub fn mark_success(&self, account_id: &str, latency_ms: u64) {
+        let mut health = self.health.entry(account_id.to_string()).or_default();
+        health.consecutive_auth_failures = 0;
+        health.circuit_open_until = None;
+        let sample = latency_ms as f64;
+        health.latency_ewma_ms = Some(match health.latency_ewma_ms {
+            Some(prev) => prev + LATENCY_EWMA_ALPHA * (sample - prev),
+            None => sample,
+        });
+    }
+
+    /// 手动清除账号的冷却与熔断状态
+    pub fn reset_health(&self, account_id: &str) {
+        self.health.remove(account_id);
+    }
+
+    /// 账号池健康状态快照: (account_id, email, health)
+    pub fn health_snapshot(&self) -> Vec<(String, String, AccountHealth)> {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let mut snapshot: Vec<(String, String, AccountHealth)> = self.tokens.iter()
+            .map(|entry| {
+                let mut health = self.health.get(entry.key())
+                    .map(|h| h.value().clone())
+                    .unwrap_or_default();
+                // 过期的冷却不再展示
+                health.cooldowns.retain(|_, until| *until > now_ms);
+                (entry.account_id.clone(), entry.email.clone(), health)
+            })
+            .collect();
+        snapshot.sort_by(|a, b| a.1.cmp(&b.1));
+        snapshot
     }
     
+    /// 按调度策略选择账号
+    /// 先排除目标模型配额已耗尽 (且未到重置时间)、处于冷却或熔断中的账号，再在剩余候选中按策略挑选
+    fn select_account(&self, strategy: SchedulingStrategy, target_model: &str, group: Option<&str>, now_ms: i64) -> Result<ProxyToken, String> {
+        let now = now_ms / 1000;
+        // 排空中的账号只服务已绑定的会话，不参与新选号；并发已满的账号暂不参与
+        let mut candidates: Vec<(i32, ProxyToken)> = self.tokens.iter()
+            .filter(|entry| !entry.draining && entry.slots.available_permits() > 0)
+            .filter(|entry| self.is_selectable(entry.value(), target_model, group, now_ms))
+            .map(|entry| (entry.remaining_quota(target_model, now), entry.value().clone()))
+            .collect();
+
+        if candidates.is_empty() {
+            if let Some(group) = group {
+                if !self.tokens.iter().any(|entry| in_group(entry.value(), Some(group))) {
+                    return Err(format!("No account in group '{}' for model {}", group, target_model));
+                }
+                return Err(format!("All accounts in group '{}' are exhausted, cooling down or circuit-broken for model {}", group, target_model));
+            }
+            return Err(format!("All accounts are exhausted, cooling down or circuit-broken for model {}", target_model));
+        }
+
+        // 按 account_id 排序，保证各策略在同等条件下的选择顺序稳定
+        candidates.sort_by(|a, b| a.1.account_id.cmp(&b.1.account_id));
+
+        let idx = match strategy {
+            SchedulingStrategy::RoundRobin => {
+                // 优先剩余配额最多的梯队，梯队内轮询，避免配额数据未刷新前所有请求都压在同一个账号上
+                candidates.sort_by_key(|(remaining, _)| std::cmp::Reverse(*remaining));
+                let best = candidates[0].0;
+                let tier_len = candidates.iter()
+                    .take_while(|(remaining, _)| *remaining >= best - QUOTA_TIER_TOLERANCE)
+                    .count();
+                self.current_index.fetch_add(1, Ordering::SeqCst) % tier_len
+            }
+            // 始终使用顺序上的第一个可用账号，直到它耗尽/冷却再轮到下一个
+            SchedulingStrategy::FillFirst => 0,
+            SchedulingStrategy::LeastRecentlyUsed => candidates.iter()
+                .enumerate()
+                .min_by_key(|(_, (_, t))| self.health.get(&t.account_id).and_then(|h| h.last_selected_at))
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+            SchedulingStrategy::Random => rand::thread_rng().gen_range(0..candidates.len()),
+            SchedulingStrategy::QuotaWeighted => {
+                let total: i64 = candidates.iter().map(|(remaining, _)| (*remaining).max(1) as i64).sum();
+                let mut pick = rand::thread_rng().gen_range(0..total);
+                candidates.iter()
+                    .position(|(remaining, _)| {
+                        pick -= (*remaining).max(1) as i64;
+                        pick < 0
+                    })
+                    .unwrap_or(0)
+            }
+            // 没有延迟样本的账号按 0 计算，保证新账号会被探测到
+            SchedulingStrategy::LowestLatency => candidates.iter()
+                .enumerate()
+                .min_by(|(_, (_, a)), (_, (_, b))| {
+                    let latency = |t: &ProxyToken| self.health.get(&t.account_id)
+                        .and_then(|h| h.latency_ewma_ms)
+                        .unwrap_or(0.0);
+                    latency(a).total_cmp(&latency(b))
+                })
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+        };
+
+        Ok(candidates.swap_remove(idx).1)
+    }
+
+    /// 刷新账号的 access token (single-flight)
+    /// 同一账号的并发刷新会合并为一个进行中的请求，其余调用方等待同一结果；
+    /// 刷新成功后同步更新 DashMap 并写回账号文件，重启后无需再次刷新
+    async fn refresh_token(&self, token: &ProxyToken, skew_secs: i64) -> Result<RefreshedToken, String> {
+        let flight = match self.refreshing.entry(token.account_id.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => {
+                tracing::debug!("账号 {} 的 token 正在刷新，等待进行中的刷新结果", token.email);
+                entry.get().clone()
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                // 调用方持有的可能是旧快照：若其他请求刚完成刷新，直接使用最新 token
+                if let Some(current) = self.tokens.get(&token.account_id) {
+                    if chrono::Utc::now().timestamp() < current.timestamp - skew_secs {
+                        return Ok(RefreshedToken {
+                            access_token: current.access_token.clone(),
+                            refresh_token: current.refresh_token.clone(),
+                            expires_in: current.expires_in,
+                            timestamp: current.timestamp,
+                        });
+                    }
+                }
+
+                let tokens = self.tokens.clone();
+                let health = self.health.clone();
+                let refreshing = self.refreshing.clone();
+                let account_id = token.account_id.clone();
+                let email = token.email.clone();
+                let refresh_token = token.refresh_token.clone();
+                let account_path = token.account_path.clone();
+
+                let flight = async move {
+                    tracing::info!("账号 {} 的 token 即将过期，正在刷新...", email);
+                    let result = crate::modules::oauth::refresh_access_token(&refresh_token).await
+                        .map(|token_response| {
+                            tracing::info!("Token 刷新成功！");
+                            let refreshed = RefreshedToken {
+                                access_token: token_response.access_token.clone(),
+                                refresh_token: token_response.refresh_token.clone().unwrap_or(refresh_token),
+                                expires_in: token_response.expires_in,
+                                timestamp: chrono::Utc::now().timestamp() + token_response.expires_in,
+                            };
+
+                            // 同步更新跨线程共享的 DashMap
+                            if let Some(mut entry) = tokens.get_mut(&account_id) {
+                                entry.access_token = refreshed.access_token.clone();
+                                entry.refresh_token = refreshed.refresh_token.clone();
+                                entry.expires_in = refreshed.expires_in;
+                                entry.timestamp = refreshed.timestamp;
+                            }
+
+                            // 持久化失败不影响本次请求，仅记录日志
+                            if let Err(e) = Self::save_refreshed_token(&account_path, &refreshed) {
+                                tracing::warn!("保存刷新后的 token 失败 ({}): {}", email, e);
+                            } else {
+                                tracing::info!("已保存刷新后的 token 到账号 {}", account_id);
+                            }
+                            refreshed
+                        });
+
+                    // 记录刷新结果；invalid_grant 说明 refresh_token 已被吊销，标记账号失效
+                    {
+                        let mut h = health.entry(account_id.clone()).or_default();
+                        h.last_refresh_at = Some(chrono::Utc::now().timestamp_millis());
+                        match &result {
+                            Ok(_) => {
+                                h.last_refresh_error = None;
+                                h.invalid = false;
+                            }
+                            Err(e) => {
+                                h.last_refresh_error = Some(e.clone());
+                                if e.contains("invalid_grant") {
+                                    tracing::error!("账号 {} 的 refresh_token 已失效 (invalid_grant)，已标记为 invalid", email);
+                                    h.invalid = true;
+                                }
+                            }
+                        }
+                    }
+
+                    refreshing.remove(&account_id);
+                    result
+                }.boxed().shared();
+
+                entry.insert(flight.clone());
+                flight
+            }
+        };
+
+        flight.await
+    }
+
+    /// 启动后台 token 刷新任务：定期提前刷新即将过期的账号，避免首个请求承担刷新延迟，
+    /// 并尽早发现失效的 refresh_token。返回的句柄由调用方在停止服务时 abort
+    pub fn start_background_refresher(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(BACKGROUND_REFRESH_INTERVAL_SECS));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                manager.refresh_expiring_tokens().await;
+            }
+        })
+    }
+
+    /// 刷新所有即将过期的账号 (有界并发，跳过已失效账号)
+    async fn refresh_expiring_tokens(&self) {
+        use futures::StreamExt;
+
+        let now = chrono::Utc::now().timestamp();
+        let expiring: Vec<ProxyToken> = self.tokens.iter()
+            .filter(|entry| now >= entry.timestamp - BACKGROUND_REFRESH_AHEAD_SECS)
+            .filter(|entry| !self.health.get(entry.key()).is_some_and(|h| h.invalid))
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        if expiring.is_empty() {
+            return;
+        }
+        tracing::info!("后台刷新: {} 个账号的 token 即将过期", expiring.len());
+
+        futures::stream::iter(expiring)
+            .for_each_concurrent(BACKGROUND_REFRESH_CONCURRENCY, |token| async move {
+                if let Err(e) = self.refresh_token(&token, BACKGROUND_REFRESH_AHEAD_SECS).await {
+                    tracing::warn!("后台刷新账号 {} 失败: {}", token.email, e);
+                }
+            })
+            .await;
+    }
+
+    /// 监听账号目录，文件新增/修改/删除后防抖并增量重新加载账号池。
+    /// 返回的句柄由调用方在停止服务时 abort (watcher 随任务一起释放)
+    pub fn start_account_watcher(self: &Arc<Self>) -> Result<tokio::task::JoinHandle<()>, String> {
+        use notify::{EventKind, RecursiveMode, Watcher};
+
+        let accounts_dir = self.data_dir.join("accounts");
+        std::fs::create_dir_all(&accounts_dir)
+            .map_err(|e| format!("创建账号目录失败: {}", e))?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            match res {
+                Ok(event) => {
+                    let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+                        && event.paths.iter().any(|p| p.extension().and_then(|s| s.to_str()) == Some("json"));
+                    if relevant {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(e) => tracing::warn!("账号目录监听出错: {}", e),
+            }
+        }).map_err(|e| format!("创建文件监听失败: {}", e))?;
+        watcher.watch(&accounts_dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("监听账号目录失败: {}", e))?;
+        tracing::info!("已开始监听账号目录: {:?}", accounts_dir);
+
+        let manager = self.clone();
+        Ok(tokio::spawn(async move {
+            let _watcher = watcher;
+            while rx.recv().await.is_some() {
+                // 防抖：一次保存往往触发多个事件，等目录安静后再重新加载
+                loop {
+                    match tokio::time::timeout(std::time::Duration::from_millis(ACCOUNT_WATCH_DEBOUNCE_MS), rx.recv()).await {
+                        Ok(Some(())) => continue,
+                        Ok(None) => return,
+                        Err(_) => break,
+                    }
+                }
+                if let Err(e) = manager.reload_accounts().await {
+                    tracing::warn!("账号目录变更后重新加载失败: {}", e);
+                }
+            }
+        }))
+    }
+
+    /// 启动配额复查调度：配额重置时间过后自动复查并让账号重新参与轮换，
+    /// forbidden 账号定期复查。返回的句柄由调用方在停止服务时 abort
+    pub fn start_quota_scheduler(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(QUOTA_RECHECK_INTERVAL_SECS));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                manager.recheck_due_quotas().await;
+            }
+        })
+    }
+
+    /// 复查所有到期账号的配额，完成后重新加载账号池
+    async fn recheck_due_quotas(&self) {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let due: Vec<(String, String)> = self.tokens.iter()
+            .filter(|entry| {
+                let last_check = self.health.get(entry.key()).and_then(|h| h.last_quota_check_at);
+                entry.quota_recheck_due(last_check, now_ms)
+            })
+            .map(|entry| (entry.account_id.clone(), entry.email.clone()))
+            .collect();
+
+        if due.is_empty() {
+            return;
+        }
+
+        for (account_id, email) in due {
+            self.health.entry(account_id.clone()).or_default().last_quota_check_at = Some(now_ms);
+            match Self::recheck_quota(&account_id).await {
+                Ok(quota) if quota.is_forbidden => {
+                    tracing::warn!("配额复查: 账号 {} 仍为 forbidden，继续隔离", email);
+                }
+                Ok(_) => tracing::info!("配额复查: 账号 {} 配额已更新", email),
+                Err(e) => tracing::warn!("配额复查: 账号 {} 查询失败: {}", email, e),
+            }
+        }
+
+        if let Err(e) = self.reload_accounts().await {
+            tracing::warn!("配额复查后重新加载账号失败: {}", e);
+        }
+    }
+
+    async fn recheck_quota(account_id: &str) -> Result<crate::models::QuotaData, String> {
+        let mut account = crate::modules::account::load_account(account_id)?;
+        let quota = crate::modules::account::fetch_quota_with_retry(&mut account)
+            .await
+            .map_err(|e| e.to_string())?;
+        crate::modules::account::update_account_quota(account_id, quota.clone())?;
+        Ok(quota)
+    }
+
     /// 保存 project_id 到账号文件
     async fn save_project_id(&self, account_id: &str, project_id: &str) -> Result<(), String> {
         let entry = self.tokens.get(account_id)
@@ -336,39 +1219,464 @@ impl TokenManager {
         
         content["token"]["project_id"] = serde_json::Value::String(project_id.to_string());
         
-        std::fs::write(path, serde_json::to_string_pretty(&content).unwrap())
-            .map_err(|e| format!("写入文件失败: {}", e))?;
+        write_account_file(path, &content)?;
         
         tracing::info!("已保存 project_id 到账号 {}", account_id);
         Ok(())
     }
     
     /// 保存刷新后的 token 到账号文件
-    #[allow(dead_code)]
-    async fn save_refreshed_token(&self, account_id: &str, token_response: &crate::modules::oauth::TokenResponse) -> Result<(), String> {
-        let entry = self.tokens.get(account_id)
-            .ok_or("账号不存在")?;
-        
-        let path = &entry.account_path;
-        
+    fn save_refreshed_token(path: &Path, refreshed: &RefreshedToken) -> Result<(), String> {
         let mut content: serde_json::Value = serde_json::from_str(
             &std::fs::read_to_string(path).map_err(|e| format!("读取文件失败: {}", e))?
         ).map_err(|e| format!("解析 JSON 失败: {}", e))?;
-        
-        let now = chrono::Utc::now().timestamp();
-        
-        content["token"]["access_token"] = serde_json::Value::String(token_response.access_token.clone());
-        content["token"]["expires_in"] = serde_json::Value::Number(token_response.expires_in.into());
-        content["token"]["expiry_timestamp"] = serde_json::Value::Number((now + token_response.expires_in).into());
-        
-        std::fs::write(path, serde_json::to_string_pretty(&content).unwrap())
-            .map_err(|e| format!("写入文件失败: {}", e))?;
-        
-        tracing::info!("已保存刷新后的 token 到账号 {}", account_id);
-        Ok(())
+
+        content["token"]["access_token"] = serde_json::Value::String(refreshed.access_token.clone());
+        content["token"]["refresh_token"] = serde_json::Value::String(refreshed.refresh_token.clone());
+        content["token"]["expires_in"] = serde_json::Value::Number(refreshed.expires_in.into());
+        content["token"]["expiry_timestamp"] = serde_json::Value::Number(refreshed.timestamp.into());
+
+        write_account_file(path, &content)
     }
     
     pub fn len(&self) -> usize {
         self.tokens.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_with_quota(percentage: i32, reset_time: &str) -> ProxyToken {
+        ProxyToken {
+            account_id: "acc".to_string(),
+            access_token: String::new(),
+            refresh_token: String::new(),
+            expires_in: 3600,
+            timestamp: 0,
+            email: "a@example.com".to_string(),
+            account_path: PathBuf::new(),
+            project_id: None,
+            quotas: vec![ModelQuota {
+                name: "claude-opus-4-5-thinking".to_string(),
+                percentage,
+                reset_time: reset_time.to_string(),
+            }],
+            tags: Vec::new(),
+            draining: false,
+            forbidden: false,
+            slots: new_slots(0),
+        }
+    }
+
+    #[test]
+    fn test_quota_exhausted_until_reset() {
+        let now = parse_reset_time("2025-01-01T00:00:00Z").unwrap();
+        let token = token_with_quota(0, "2025-01-01T01:00:00Z");
+        assert!(token.is_quota_exhausted("claude-opus-4-5-thinking", now));
+        assert_eq!(token.remaining_quota("claude-opus-4-5-thinking", now), 0);
+
+        // 过了重置时间后恢复可用
+        let later = now + 2 * 3600;
+        assert!(!token.is_quota_exhausted("claude-opus-4-5-thinking", later));
+        assert_eq!(token.remaining_quota("claude-opus-4-5-thinking", later), 100);
+    }
+
+    #[test]
+    fn test_unknown_model_is_available() {
+        let token = token_with_quota(0, "");
+        assert!(token.is_quota_exhausted("claude-opus-4-5-thinking", 0));
+        assert!(!token.is_quota_exhausted("gemini-2.5-flash", 0));
+        assert_eq!(token.remaining_quota("gemini-2.5-flash", 0), 100);
+    }
+
+    #[tokio::test]
+    async fn test_select_prefers_most_remaining_quota() {
+        let manager = TokenManager::new(PathBuf::new());
+        for (id, percentage) in [("a", 0), ("b", 80), ("c", 20)] {
+            let mut token = token_with_quota(percentage, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            manager.tokens.insert(id.to_string(), token);
+        }
+
+        for _ in 0..3 {
+            let selected = manager.select_account(SchedulingStrategy::RoundRobin, "claude-opus-4-5-thinking", None, 0).unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+    }
+
+    #[test]
+    fn test_rate_limited_account_is_skipped() {
+        let manager = TokenManager::new(PathBuf::new());
+        for id in ["a", "b"] {
+            let mut token = token_with_quota(50, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            manager.tokens.insert(id.to_string(), token);
+        }
+
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        manager.mark_rate_limited("a", "claude-opus-4-5-thinking", Some(60_000));
+        for _ in 0..4 {
+            let selected = manager.select_account(SchedulingStrategy::RoundRobin, "claude-opus-4-5-thinking", None, now_ms).unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+        // 冷却只针对对应模型
+        assert!(manager.is_selectable(&manager.tokens.get("a").unwrap(), "gemini-2.5-flash", None, now_ms));
+    }
+
+    #[test]
+    fn test_scheduling_strategies() {
+        let manager = TokenManager::new(PathBuf::new());
+        for (id, percentage) in [("a", 10), ("b", 90), ("c", 50)] {
+            let mut token = token_with_quota(percentage, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            manager.tokens.insert(id.to_string(), token);
+        }
+        let model = "claude-opus-4-5-thinking";
+        let pick = |strategy| manager.select_account(strategy, model, None, 0).unwrap().account_id;
+
+        assert_eq!(pick(SchedulingStrategy::FillFirst), "a");
+
+        manager.mark_success("a", 900);
+        manager.mark_success("b", 300);
+        manager.mark_success("c", 600);
+        assert_eq!(pick(SchedulingStrategy::LowestLatency), "b");
+
+        manager.health.get_mut("a").unwrap().last_selected_at = Some(3);
+        manager.health.get_mut("b").unwrap().last_selected_at = Some(1);
+        manager.health.get_mut("c").unwrap().last_selected_at = Some(2);
+        assert_eq!(pick(SchedulingStrategy::LeastRecentlyUsed), "b");
+
+        for _ in 0..10 {
+            assert!(["a", "b", "c"].contains(&pick(SchedulingStrategy::Random).as_str()));
+            assert!(["a", "b", "c"].contains(&pick(SchedulingStrategy::QuotaWeighted).as_str()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_watcher_reloads_pool() {
+        let data_dir = std::env::temp_dir().join(format!("token_manager_watch_{}", uuid::Uuid::new_v4()));
+        let manager = Arc::new(TokenManager::new(data_dir.clone()));
+        let handle = manager.start_account_watcher().unwrap();
+
+        std::fs::write(data_dir.join("accounts").join("a.json"), serde_json::json!({
+            "id": "a",
+            "email": "a@example.com",
+            "token": {
+                "access_token": "at",
+                "refresh_token": "rt",
+                "expires_in": 3599,
+                "expiry_timestamp": 4_000_000_000i64
+            }
+        }).to_string()).unwrap();
+
+        let mut loaded = false;
+        for _ in 0..50 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            if manager.len() == 1 {
+                loaded = true;
+                break;
+            }
+        }
+        handle.abort();
+        let _ = std::fs::remove_dir_all(&data_dir);
+        assert!(loaded, "watcher did not reload the new account");
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_account_when_file_fails_to_parse() {
+        let data_dir = std::env::temp_dir().join(format!("token_manager_reload_{}", uuid::Uuid::new_v4()));
+        let accounts_dir = data_dir.join("accounts");
+        std::fs::create_dir_all(&accounts_dir).unwrap();
+        let path = accounts_dir.join("a.json");
+        std::fs::write(&path, serde_json::json!({
+            "id": "a",
+            "email": "a@example.com",
+            "token": {
+                "access_token": "at",
+                "refresh_token": "rt",
+                "expires_in": 3599,
+                "expiry_timestamp": 4_000_000_000i64
+            }
+        }).to_string()).unwrap();
+
+        let manager = TokenManager::new(data_dir.clone());
+        assert_eq!(manager.reload_accounts().await.unwrap(), 1);
+        manager.health.entry("a".to_string()).or_default().invalid = true;
+
+        // 写了一半的文件：账号与健康状态保持不变
+        std::fs::write(&path, "{\"id\": \"a\", \"ema").unwrap();
+        assert_eq!(manager.reload_accounts().await.unwrap(), 1);
+        assert!(manager.health.get("a").unwrap().invalid);
+
+        // 文件确实被删除后才移除账号
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(manager.reload_accounts().await.unwrap(), 0);
+        assert!(manager.health.get("a").is_none());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn test_save_refreshed_token_updates_account_file() {
+        let path = std::env::temp_dir().join(format!("token_manager_test_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, serde_json::json!({
+            "id": "a",
+            "email": "a@example.com",
+            "token": {
+                "access_token": "old",
+                "refresh_token": "refresh",
+                "expires_in": 3599,
+                "expiry_timestamp": 1,
+                "project_id": "p"
+            }
+        }).to_string()).unwrap();
+
+        let refreshed = RefreshedToken {
+            access_token: "new".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in: 3599,
+            timestamp: 4_000_000_000,
+        };
+        TokenManager::save_refreshed_token(&path, &refreshed).unwrap();
+
+        let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(content["token"]["access_token"], "new");
+        assert_eq!(content["token"]["expiry_timestamp"], 4_000_000_000i64);
+        assert_eq!(content["token"]["project_id"], "p");
+        assert_eq!(content["email"], "a@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_skipped_when_token_already_fresh() {
+        let manager = TokenManager::new(PathBuf::new());
+        let mut fresh = token_with_quota(100, "2999-01-01T00:00:00Z");
+        fresh.account_id = "a".to_string();
+        fresh.access_token = "fresh".to_string();
+        fresh.timestamp = chrono::Utc::now().timestamp() + 3600;
+        manager.tokens.insert("a".to_string(), fresh.clone());
+
+        // 调用方持有过期的旧快照，但共享状态中已是刷新后的 token，不应再次请求 OAuth
+        let mut stale = fresh;
+        stale.access_token = "stale".to_string();
+        stale.timestamp = 0;
+        let refreshed = manager.refresh_token(&stale, REFRESH_SKEW_SECS).await.unwrap();
+        assert_eq!(refreshed.access_token, "fresh");
+        assert!(manager.refreshing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_account_is_not_selectable() {
+        let manager = TokenManager::new(PathBuf::new());
+        for id in ["a", "b"] {
+            let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            token.timestamp = chrono::Utc::now().timestamp() + 3600;
+            manager.tokens.insert(id.to_string(), token);
+        }
+        manager.health.entry("a".to_string()).or_default().invalid = true;
+
+        for _ in 0..4 {
+            let selected = manager.select_account(SchedulingStrategy::RoundRobin, "claude-opus-4-5-thinking", None, 0).unwrap();
+            assert_eq!(selected.account_id, "b");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_affinity_keeps_session_on_one_account() {
+        let manager = TokenManager::new(PathBuf::new());
+        for id in ["a", "b", "c"] {
+            let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            token.project_id = Some("p".to_string());
+            token.timestamp = chrono::Utc::now().timestamp() + 3600;
+            manager.tokens.insert(id.to_string(), token);
+        }
+        let model = "claude-opus-4-5-thinking";
+
+        let first = manager.get_token("claude", model, false, Some("s1")).await.unwrap().account_id;
+        for _ in 0..5 {
+            let again = manager.get_token("claude", model, false, Some("s1")).await.unwrap().account_id;
+            assert_eq!(again, first);
+        }
+
+        // 其他会话不受 s1 绑定影响，按轮询分散
+        let others: std::collections::HashSet<String> = futures::future::join_all(
+            ["s2", "s3", "s4"].iter().map(|sid| manager.get_token("claude", model, false, Some(sid)))
+        ).await.into_iter().map(|t| t.unwrap().account_id).collect();
+        assert!(others.len() > 1);
+
+        // 绑定账号冷却后改绑其他账号
+        manager.mark_rate_limited(&first, model, Some(60_000));
+        let rebound = manager.get_token("claude", model, false, Some("s1")).await.unwrap().account_id;
+        assert_ne!(rebound, first);
+        assert_eq!(manager.get_token("claude", model, false, Some("s1")).await.unwrap().account_id, rebound);
+    }
+
+    #[tokio::test]
+    async fn test_routing_rule_restricts_pool_to_group() {
+        let manager = TokenManager::new(PathBuf::new());
+        for (id, tags) in [("a", vec!["claude"]), ("b", vec![]), ("c", vec!["claude", "gemini"])] {
+            let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            token.project_id = Some("p".to_string());
+            token.timestamp = chrono::Utc::now().timestamp() + 3600;
+            token.tags = tags.into_iter().map(String::from).collect();
+            manager.tokens.insert(id.to_string(), token);
+        }
+        manager.update_routing_rules(vec![
+            RoutingRule { model: Some("claude-*".to_string()), request_type: None, group: "claude".to_string() },
+            RoutingRule { model: None, request_type: Some("image_gen".to_string()), group: "images".to_string() },
+        ]).await;
+
+        for _ in 0..6 {
+            let id = manager.get_token("agent", "claude-opus-4-5-thinking", false, None).await.unwrap().account_id;
+            assert!(id == "a" || id == "c");
+        }
+        let err = manager.get_token("image_gen", "gemini-3-pro-image", false, None).await.unwrap_err();
+        assert!(err.contains("images"));
+        // 未命中规则的请求可使用所有账号
+        assert!(manager.get_token("agent", "gemini-2.5-flash", false, None).await.is_ok());
+        // 客户端 Key 固定分组优先于路由规则
+        for _ in 0..4 {
+            let id = manager.get_token_in_group("agent", "claude-opus-4-5-thinking", false, None, Some("gemini")).await.unwrap().account_id;
+            assert_eq!(id, "c");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_draining_account_keeps_sessions_but_takes_no_new_ones() {
+        let manager = TokenManager::new(PathBuf::new());
+        for id in ["a", "b"] {
+            let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+            token.account_id = id.to_string();
+            token.project_id = Some("p".to_string());
+            token.timestamp = chrono::Utc::now().timestamp() + 3600;
+            manager.tokens.insert(id.to_string(), token);
+        }
+        let model = "claude-opus-4-5-thinking";
+
+        let bound = manager.get_token("agent", model, false, Some("s1")).await.unwrap().account_id;
+        manager.tokens.get_mut(&bound).unwrap().draining = true;
+
+        assert_eq!(manager.get_token("agent", model, false, Some("s1")).await.unwrap().account_id, bound);
+        for sid in ["s2", "s3", "s4"] {
+            assert_ne!(manager.get_token("agent", model, false, Some(sid)).await.unwrap().account_id, bound);
+        }
+    }
+
+    #[test]
+    fn test_forbidden_account_is_quarantined() {
+        let manager = TokenManager::new(PathBuf::new());
+        let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+        token.forbidden = true;
+        manager.tokens.insert("acc".to_string(), token);
+        assert!(manager.select_account(SchedulingStrategy::RoundRobin, "claude-opus-4-5-thinking", None, 0).is_err());
+    }
+
+    #[test]
+    fn test_quota_recheck_due_after_reset() {
+        let reset_ms = parse_reset_time("2025-01-01T00:00:00Z").unwrap() * 1000;
+        let token = token_with_quota(0, "2025-01-01T00:00:00Z");
+        let delay_ms = QUOTA_RECHECK_DELAY_SECS * 1000;
+
+        assert!(!token.quota_recheck_due(None, reset_ms));
+        assert!(token.quota_recheck_due(None, reset_ms + delay_ms));
+        // 刚复查过的不再重复复查
+        assert!(!token.quota_recheck_due(Some(reset_ms + delay_ms), reset_ms + delay_ms + 1000));
+
+        let healthy = token_with_quota(50, "2025-01-01T00:00:00Z");
+        assert!(!healthy.quota_recheck_due(None, reset_ms + delay_ms));
+
+        let mut forbidden = token_with_quota(50, "2999-01-01T00:00:00Z");
+        forbidden.forbidden = true;
+        assert!(forbidden.quota_recheck_due(None, reset_ms));
+        assert!(!forbidden.quota_recheck_due(Some(reset_ms), reset_ms + 1000));
+        assert!(forbidden.quota_recheck_due(Some(reset_ms), reset_ms + FORBIDDEN_RECHECK_MS));
+    }
+
+    #[tokio::test]
+    async fn test_saturated_pool_queues_until_slot_released() {
+        let manager = Arc::new(TokenManager::new(PathBuf::new()));
+        manager.update_scheduling(SchedulingConfig {
+            max_concurrency_per_account: 1,
+            queue_timeout_secs: 1,
+            ..SchedulingConfig::default()
+        }).await;
+        let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+        token.project_id = Some("p".to_string());
+        token.timestamp = chrono::Utc::now().timestamp() + 3600;
+        token.slots = new_slots(1);
+        manager.tokens.insert("acc".to_string(), token);
+        let model = "claude-opus-4-5-thinking";
+
+        let first = manager.get_token("agent", model, false, None).await.unwrap();
+
+        // 槽位被占用：排队直到超时
+        let err = manager.get_token("agent", model, false, None).await.unwrap_err();
+        assert!(err.contains("max concurrency"));
+
+        // 排队期间释放槽位：排队的请求拿到账号
+        let waiter = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.get_token("agent", model, false, None).await })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        drop(first);
+        assert_eq!(waiter.await.unwrap().unwrap().account_id, "acc");
+    }
+
+    #[tokio::test]
+    async fn test_lowering_concurrency_keeps_in_flight_requests_counted() {
+        let slots = new_slots(3);
+        let first = slots.clone().try_acquire_owned().unwrap();
+        let second = slots.clone().try_acquire_owned().unwrap();
+
+        // 3 -> 1：空闲槽位立即回收，进行中的 2 个请求仍占满上限
+        resize_slots(&slots, 3, 1);
+        assert_eq!(slots.available_permits(), 0);
+        drop(first);
+        tokio::task::yield_now().await;
+        assert_eq!(slots.available_permits(), 0);
+        drop(second);
+        assert_eq!(slots.available_permits(), 1);
+
+        resize_slots(&slots, 1, 4);
+        assert_eq!(slots.available_permits(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_peek_token_never_takes_a_slot() {
+        let manager = TokenManager::new(PathBuf::new());
+        let mut token = token_with_quota(100, "2999-01-01T00:00:00Z");
+        token.project_id = Some("p".to_string());
+        token.timestamp = chrono::Utc::now().timestamp() + 3600;
+        token.slots = new_slots(1);
+        manager.tokens.insert("acc".to_string(), token);
+        let model = "claude-opus-4-5-thinking";
+
+        assert_eq!(manager.peek_token("agent", model, None).await.map(|(_, p)| p).as_deref(), Some("p"));
+        assert!(manager.health.get("acc").is_none());
+
+        // 槽位被占满时立即返回 None，不排队
+        let _held = manager.get_token("agent", model, false, None).await.unwrap();
+        assert!(manager.peek_token("agent", model, None).await.is_none());
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_after_repeated_auth_failures() {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let manager = TokenManager::new(PathBuf::new());
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD - 1 {
+            manager.mark_auth_failure("a", 403);
+        }
+        assert!(!manager.health.get("a").unwrap().is_circuit_open(now_ms));
+
+        manager.mark_auth_failure("a", 403);
+        assert!(manager.health.get("a").unwrap().is_circuit_open(now_ms));
+
+        manager.mark_success("a", 100);
+        assert!(!manager.health.get("a").unwrap().is_circuit_open(now_ms));
+    }
+}
@@ -7,6 +7,8 @@ use tokio::time::Duration;
 
 // 生产环境端点
 const V1_INTERNAL_BASE_URL: &str = "https://cloudcode-pa.googleapis.com/v1internal";
+// countTokens 超时 (超时后回退到本地估算，不宜让客户端久等)
+const COUNT_TOKENS_TIMEOUT_SECS: u64 = 15;
 
 pub struct UpstreamClient {
     http_client: Client,
@@ -120,6 +122,29 @@ impl UpstreamClient {
         let json: Value = response.json().await.map_err(|e| format!("Parse json failed: {}", e))?;
         Ok(json)
     }
+
+    /// 调用上游 countTokens 统计输入 token
+    ///
+    /// `request` 为未包装的 Gemini 请求体，`model` 为映射后的上游模型名
+    pub async fn count_tokens(&self, access_token: &str, model: &str, request: &Value) -> Result<u64, String> {
+        let body = crate::proxy::common::token_count::build_count_tokens_body(request, model);
+        let response = tokio::time::timeout(
+            Duration::from_secs(COUNT_TOKENS_TIMEOUT_SECS),
+            self.call_v1_internal("countTokens", access_token, body, None),
+        )
+        .await
+        .map_err(|_| "countTokens request timed out".to_string())??;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!("Upstream error: {} {}", status, text));
+        }
+
+        let json: Value = response.json().await.map_err(|e| format!("Parse json failed: {}", e))?;
+        crate::proxy::common::token_count::parse_total_tokens(&json)
+            .ok_or_else(|| format!("Missing totalTokens in response: {}", json))
+    }
 }
 
 #[cfg(test)]
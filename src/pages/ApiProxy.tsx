@@ -29,6 +29,7 @@ interface ProxyStatus {
     port: number;
     base_url: string;
     active_accounts: number;
+    tls_fingerprint?: string | null;
 }
 
 
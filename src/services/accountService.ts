@@ -34,6 +34,22 @@ export async function switchAccount(accountId: string): Promise<void> {
     return await invoke('switch_account', { accountId });
 }
 
+export async function updateAccountTags(accountId: string, tags: string[]): Promise<Account> {
+    return await invoke('update_account_tags', { accountId, tags });
+}
+
+export async function disableAccount(accountId: string): Promise<Account> {
+    return await invoke('disable_account', { accountId });
+}
+
+export async function enableAccount(accountId: string): Promise<Account> {
+    return await invoke('enable_account', { accountId });
+}
+
+export async function drainAccount(accountId: string): Promise<Account> {
+    return await invoke('drain_account', { accountId });
+}
+
 export async function fetchAccountQuota(accountId: string): Promise<QuotaData> {
     return await invoke('fetch_account_quota', { accountId });
 }
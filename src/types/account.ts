@@ -6,8 +6,12 @@ export interface Account {
     quota?: QuotaData;
     created_at: number;
     last_used: number;
+    tags?: string[];
+    status?: AccountStatus;
 }
 
+export type AccountStatus = 'active' | 'disabled' | 'draining';
+
 export interface TokenData {
     access_token: string;
     refresh_token: string;
@@ -8,12 +8,120 @@ export interface ProxyConfig {
     allow_lan_access?: boolean;
     port: number;
     api_key: string;
+    disable_auth?: boolean;
+    client_keys?: ClientApiKey[];
+    rate_limit?: RateLimitConfig;
+    ip_access?: IpAccessConfig;
+    tls?: TlsConfig;
+    cors?: CorsConfig;
+    response_store?: ResponseStoreConfig;
+    admin_password_hash?: string | null; // Web 管理后台密码 (Argon2 哈希)
     auto_start: boolean;
     anthropic_mapping?: Record<string, string>;
     openai_mapping?: Record<string, string>;
     custom_mapping?: Record<string, string>;
     request_timeout: number;
     upstream_proxy: UpstreamProxyConfig;
+    scheduling?: SchedulingConfig;
+    routing_rules?: RoutingRule[];
+}
+
+export type ApiProtocol = 'openai' | 'claude' | 'gemini';
+
+export interface ClientApiKey {
+    id: string;
+    name: string;
+    key: string;
+    created_at: number;
+    expires_at?: number | null;
+    allowed_models: string[];
+    allowed_protocols: ApiProtocol[];
+    group?: string | null;
+    disabled: boolean;
+    budget?: KeyBudget;
+    rate_limit?: RateLimit | null;
+}
+
+export interface RateLimit {
+    requests_per_minute: number;
+    max_concurrent_streams: number;
+}
+
+export interface RateLimitConfig {
+    per_key: RateLimit;
+    per_ip: RateLimit;
+}
+
+// allowed_origins 中 "*" 表示任意来源，为空表示仅同源
+export interface CorsPolicy {
+    allowed_origins: string[];
+    allow_credentials: boolean;
+    exposed_headers: string[];
+}
+
+export interface CorsConfig {
+    api: CorsPolicy;
+    admin: CorsPolicy;
+}
+
+// Responses API 响应存储；persist 修改后需重启服务
+export interface ResponseStoreConfig {
+    max_entries: number;
+    max_bytes?: number;
+    persist: boolean;
+    retention_days: number;
+}
+
+export interface TlsConfig {
+    enabled: boolean;
+    mode: 'files' | 'self_signed';
+    cert_path?: string | null;
+    key_path?: string | null;
+    self_signed_hosts: string[];
+}
+
+// CIDR 或单个 IP；deny 优先，allow 为空表示不限制
+export interface IpRules {
+    allow: string[];
+    deny: string[];
+}
+
+export interface IpAccessConfig {
+    api: IpRules;
+    admin: IpRules;
+    trusted_proxies: string[];
+}
+
+export interface BudgetLimits {
+    requests?: number | null;
+    input_tokens?: number | null;
+    output_tokens?: number | null;
+}
+
+export interface KeyBudget {
+    daily?: BudgetLimits;
+    monthly?: BudgetLimits;
+}
+
+export interface RoutingRule {
+    model?: string;
+    request_type?: 'agent' | 'web_search' | 'image_gen';
+    group: string;
+}
+
+export type SchedulingStrategy =
+    | 'round_robin'
+    | 'fill_first'
+    | 'least_recently_used'
+    | 'random'
+    | 'quota_weighted'
+    | 'lowest_latency';
+
+export interface SchedulingConfig {
+    strategy: SchedulingStrategy;
+    sticky_window_secs: number;
+    max_concurrency_per_account?: number;
+    queue_timeout_secs?: number;
 }
 
 export interface AppConfig {